@@ -33,6 +33,162 @@ pub enum Commands {
         /// A short summary of the changes
         #[arg(short, long)]
         message: Option<String>,
+
+        /// Metadata to attach to the checkpoint, as key=value. Can be repeated.
+        #[arg(long = "meta", value_parser = parse_key_value)]
+        metadata: Vec<(String, String)>,
+
+        /// The branch to start when committing on top of an older checkpoint.
+        /// Named after the current branch and checkpoint if not given.
+        #[arg(short, long)]
+        branch: Option<String>,
+    },
+
+    /// Keep running and create a checkpoint whenever the file is saved
+    Watch {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// Path to the blender file to watch
+        #[arg(short, long)]
+        file_path: String,
+
+        /// How long the file has to stay the same after a save before it is committed
+        #[arg(long, default_value_t = 2000)]
+        debounce_ms: u64,
+    },
+
+    /// Change the message or metadata of the latest checkpoint on a branch
+    Amend {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// The branch to amend the latest checkpoint of, the current one if not given
+        #[arg(short, long)]
+        branch: Option<String>,
+
+        /// The new message
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Metadata to set on the checkpoint, as key=value. Can be repeated.
+        #[arg(long = "meta", value_parser = parse_key_value)]
+        metadata: Vec<(String, String)>,
+    },
+
+    /// Remove the latest checkpoint on a branch, without touching the file
+    Undo {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// The branch to undo the latest checkpoint of, the current one if not given
+        #[arg(short, long)]
+        branch: Option<String>,
+    },
+
+    /// Collapse consecutive checkpoints of a branch into one
+    Squash {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// The branch the checkpoints are on, the current one if not given
+        #[arg(short, long)]
+        branch: Option<String>,
+
+        /// The oldest checkpoint to squash
+        #[arg(long)]
+        from: String,
+
+        /// The newest checkpoint to squash, whose contents are kept
+        #[arg(long)]
+        to: String,
+
+        /// The message of the squashed checkpoint, all of their messages if not given
+        #[arg(short, long)]
+        message: Option<String>,
+    },
+
+    /// Set how long the checkpoints of a branch are kept when it is pruned
+    SetRetention {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// The branch to set the policy for, the current one if not given
+        #[arg(short, long)]
+        branch: Option<String>,
+
+        /// Keep every checkpoint from this many hours
+        #[arg(long, default_value_t = 24)]
+        keep_all_hours: u64,
+
+        /// Keep the latest checkpoint of every hour from this many days, and the
+        /// latest of every day before that
+        #[arg(long, default_value_t = 7)]
+        keep_hourly_days: u64,
+    },
+
+    /// Remove the checkpoints of a branch that its retention policy does not keep
+    Prune {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// The branch to prune, the current one if not given
+        #[arg(short, long)]
+        branch: Option<String>,
+    },
+
+    /// Show what changed in the file since the latest checkpoint, without committing
+    Status {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// Path to the blender file to compare with the latest checkpoint
+        #[arg(short, long)]
+        file_path: String,
+    },
+
+    /// Set a metadata entry on a checkpoint
+    SetMetadata {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// The hash of the checkpoint
+        #[arg(long)]
+        hash: String,
+
+        /// The entry to set, as key=value
+        #[arg(long = "meta", value_parser = parse_key_value)]
+        metadata: (String, String),
+    },
+
+    /// Print the metadata of a checkpoint
+    ShowMetadata {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// The hash of the checkpoint
+        #[arg(long)]
+        hash: String,
+    },
+
+    /// List the checkpoints that have a metadata entry
+    FindByMetadata {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// The entry to look for, as key=value
+        #[arg(long = "meta", value_parser = parse_key_value)]
+        metadata: (String, String),
     },
 
     /// Write the contents of a checkpoint to a file
@@ -48,9 +204,32 @@ pub enum Commands {
         /// The hash of the comit to check out
         #[arg(long)]
         hash: String,
+
+        /// Check that the written file matches the checkpoint, and put the previous file back if not
+        #[arg(long)]
+        verify: bool,
     },
 
-    /// Create a new branch
+    /// Write the contents of a checkpoint to any file, without changing the current branch or checkpoint
+    Materialize {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// Path of the file to write to
+        #[arg(short, long)]
+        file_path: String,
+
+        /// The hash of the commit to write
+        #[arg(long)]
+        hash: String,
+
+        /// Check that the written file matches the checkpoint, and put the previous file back if not
+        #[arg(long)]
+        verify: bool,
+    },
+
+    /// Create a new branch and make it the current one
     NewBranch {
         /// Path to the blend file DB
         #[arg(short, long)]
@@ -59,6 +238,33 @@ pub enum Commands {
         /// The name of the new branch
         #[arg(short, long)]
         branch_name: String,
+
+        /// Branch, tag or checkpoint hash to start from, the current branch by default
+        #[arg(long)]
+        from: Option<String>,
+
+        /// What the branch is for
+        #[arg(long)]
+        description: Option<String>,
+
+        /// Path of the file to restore the starting point to, needed when it is not the current checkpoint
+        #[arg(short, long)]
+        file_path: Option<String>,
+
+        /// Check that the restored file matches the starting point, and put the previous file back if not
+        #[arg(long)]
+        verify: bool,
+    },
+
+    /// Print where a branch started from
+    BranchInfo {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// The name of the branch
+        #[arg(short, long)]
+        branch_name: String,
     },
 
     /// Delete a brach
@@ -72,6 +278,71 @@ pub enum Commands {
         branch_name: String,
     },
 
+    /// Rename a branch
+    RenameBranch {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// The current name of the branch
+        #[arg(short, long)]
+        branch_name: String,
+
+        /// The new name of the branch
+        #[arg(short, long)]
+        new_name: String,
+    },
+
+    /// Merge a branch into the current branch
+    Merge {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// Path of the file to write the merged file to
+        #[arg(short, long)]
+        file_path: String,
+
+        /// The branch to merge
+        #[arg(short, long)]
+        branch_name: String,
+
+        /// A conflicting datablock to keep from the current branch, like OBCube. Can be repeated.
+        #[arg(long)]
+        ours: Vec<String>,
+
+        /// A conflicting datablock to take from the merged branch. Can be repeated.
+        #[arg(long)]
+        theirs: Vec<String>,
+    },
+
+    /// Apply the changes of a checkpoint on top of the current branch and commit them
+    CherryPick {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// Path of the file to write the result to
+        #[arg(short, long)]
+        file_path: String,
+
+        /// The checkpoint to pick
+        #[arg(long)]
+        hash: String,
+
+        /// Only apply the changes to this datablock, like MAMaterial. Can be repeated.
+        #[arg(long)]
+        datablock: Vec<String>,
+
+        /// A conflicting datablock to keep from the current branch, like OBCube. Can be repeated.
+        #[arg(long)]
+        ours: Vec<String>,
+
+        /// A conflicting datablock to take from the picked checkpoint. Can be repeated.
+        #[arg(long)]
+        theirs: Vec<String>,
+    },
+
     /// Lists all existing branches
     ListBranches {
         /// Path to the blend file DB
@@ -79,6 +350,59 @@ pub enum Commands {
         db_path: String,
     },
 
+    /// Give a name to a checkpoint
+    Tag {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// The name of the tag
+        #[arg(short, long)]
+        name: String,
+
+        /// The hash of the checkpoint to tag
+        #[arg(long)]
+        hash: String,
+    },
+
+    /// Lists all tags
+    ListTags {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+    },
+
+    /// Lists the changes of the current checkpoint, the current branch and the
+    /// branch tips, newest first
+    Reflog {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// Show at most this many entries
+        #[arg(short, long)]
+        limit: Option<usize>,
+    },
+
+    /// Undo a reflog entry, moving what it changed back to where it was
+    JumpBack {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// Path of the file to write to
+        #[arg(short, long)]
+        file_path: String,
+
+        /// The id of the reflog entry
+        #[arg(long)]
+        id: i64,
+
+        /// Check that the written file matches the checkpoint, and put the previous file back if not
+        #[arg(long)]
+        verify: bool,
+    },
+
     /// Switch to the latest version on a branch
     Switch {
         /// Path to the blend file DB
@@ -92,6 +416,17 @@ pub enum Commands {
         /// Path of the file to write to
         #[arg(short, long)]
         file_path: String,
+
+        /// Check that the written file matches the tip of the branch, and put the previous file back if not
+        #[arg(long)]
+        verify: bool,
+    },
+
+    /// Roll back the operations that were interrupted halfway, and list them
+    Recover {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
     },
 
     // Gets the currently active branch
@@ -112,6 +447,59 @@ pub enum Commands {
         branch: String,
     },
 
+    /// Search the checkpoints of all branches, newest first
+    History {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// Only checkpoints by this author
+        #[arg(long)]
+        author: Option<String>,
+
+        /// Only checkpoints on this branch
+        #[arg(short, long)]
+        branch: Option<String>,
+
+        /// Only checkpoints with a message containing this text
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Only checkpoints made at or after this unix timestamp
+        #[arg(long)]
+        since: Option<u64>,
+
+        /// Only checkpoints made at or before this unix timestamp
+        #[arg(long)]
+        until: Option<u64>,
+
+        /// Print at most this many checkpoints
+        #[arg(short, long)]
+        limit: Option<usize>,
+
+        /// Skip this many checkpoints
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+
+        /// List the oldest checkpoints first
+        #[arg(long)]
+        oldest_first: bool,
+    },
+
+    /// Search the messages of the checkpoints on all branches
+    Search {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// The words to look for
+        query: String,
+
+        /// Print at most this many checkpoints
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+    },
+
     /// Initialize the DB
     Init {
         /// Path to the blend file DB
@@ -121,6 +509,63 @@ pub enum Commands {
         /// Path to the blender file to create the DB from
         #[arg(short, long)]
         file_path: String,
+
+        /// Hash function used for blocks and commits: blake3 (default), sha256 or md5
+        #[arg(long)]
+        hash_algorithm: Option<String>,
+    },
+
+    /// Write the checkpoints of a branch into a folder as numbered blender files, with a manifest
+    ExportBranch {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// The branch to export, the current one if not given
+        #[arg(short, long)]
+        branch: Option<String>,
+
+        /// The folder to write the files to
+        #[arg(long)]
+        dir: String,
+
+        /// The oldest checkpoint to export, the first one of the branch if not given
+        #[arg(long)]
+        from: Option<String>,
+
+        /// The newest checkpoint to export, the tip of the branch if not given
+        #[arg(long)]
+        to: Option<String>,
+    },
+
+    /// Build a timeline out of Blender backups (scene.blend1, scene.blend2...) or a folder of versions
+    ImportVersions {
+        /// Path to the blend file DB, initialized with the oldest version if it does not exist
+        #[arg(short, long)]
+        db_path: String,
+
+        /// A blender file with backups next to it, or a folder of blender files
+        #[arg(short, long)]
+        path: String,
+
+        /// Order the versions by the time they were saved instead of the numbers in their names
+        #[arg(long)]
+        by_mtime: bool,
+
+        /// Hash function used for blocks and commits if the DB is created: blake3 (default), sha256 or md5
+        #[arg(long)]
+        hash_algorithm: Option<String>,
+    },
+
+    /// Rehash all blocks and commits with a different hash function
+    MigrateHash {
+        /// Path to the blend file DB
+        #[arg(short, long)]
+        db_path: String,
+
+        /// Hash function to migrate to: blake3, sha256 or md5
+        #[arg(long)]
+        hash_algorithm: String,
     },
 
     InitFromImport {
@@ -179,6 +624,12 @@ pub enum Commands {
     },
 }
 
+fn parse_key_value(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or(format!("Expected key=value, got {}", arg))
+}
+
 pub fn parse_args() -> Cli {
     Cli::parse()
 }
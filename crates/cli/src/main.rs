@@ -5,26 +5,49 @@ use cli::{parse_args, Commands};
 use log::error;
 use parserprinter::{
     api::{
+        amend_command::{amend_latest_commit, undo_latest_commit},
+        cherry_pick_command::{cherry_pick, CherryPickOutcome},
         commit_command::create_new_commit,
         delete_branch::delete_branch,
+        export_branch_command::export_branch,
         export_descendants_of_commit::export_descendants_of_commit,
         get_current_branch::get_current_branch,
+        history_command::query_history,
         import_exchange,
+        import_versions_command::{import_versions, VersionOrder},
         init_command::init_db,
         init_from_import_command,
-        list_branches_command::list_braches,
+        list_branches_command::{get_branch_info, list_braches},
         log_checkpoints_command::list_checkpoints,
+        merge_command::{merge_branch, MergeOutcome},
+        metadata_command::{find_commits_by_metadata, read_commit_metadata, set_commit_metadata},
+        migrate_hash_command::migrate_hash_algorithm,
         new_branch_command::create_new_branch,
         prepare_sync::prepare_sync,
-        restore_command::restore_checkpoint,
+        prune_command::{prune_branch, set_retention_policy},
+        recover_command::roll_back_unfinished_operations,
+        ref_log_command::{jump_back, list_ref_log},
+        rename_branch_command::rename_branch,
+        restore_command::{materialize_checkpoint, restore_checkpoint},
+        search_command::search_commits,
+        squash_command::squash_checkpoints,
+        status_command::working_file_status,
         switch_command::switch_branches,
+        tag_command::{create_tag, list_tags},
         test_command::run_command_test,
         utils::{read_exchange_from_file, write_exchange_to_file},
+        watch_command::{watch_file, WatchEvent, WatchOptions},
+    },
+    blend::merge::MergeSide,
+    db::{
+        db_ops::DBError,
+        structs::{HashAlgorithm, HistoryOrder, HistoryQuery, RefLogTarget, RetentionPolicy},
     },
-    db::db_ops::DBError,
     exchange::structs::{decode_exchange, encode_sync},
 };
 
+use std::{collections::HashMap, sync::atomic::AtomicBool, time::Duration};
+
 fn print_error_discard_rest<T>(res: Result<T, DBError>) {
     match res {
         Ok(_) => {}
@@ -32,6 +55,12 @@ fn print_error_discard_rest<T>(res: Result<T, DBError>) {
     }
 }
 
+fn print_skipped_tags(skipped_tags: Vec<String>) {
+    skipped_tags
+        .into_iter()
+        .for_each(|tag| println!("Tag {} already exists, skipping", tag));
+}
+
 fn print_all_branches(db_path: &str) {
     let result = list_braches(db_path);
     match result {
@@ -52,20 +81,284 @@ fn print_checkpoints(db_path: &str, branch_name: &str) {
     }
 }
 
-fn run_new_branch_command(db_path: &str, new_branch_name: &str) {
-    print_error_discard_rest(create_new_branch(db_path, new_branch_name));
+fn print_all_tags(db_path: &str) {
+    let result = list_tags(db_path);
+    match result {
+        Ok(tags) => tags
+            .into_iter()
+            .for_each(|tag| println!("{} {}", tag.name, tag.hash)),
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn run_tag_command(db_path: &str, name: &str, hash: &str) {
+    print_error_discard_rest(create_tag(db_path, name, hash));
+}
+
+fn print_ref_log(db_path: &str, limit: Option<usize>) {
+    let result = list_ref_log(db_path, limit);
+    match result {
+        Ok(entries) => entries.into_iter().for_each(|entry| {
+            let target = match entry.target {
+                RefLogTarget::CurrentCommit => "checkpoint".to_owned(),
+                RefLogTarget::CurrentBranch => "branch".to_owned(),
+                RefLogTarget::BranchTip(branch_name) => format!("tip of {}", branch_name),
+            };
+            println!(
+                "{} {} {} {}: {} -> {}",
+                entry.id,
+                entry.date,
+                entry.operation,
+                target,
+                entry.old_value.unwrap_or("none".to_owned()),
+                entry.new_value.unwrap_or("none".to_owned())
+            )
+        }),
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn run_jump_back(db_path: &str, file_path: &str, id: i64, verify: bool) {
+    print_error_discard_rest(jump_back(file_path, db_path, id, verify));
+}
+
+fn print_history(db_path: &str, query: HistoryQuery) {
+    let result = query_history(db_path, &query);
+    match result {
+        Ok(commits) => commits.into_iter().for_each(|commit| {
+            println!(
+                "{} {} {} {} {}",
+                commit.hash, commit.branch, commit.author, commit.date, commit.message
+            )
+        }),
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn print_search_results(db_path: &str, query: &str, limit: usize) {
+    let result = search_commits(db_path, query, limit);
+    match result {
+        Ok(commits) => commits.into_iter().for_each(|commit| {
+            println!(
+                "{} {} {} {} {}",
+                commit.hash, commit.branch, commit.author, commit.date, commit.message
+            )
+        }),
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn run_new_branch_command(
+    db_path: &str,
+    new_branch_name: &str,
+    from: Option<String>,
+    description: Option<String>,
+    file_path: Option<String>,
+    verify: bool,
+) {
+    print_error_discard_rest(create_new_branch(
+        db_path,
+        new_branch_name,
+        from.as_deref(),
+        description.as_deref(),
+        file_path.as_deref(),
+        verify,
+    ));
+}
+
+fn print_branch_info(db_path: &str, branch_name: &str) {
+    let result = get_branch_info(db_path, branch_name);
+    match result {
+        Ok(Some(info)) => {
+            println!("Base: {}", info.base);
+            println!("Created: {}", info.created);
+            if let Some(description) = info.description {
+                println!("Description: {}", description);
+            }
+        }
+        Ok(None) => println!("No info recorded for {}", branch_name),
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn print_status(db_path: &str, file_path: &str) {
+    match working_file_status(file_path, db_path) {
+        Ok(status) => {
+            if status.detached {
+                println!("On an older checkpoint, committing starts a new branch");
+            }
+            if !status.dirty {
+                println!("No changes since {}", status.commit);
+                return;
+            }
+
+            println!("Changes since {}", status.commit);
+            println!("Changed blocks: {}", status.changed_blocks);
+            for datablock in status.changed_datablocks {
+                println!("  {}", datablock);
+            }
+        }
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn run_create_new_commit(
+    db_path: &str,
+    file_path: &str,
+    message: Option<String>,
+    metadata: Vec<(String, String)>,
+    branch: Option<String>,
+) {
+    print_error_discard_rest(create_new_commit(
+        file_path,
+        db_path,
+        message,
+        metadata.into_iter().collect(),
+        branch,
+    ));
 }
 
-fn run_create_new_commit(db_path: &str, file_path: &str, message: Option<String>) {
-    print_error_discard_rest(create_new_commit(file_path, db_path, message));
+fn branch_or_current(db_path: &str, branch: Option<String>) -> Result<String, DBError> {
+    branch.map_or_else(|| get_current_branch(db_path), Ok)
 }
 
-fn run_restore_checkpoint(db_path: &str, file_path: &str, hash: &str) {
-    print_error_discard_rest(restore_checkpoint(file_path, db_path, hash));
+fn run_watch_command(db_path: &str, file_path: &str, debounce_ms: u64) {
+    let options = WatchOptions {
+        debounce: Duration::from_millis(debounce_ms),
+        ..WatchOptions::default()
+    };
+
+    println!("Watching {}, press Ctrl-C to stop", file_path);
+    watch_file(
+        file_path,
+        db_path,
+        &options,
+        &AtomicBool::new(false),
+        |event| match event {
+            WatchEvent::Committed(hash) => println!("Committed {}", hash),
+            WatchEvent::Unchanged => println!("Saved without changes"),
+            WatchEvent::Detached(hash) => println!(
+                "Not committed, checkpoint {} is restored, commit it to start a new branch",
+                hash
+            ),
+            WatchEvent::Failed(err) => error!("{}", err),
+        },
+    );
+}
+
+fn run_amend_command(
+    db_path: &str,
+    branch: Option<String>,
+    message: Option<String>,
+    metadata: Vec<(String, String)>,
+) {
+    let result = branch_or_current(db_path, branch).and_then(|branch_name| {
+        amend_latest_commit(
+            db_path,
+            &branch_name,
+            message,
+            metadata.into_iter().collect(),
+        )
+    });
+    match result {
+        Ok(hash) => println!("Amended checkpoint: {}", hash),
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn run_undo_command(db_path: &str, branch: Option<String>) {
+    let result = branch_or_current(db_path, branch)
+        .and_then(|branch_name| undo_latest_commit(db_path, &branch_name));
+    match result {
+        Ok(hash) => println!("Latest checkpoint is now {}", hash),
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn run_squash_command(
+    db_path: &str,
+    branch: Option<String>,
+    from: &str,
+    to: &str,
+    message: Option<String>,
+) {
+    let result = branch_or_current(db_path, branch)
+        .and_then(|branch_name| squash_checkpoints(db_path, &branch_name, from, to, message));
+    match result {
+        Ok(hash) => println!("Squashed checkpoint: {}", hash),
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn run_set_retention_command(
+    db_path: &str,
+    branch: Option<String>,
+    keep_all_hours: u64,
+    keep_hourly_days: u64,
+) {
+    let policy = RetentionPolicy {
+        keep_all_for: keep_all_hours * 60 * 60,
+        keep_hourly_for: keep_hourly_days * 24 * 60 * 60,
+    };
+    print_error_discard_rest(
+        branch_or_current(db_path, branch)
+            .and_then(|branch_name| set_retention_policy(db_path, &branch_name, policy)),
+    );
+}
+
+fn run_prune_command(db_path: &str, branch: Option<String>) {
+    let result = branch_or_current(db_path, branch)
+        .and_then(|branch_name| prune_branch(db_path, &branch_name));
+    match result {
+        Ok(removed) => println!("Removed {} checkpoints", removed.len()),
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn run_set_metadata_command(db_path: &str, hash: &str, key: &str, value: &str) {
+    print_error_discard_rest(set_commit_metadata(db_path, hash, key, value));
+}
+
+fn print_metadata(db_path: &str, hash: &str) {
+    let result = read_commit_metadata(db_path, hash);
+    match result {
+        Ok(metadata) => metadata
+            .into_iter()
+            .for_each(|entry| println!("{}={}", entry.key, entry.value)),
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn print_commits_by_metadata(db_path: &str, key: &str, value: &str) {
+    let result = find_commits_by_metadata(db_path, key, value);
+    match result {
+        Ok(commits) => commits
+            .into_iter()
+            .for_each(|commit| println!("{} {} {}", commit.hash, commit.branch, commit.message)),
+        Err(err) => error!("{}", err),
+    }
 }
 
-fn run_switch_branches(db_path: &str, file_path: &str, branch_name: &str) {
-    print_error_discard_rest(switch_branches(db_path, branch_name, file_path));
+fn run_restore_checkpoint(db_path: &str, file_path: &str, hash: &str, verify: bool) {
+    print_error_discard_rest(restore_checkpoint(file_path, db_path, hash, verify));
+}
+
+fn run_materialize_checkpoint(db_path: &str, file_path: &str, hash: &str, verify: bool) {
+    print_error_discard_rest(materialize_checkpoint(file_path, db_path, hash, verify));
+}
+
+fn run_switch_branches(db_path: &str, file_path: &str, branch_name: &str, verify: bool) {
+    print_error_discard_rest(switch_branches(db_path, branch_name, file_path, verify));
+}
+
+fn run_recover_command(db_path: &str) {
+    match roll_back_unfinished_operations(db_path) {
+        Ok(operations) if operations.is_empty() => println!("Nothing to roll back"),
+        Ok(operations) => operations
+            .into_iter()
+            .for_each(|operation| println!("Rolled back unfinished operation: {}", operation)),
+        Err(err) => error!("{}", err),
+    }
 }
 
 fn run_get_current_branch(db_path: &str) {
@@ -76,9 +369,74 @@ fn run_get_current_branch(db_path: &str) {
     }
 }
 
-fn run_init_command(db_path: &str, file_path: &str) {
+fn parse_hash_algorithm(name: &str) -> Result<HashAlgorithm, DBError> {
+    HashAlgorithm::from_name(name)
+        .ok_or(DBError::Error(format!("Unknown hash algorithm: {}", name)))
+}
+
+fn run_init_command(db_path: &str, file_path: &str, hash_algorithm: Option<String>) {
+    let project_id = uuid::Uuid::new_v4().to_string();
+    let result = hash_algorithm
+        .map_or(Ok(HashAlgorithm::default()), |name| {
+            parse_hash_algorithm(&name)
+        })
+        .and_then(|hash_algorithm| init_db(db_path, &project_id, file_path, hash_algorithm));
+    print_error_discard_rest(result);
+}
+
+fn run_export_branch_command(
+    db_path: &str,
+    branch: Option<String>,
+    dir: &str,
+    from: Option<String>,
+    to: Option<String>,
+) {
+    let result = branch_or_current(db_path, branch).and_then(|branch_name| {
+        export_branch(db_path, &branch_name, dir, from.as_deref(), to.as_deref())
+    });
+    match result {
+        Ok(manifest) => manifest
+            .checkpoints
+            .into_iter()
+            .for_each(|checkpoint| println!("{} {}", checkpoint.file_name, checkpoint.hash)),
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn run_import_versions_command(
+    db_path: &str,
+    path: &str,
+    by_mtime: bool,
+    hash_algorithm: Option<String>,
+) {
     let project_id = uuid::Uuid::new_v4().to_string();
-    print_error_discard_rest(init_db(db_path, &project_id, file_path));
+    let order = if by_mtime {
+        VersionOrder::Mtime
+    } else {
+        VersionOrder::Number
+    };
+    let result = hash_algorithm
+        .map_or(Ok(HashAlgorithm::default()), |name| {
+            parse_hash_algorithm(&name)
+        })
+        .and_then(|hash_algorithm| {
+            import_versions(db_path, path, order, &project_id, hash_algorithm)
+        });
+
+    match result {
+        Ok(imported) => imported.into_iter().for_each(|version| match version.hash {
+            Some(hash) => println!("{} {}", hash, version.file_path),
+            None => println!("Skipped {}, nothing changed", version.file_path),
+        }),
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn run_migrate_hash_command(db_path: &str, hash_algorithm: &str) {
+    print_error_discard_rest(
+        parse_hash_algorithm(hash_algorithm)
+            .and_then(|hash_algorithm| migrate_hash_algorithm(db_path, hash_algorithm)),
+    );
 }
 
 fn run_export_command(db_path: &str, root_hash: &str, path_to_file: &str) {
@@ -89,7 +447,74 @@ fn run_export_command(db_path: &str, root_hash: &str, path_to_file: &str) {
 fn run_import_command(db_path: &str, path_to_exchange: &str) {
     let exchange =
         read_exchange_from_file(path_to_exchange).expect("Cannot read exchange from file");
-    import_exchange::import_exchange(db_path, exchange).expect("Cannot import exchange");
+    let skipped_tags =
+        import_exchange::import_exchange(db_path, exchange).expect("Cannot import exchange");
+    print_skipped_tags(skipped_tags);
+}
+
+fn run_rename_branch_command(db_path: &str, branch_name: &str, new_name: &str) {
+    print_error_discard_rest(rename_branch(db_path, branch_name, new_name));
+}
+
+fn run_merge_command(
+    db_path: &str,
+    file_path: &str,
+    branch_name: &str,
+    ours: Vec<String>,
+    theirs: Vec<String>,
+) {
+    let resolutions: HashMap<String, MergeSide> = ours
+        .into_iter()
+        .map(|name| (name, MergeSide::Ours))
+        .chain(theirs.into_iter().map(|name| (name, MergeSide::Theirs)))
+        .collect();
+
+    match merge_branch(file_path, db_path, branch_name, &resolutions) {
+        Ok(MergeOutcome::Merged) => println!("Merged {}", branch_name),
+        Ok(MergeOutcome::FastForward) => println!("Fast-forwarded to {}", branch_name),
+        Ok(MergeOutcome::UpToDate) => println!("Already up to date"),
+        Ok(MergeOutcome::Conflicts(conflicts)) => {
+            println!("Pick a side with --ours or --theirs for:");
+            for conflict in conflicts {
+                println!("{}: {}", conflict.datablock, conflict.reason);
+            }
+        }
+        Err(err) => error!("{}", err),
+    }
+}
+
+fn run_cherry_pick_command(
+    db_path: &str,
+    file_path: &str,
+    hash: &str,
+    datablocks: Vec<String>,
+    ours: Vec<String>,
+    theirs: Vec<String>,
+) {
+    let resolutions: HashMap<String, MergeSide> = ours
+        .into_iter()
+        .map(|name| (name, MergeSide::Ours))
+        .chain(theirs.into_iter().map(|name| (name, MergeSide::Theirs)))
+        .collect();
+    let datablocks = (!datablocks.is_empty()).then_some(datablocks);
+
+    match cherry_pick(
+        file_path,
+        db_path,
+        hash,
+        datablocks.as_deref(),
+        &resolutions,
+    ) {
+        Ok(CherryPickOutcome::Picked(hash)) => println!("Picked as {}", hash),
+        Ok(CherryPickOutcome::Empty) => println!("Nothing to pick"),
+        Ok(CherryPickOutcome::Conflicts(conflicts)) => {
+            println!("Pick a side with --ours or --theirs for:");
+            for conflict in conflicts {
+                println!("{}: {}", conflict.datablock, conflict.reason);
+            }
+        }
+        Err(err) => error!("{}", err),
+    }
 }
 
 fn run_delete_branch_command(db_path: &str, branch_name: &str) {
@@ -118,7 +543,9 @@ fn sync_to_server(db_path: &str, url: &str) {
 
     let exchange_data = res.bytes().expect("Cannot read response body");
     let exchange = decode_exchange(&exchange_data).expect("Cannot decode exchange");
-    import_exchange::import_exchange(db_path, exchange).expect("Cannot import exchange file");
+    let skipped_tags =
+        import_exchange::import_exchange(db_path, exchange).expect("Cannot import exchange file");
+    print_skipped_tags(skipped_tags);
 }
 
 fn run_init_from_import(url: &str, db_path: &str, file_path: &str, project_id: &str) {
@@ -145,25 +572,167 @@ fn main() {
             db_path,
             file_path,
             message,
-        } => run_create_new_commit(&db_path, &file_path, message),
+            metadata,
+            branch,
+        } => run_create_new_commit(&db_path, &file_path, message, metadata, branch),
+        Commands::Watch {
+            db_path,
+            file_path,
+            debounce_ms,
+        } => run_watch_command(&db_path, &file_path, debounce_ms),
+        Commands::Amend {
+            db_path,
+            branch,
+            message,
+            metadata,
+        } => run_amend_command(&db_path, branch, message, metadata),
+        Commands::Undo { db_path, branch } => run_undo_command(&db_path, branch),
+        Commands::Squash {
+            db_path,
+            branch,
+            from,
+            to,
+            message,
+        } => run_squash_command(&db_path, branch, &from, &to, message),
+        Commands::SetRetention {
+            db_path,
+            branch,
+            keep_all_hours,
+            keep_hourly_days,
+        } => run_set_retention_command(&db_path, branch, keep_all_hours, keep_hourly_days),
+        Commands::Prune { db_path, branch } => run_prune_command(&db_path, branch),
+        Commands::Status { db_path, file_path } => print_status(&db_path, &file_path),
+        Commands::SetMetadata {
+            db_path,
+            hash,
+            metadata: (key, value),
+        } => run_set_metadata_command(&db_path, &hash, &key, &value),
+        Commands::ShowMetadata { db_path, hash } => print_metadata(&db_path, &hash),
+        Commands::FindByMetadata {
+            db_path,
+            metadata: (key, value),
+        } => print_commits_by_metadata(&db_path, &key, &value),
         Commands::Restore {
             db_path,
             file_path,
             hash,
-        } => run_restore_checkpoint(&db_path, &file_path, &hash),
+            verify,
+        } => run_restore_checkpoint(&db_path, &file_path, &hash, verify),
+        Commands::Materialize {
+            db_path,
+            file_path,
+            hash,
+            verify,
+        } => run_materialize_checkpoint(&db_path, &file_path, &hash, verify),
         Commands::NewBranch {
             db_path,
             branch_name,
-        } => run_new_branch_command(&db_path, &branch_name),
+            from,
+            description,
+            file_path,
+            verify,
+        } => run_new_branch_command(&db_path, &branch_name, from, description, file_path, verify),
+        Commands::RenameBranch {
+            db_path,
+            branch_name,
+            new_name,
+        } => run_rename_branch_command(&db_path, &branch_name, &new_name),
+        Commands::Merge {
+            db_path,
+            file_path,
+            branch_name,
+            ours,
+            theirs,
+        } => run_merge_command(&db_path, &file_path, &branch_name, ours, theirs),
+        Commands::CherryPick {
+            db_path,
+            file_path,
+            hash,
+            datablock,
+            ours,
+            theirs,
+        } => run_cherry_pick_command(&db_path, &file_path, &hash, datablock, ours, theirs),
+        Commands::BranchInfo {
+            db_path,
+            branch_name,
+        } => print_branch_info(&db_path, &branch_name),
         Commands::ListBranches { db_path } => print_all_branches(&db_path),
+        Commands::Tag {
+            db_path,
+            name,
+            hash,
+        } => run_tag_command(&db_path, &name, &hash),
+        Commands::ListTags { db_path } => print_all_tags(&db_path),
+        Commands::Reflog { db_path, limit } => print_ref_log(&db_path, limit),
+        Commands::JumpBack {
+            db_path,
+            file_path,
+            id,
+            verify,
+        } => run_jump_back(&db_path, &file_path, id, verify),
+        Commands::Recover { db_path } => run_recover_command(&db_path),
         Commands::GetCurrentBranch { db_path } => run_get_current_branch(&db_path),
         Commands::Switch {
             db_path,
             branch,
             file_path,
-        } => run_switch_branches(&db_path, &file_path, &branch),
+            verify,
+        } => run_switch_branches(&db_path, &file_path, &branch, verify),
         Commands::LogCheckpoints { db_path, branch } => print_checkpoints(&db_path, &branch),
-        Commands::Init { db_path, file_path } => run_init_command(&db_path, &file_path),
+        Commands::Search {
+            db_path,
+            query,
+            limit,
+        } => print_search_results(&db_path, &query, limit),
+        Commands::History {
+            db_path,
+            author,
+            branch,
+            message,
+            since,
+            until,
+            limit,
+            offset,
+            oldest_first,
+        } => print_history(
+            &db_path,
+            HistoryQuery {
+                author,
+                branch,
+                message_contains: message,
+                since,
+                until,
+                order: if oldest_first {
+                    HistoryOrder::OldestFirst
+                } else {
+                    HistoryOrder::NewestFirst
+                },
+                limit,
+                offset,
+            },
+        ),
+        Commands::Init {
+            db_path,
+            file_path,
+            hash_algorithm,
+        } => run_init_command(&db_path, &file_path, hash_algorithm),
+        Commands::ExportBranch {
+            db_path,
+            branch,
+            dir,
+            from,
+            to,
+        } => run_export_branch_command(&db_path, branch, &dir, from, to),
+        Commands::ImportVersions {
+            db_path,
+            path,
+            by_mtime,
+            hash_algorithm,
+        } => run_import_versions_command(&db_path, &path, by_mtime, hash_algorithm),
+        Commands::MigrateHash {
+            db_path,
+            hash_algorithm,
+        } => run_migrate_hash_command(&db_path, &hash_algorithm),
         Commands::Export {
             db_path,
             from_commit,
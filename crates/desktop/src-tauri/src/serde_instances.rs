@@ -26,6 +26,7 @@ impl Serialize for DBErrorWrapper {
             DBError::Error(ref err) => serializer.serialize_str(err),
             DBError::Consistency(ref err) => serializer.serialize_str(err),
             DBError::Fundamental(ref err) => serializer.serialize_str(err),
+            DBError::Busy(ref err) => serializer.serialize_str(err),
         }
     }
 }
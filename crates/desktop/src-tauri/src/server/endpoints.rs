@@ -3,19 +3,51 @@ use actix_web::{
     web::{self, Json},
     HttpResponse, Responder,
 };
-use log::error;
+use log::{error, info, warn};
 use parserprinter::{
     api::{
-        commit_command::create_new_commit, get_current_branch, get_latest_commit, init_command,
-        list_branches_command::list_braches, log_checkpoints_command::list_checkpoints,
-        new_branch_command::create_new_branch, restore_command::restore_checkpoint,
+        amend_command::{amend_latest_commit, undo_latest_commit},
+        cherry_pick_command::cherry_pick,
+        commit_command::create_new_commit,
+        export_branch_command::export_branch,
+        get_current_branch, get_latest_commit,
+        history_command::query_history,
+        import_versions_command::{import_versions, VersionOrder},
+        init_command,
+        list_branches_command::{get_branch_info, list_braches},
+        log_checkpoints_command::list_checkpoints,
+        merge_command::merge_branch,
+        metadata_command::{find_commits_by_metadata, read_commit_metadata},
+        new_branch_command::create_new_branch,
+        prune_command::{get_retention_policy, prune_branch, set_retention_policy},
+        ref_log_command::{jump_back, list_ref_log},
+        rename_branch_command,
+        restore_command::{materialize_checkpoint, restore_checkpoint},
+        search_command::search_commits,
+        squash_command::squash_checkpoints,
+        status_command::working_file_status,
         switch_command::switch_branches,
+        tag_command::{create_tag, list_tags},
+        watch_command::{watch_file, WatchEvent, WatchOptions},
+    },
+    blend::merge::MergeSide,
+    db::{
+        db_ops::DBError,
+        structs::{HashAlgorithm, HistoryQuery, RetentionPolicy},
     },
-    db::db_ops::DBError,
 };
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
 
-use crate::serde_instances::DBErrorWrapper;
+use crate::serde_instances::{DBErrorWrapper, ShortCommitRecordWrapper};
 
 fn error_if_not_exists(db_path: &str) -> Result<(), DBError> {
     let exists = std::path::Path::new(db_path).exists();
@@ -35,6 +67,11 @@ pub struct CommitPayload {
     db_path: String,
     file_path: String,
     message: String,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    /// The branch to start when committing on top of an older checkpoint
+    #[serde(default)]
+    branch_name: Option<String>,
 }
 
 #[post("/commit")]
@@ -43,6 +80,8 @@ pub async fn commit(data: Json<CommitPayload>) -> impl Responder {
         &data.file_path,
         &data.db_path,
         Some(data.message.to_owned()),
+        data.metadata.clone(),
+        data.branch_name.clone(),
     );
 
     match result {
@@ -84,16 +123,245 @@ pub async fn checkpoints(path: web::Path<(String, String)>) -> impl Responder {
     }
 }
 
+#[get("/history/{db_path}")]
+pub async fn history(
+    path: web::Path<(String,)>,
+    query: web::Query<HistoryQuery>,
+) -> impl Responder {
+    let (db_path,) = path.into_inner();
+    let result = error_if_not_exists(&db_path).and_then(|_| query_history(&db_path, &query));
+    match result {
+        Ok(commits) => HttpResponse::Ok().json(commits),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    limit: Option<usize>,
+}
+
+#[get("/search/{db_path}")]
+pub async fn search(path: web::Path<(String,)>, query: web::Query<SearchQuery>) -> impl Responder {
+    let (db_path,) = path.into_inner();
+    let result = error_if_not_exists(&db_path)
+        .and_then(|_| search_commits(&db_path, &query.q, query.limit.unwrap_or(20)));
+    match result {
+        Ok(commits) => HttpResponse::Ok().json(commits),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StatusQuery {
+    file_path: String,
+}
+
+#[get("/status/{db_path}")]
+pub async fn status(path: web::Path<(String,)>, query: web::Query<StatusQuery>) -> impl Responder {
+    let (db_path,) = path.into_inner();
+    let result =
+        error_if_not_exists(&db_path).and_then(|_| working_file_status(&query.file_path, &db_path));
+    match result {
+        Ok(status) => HttpResponse::Ok().json(status),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[get("/commit/metadata/{db_path}/{hash}")]
+pub async fn commit_metadata(path: web::Path<(String, String)>) -> impl Responder {
+    let (db_path, hash) = path.into_inner();
+    let result = error_if_not_exists(&db_path).and_then(|_| read_commit_metadata(&db_path, &hash));
+    match result {
+        Ok(metadata) => HttpResponse::Ok().json(
+            metadata
+                .into_iter()
+                .map(|entry| (entry.key, entry.value))
+                .collect::<HashMap<String, String>>(),
+        ),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[get("/checkpoints/metadata/{db_path}/{key}/{value}")]
+pub async fn checkpoints_with_metadata(
+    path: web::Path<(String, String, String)>,
+) -> impl Responder {
+    let (db_path, key, value) = path.into_inner();
+    let result = error_if_not_exists(&db_path)
+        .and_then(|_| find_commits_by_metadata(&db_path, &key, &value));
+    match result {
+        Ok(commits) => HttpResponse::Ok().json(
+            commits
+                .into_iter()
+                .map(ShortCommitRecordWrapper)
+                .collect::<Vec<ShortCommitRecordWrapper>>(),
+        ),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+fn verify_by_default() -> bool {
+    true
+}
+
 #[derive(Deserialize)]
 pub struct RestorePayload {
     db_path: String,
     file_path: String,
     hash: String,
+    /// Whether the written file is checked against the checkpoint
+    #[serde(default = "verify_by_default")]
+    verify: bool,
 }
 
 #[post("/restore")]
 pub async fn restore(data: Json<RestorePayload>) -> impl Responder {
-    let result = restore_checkpoint(&data.file_path, &data.db_path, &data.hash);
+    let result = restore_checkpoint(&data.file_path, &data.db_path, &data.hash, data.verify);
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json("OK"),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[post("/materialize")]
+pub async fn materialize(data: Json<RestorePayload>) -> impl Responder {
+    let result = materialize_checkpoint(&data.file_path, &data.db_path, &data.hash, data.verify);
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json("OK"),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AmendPayload {
+    db_path: String,
+    branch_name: String,
+    message: Option<String>,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+#[post("/commit/amend")]
+pub async fn amend(data: Json<AmendPayload>) -> impl Responder {
+    let result = amend_latest_commit(
+        &data.db_path,
+        &data.branch_name,
+        data.message.clone(),
+        data.metadata.clone(),
+    );
+
+    match result {
+        Ok(hash) => HttpResponse::Ok().json(hash),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UndoPayload {
+    db_path: String,
+    branch_name: String,
+}
+
+#[post("/commit/undo")]
+pub async fn undo(data: Json<UndoPayload>) -> impl Responder {
+    let result = undo_latest_commit(&data.db_path, &data.branch_name);
+
+    match result {
+        Ok(hash) => HttpResponse::Ok().json(hash),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SquashPayload {
+    db_path: String,
+    branch_name: String,
+    from: String,
+    to: String,
+    message: Option<String>,
+}
+
+#[post("/commit/squash")]
+pub async fn squash(data: Json<SquashPayload>) -> impl Responder {
+    let result = squash_checkpoints(
+        &data.db_path,
+        &data.branch_name,
+        &data.from,
+        &data.to,
+        data.message.clone(),
+    );
+
+    match result {
+        Ok(hash) => HttpResponse::Ok().json(hash),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RefLogQuery {
+    limit: Option<usize>,
+}
+
+#[get("/reflog/{db_path}")]
+pub async fn ref_log(path: web::Path<(String,)>, query: web::Query<RefLogQuery>) -> impl Responder {
+    let (db_path,) = path.into_inner();
+    let result = error_if_not_exists(&db_path).and_then(|_| list_ref_log(&db_path, query.limit));
+    match result {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JumpBackPayload {
+    db_path: String,
+    file_path: String,
+    id: i64,
+    /// Whether the written file is checked against the checkpoint
+    #[serde(default = "verify_by_default")]
+    verify: bool,
+}
+
+#[post("/reflog/jump-back")]
+pub async fn reflog_jump_back(data: Json<JumpBackPayload>) -> impl Responder {
+    let result = jump_back(&data.file_path, &data.db_path, data.id, data.verify);
 
     match result {
         Ok(_) => HttpResponse::Ok().json("OK"),
@@ -121,11 +389,25 @@ pub async fn branches(path: web::Path<(String,)>) -> impl Responder {
 pub struct NewBranchPayload {
     db_path: String,
     branch_name: String,
+    from: Option<String>,
+    description: Option<String>,
+    /// Where the starting point is restored, if it is not the current commit
+    file_path: Option<String>,
+    /// Whether the restored file is checked against the starting point
+    #[serde(default = "verify_by_default")]
+    verify: bool,
 }
 
 #[post("/branches/new")]
 pub async fn new_branch(data: Json<NewBranchPayload>) -> impl Responder {
-    let result = create_new_branch(&data.db_path, &data.branch_name);
+    let result = create_new_branch(
+        &data.db_path,
+        &data.branch_name,
+        data.from.as_deref(),
+        data.description.as_deref(),
+        data.file_path.as_deref(),
+        data.verify,
+    );
     match result {
         Ok(_) => HttpResponse::Ok().json("OK"),
         Err(err) => {
@@ -135,16 +417,145 @@ pub async fn new_branch(data: Json<NewBranchPayload>) -> impl Responder {
     }
 }
 
+#[derive(Deserialize)]
+pub struct RenameBranchPayload {
+    db_path: String,
+    branch_name: String,
+    new_name: String,
+}
+
+#[post("/branches/rename")]
+pub async fn rename_branch(data: Json<RenameBranchPayload>) -> impl Responder {
+    let result =
+        rename_branch_command::rename_branch(&data.db_path, &data.branch_name, &data.new_name);
+    match result {
+        Ok(_) => HttpResponse::Ok().json("OK"),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MergeBranchPayload {
+    db_path: String,
+    file_path: String,
+    branch_name: String,
+    #[serde(default)]
+    resolutions: HashMap<String, MergeSide>,
+}
+
+#[post("/branches/merge")]
+pub async fn merge(data: Json<MergeBranchPayload>) -> impl Responder {
+    let result = merge_branch(
+        &data.file_path,
+        &data.db_path,
+        &data.branch_name,
+        &data.resolutions,
+    );
+    match result {
+        Ok(outcome) => HttpResponse::Ok().json(outcome),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CherryPickPayload {
+    db_path: String,
+    file_path: String,
+    hash: String,
+    /// Only the changes to these datablocks are picked if given
+    datablocks: Option<Vec<String>>,
+    #[serde(default)]
+    resolutions: HashMap<String, MergeSide>,
+}
+
+#[post("/commit/cherry-pick")]
+pub async fn cherry_pick_commit(data: Json<CherryPickPayload>) -> impl Responder {
+    let result = cherry_pick(
+        &data.file_path,
+        &data.db_path,
+        &data.hash,
+        data.datablocks.as_deref(),
+        &data.resolutions,
+    );
+    match result {
+        Ok(outcome) => HttpResponse::Ok().json(outcome),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[get("/branches/info/{db_path}/{branch}")]
+pub async fn branch_info(path: web::Path<(String, String)>) -> impl Responder {
+    let (db_path, branch_name) = path.into_inner();
+    let result =
+        error_if_not_exists(&db_path).and_then(|_| get_branch_info(&db_path, &branch_name));
+    match result {
+        Ok(info) => HttpResponse::Ok().json(info),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct SwitchBranchPayload {
     db_path: String,
     branch_name: String,
     file_path: String,
+    /// Whether the written file is checked against the tip of the branch
+    #[serde(default = "verify_by_default")]
+    verify: bool,
 }
 
 #[post("/branches/switch")]
 pub async fn switch_branch(data: Json<SwitchBranchPayload>) -> impl Responder {
-    let result = switch_branches(&data.db_path, &data.branch_name, &data.file_path);
+    let result = switch_branches(
+        &data.db_path,
+        &data.branch_name,
+        &data.file_path,
+        data.verify,
+    );
+    match result {
+        Ok(_) => HttpResponse::Ok().json("OK"),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[get("/tags/{db_path}")]
+pub async fn tags(path: web::Path<(String,)>) -> impl Responder {
+    let (db_path,) = path.into_inner();
+    let result = error_if_not_exists(&db_path).and_then(|_| list_tags(&db_path));
+    match result {
+        Ok(tags) => HttpResponse::Ok().json(tags),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NewTagPayload {
+    db_path: String,
+    name: String,
+    hash: String,
+}
+
+#[post("/tags/new")]
+pub async fn new_tag(data: Json<NewTagPayload>) -> impl Responder {
+    let result = create_tag(&data.db_path, &data.name, &data.hash);
     match result {
         Ok(_) => HttpResponse::Ok().json("OK"),
         Err(err) => {
@@ -191,7 +602,12 @@ pub struct InitDBPayload {
 #[post("/init")]
 pub async fn init_db(data: Json<InitDBPayload>) -> impl Responder {
     let project_id = uuid::Uuid::new_v4().to_string();
-    let result = init_command::init_db(&data.db_path, &project_id, &data.file_path);
+    let result = init_command::init_db(
+        &data.db_path,
+        &project_id,
+        &data.file_path,
+        HashAlgorithm::default(),
+    );
     match result {
         Ok(_) => HttpResponse::Ok().finish(),
         Err(err) => {
@@ -200,3 +616,201 @@ pub async fn init_db(data: Json<InitDBPayload>) -> impl Responder {
         }
     }
 }
+
+#[derive(Deserialize)]
+pub struct ExportBranchPayload {
+    db_path: String,
+    branch_name: String,
+    dir: String,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[post("/branches/export")]
+pub async fn export_branch_files(data: Json<ExportBranchPayload>) -> impl Responder {
+    let result = export_branch(
+        &data.db_path,
+        &data.branch_name,
+        &data.dir,
+        data.from.as_deref(),
+        data.to.as_deref(),
+    );
+    match result {
+        Ok(manifest) => HttpResponse::Ok().json(manifest),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ImportVersionsPayload {
+    db_path: String,
+    /// A blend file with backups next to it, or a folder of blend files
+    path: String,
+    order: VersionOrder,
+}
+
+#[post("/import/versions")]
+pub async fn import_file_versions(data: Json<ImportVersionsPayload>) -> impl Responder {
+    let project_id = uuid::Uuid::new_v4().to_string();
+    let result = import_versions(
+        &data.db_path,
+        &data.path,
+        data.order,
+        &project_id,
+        HashAlgorithm::default(),
+    );
+    match result {
+        Ok(imported) => HttpResponse::Ok().json(imported),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[get("/retention/{db_path}/{branch}")]
+pub async fn retention_policy(path: web::Path<(String, String)>) -> impl Responder {
+    let (db_path, branch_name) = path.into_inner();
+
+    let result =
+        error_if_not_exists(&db_path).and_then(|_| get_retention_policy(&db_path, &branch_name));
+
+    match result {
+        Ok(policy) => HttpResponse::Ok().json(policy),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RetentionPayload {
+    db_path: String,
+    branch_name: String,
+    policy: RetentionPolicy,
+}
+
+#[post("/retention")]
+pub async fn set_retention(data: Json<RetentionPayload>) -> impl Responder {
+    let result = set_retention_policy(&data.db_path, &data.branch_name, data.policy);
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json("OK"),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PrunePayload {
+    db_path: String,
+    branch_name: String,
+}
+
+#[post("/prune")]
+pub async fn prune(data: Json<PrunePayload>) -> impl Responder {
+    let result = prune_branch(&data.db_path, &data.branch_name);
+
+    match result {
+        Ok(removed) => HttpResponse::Ok().json(removed),
+        Err(err) => {
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+/// The files being watched, with the flags that stop their watchers
+#[derive(Default)]
+pub struct Watchers(Mutex<HashMap<String, Arc<AtomicBool>>>);
+
+#[derive(Deserialize)]
+pub struct WatchPayload {
+    db_path: String,
+    file_path: String,
+    debounce_ms: Option<u64>,
+}
+
+#[post("/watch")]
+pub async fn start_watching(
+    watchers: web::Data<Watchers>,
+    data: Json<WatchPayload>,
+) -> impl Responder {
+    let mut watched = watchers.0.lock().unwrap();
+
+    let result = error_if_not_exists(&data.db_path).and_then(|_| {
+        if watched.contains_key(&data.file_path) {
+            return Err(DBError::Error(format!(
+                "{} is already watched",
+                data.file_path
+            )));
+        }
+        Ok(())
+    });
+    if let Err(err) = result {
+        error!("{}", err);
+        return HttpResponse::BadRequest().json(DBErrorWrapper(err));
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    watched.insert(data.file_path.clone(), stop.clone());
+
+    let (db_path, file_path) = (data.db_path.clone(), data.file_path.clone());
+    let options = WatchOptions {
+        debounce: data
+            .debounce_ms
+            .map_or(WatchOptions::default().debounce, Duration::from_millis),
+        ..WatchOptions::default()
+    };
+    thread::spawn(move || {
+        watch_file(&file_path, &db_path, &options, &stop, |event| match event {
+            WatchEvent::Committed(hash) => info!("Committed {} as {}", file_path, hash),
+            WatchEvent::Unchanged => {}
+            WatchEvent::Detached(hash) => {
+                warn!(
+                    "Not committing {}, checkpoint {} is restored",
+                    file_path, hash
+                )
+            }
+            WatchEvent::Failed(err) => error!("{}", err),
+        })
+    });
+
+    HttpResponse::Ok().json("OK")
+}
+
+#[derive(Deserialize)]
+pub struct StopWatchingPayload {
+    file_path: String,
+}
+
+#[post("/watch/stop")]
+pub async fn stop_watching(
+    watchers: web::Data<Watchers>,
+    data: Json<StopWatchingPayload>,
+) -> impl Responder {
+    match watchers.0.lock().unwrap().remove(&data.file_path) {
+        Some(stop) => {
+            stop.store(true, Ordering::Relaxed);
+            HttpResponse::Ok().json("OK")
+        }
+        None => {
+            let err = DBError::Error(format!("{} is not watched", data.file_path));
+            error!("{}", err);
+            HttpResponse::BadRequest().json(DBErrorWrapper(err))
+        }
+    }
+}
+
+#[get("/watch")]
+pub async fn watched_files(watchers: web::Data<Watchers>) -> impl Responder {
+    let mut file_paths: Vec<String> = watchers.0.lock().unwrap().keys().cloned().collect();
+    file_paths.sort();
+    HttpResponse::Ok().json(file_paths)
+}
@@ -1,22 +1,54 @@
-use actix_web::{App, HttpServer};
+use actix_web::{web, App, HttpServer};
 
 use super::endpoints::{
-    branches, checkpoints, commit, healthcheck, new_branch, read_current_branch,
-    read_latest_commit_hash, restore, switch_branch,
+    amend, branch_info, branches, checkpoints, checkpoints_with_metadata, cherry_pick_commit,
+    commit, commit_metadata, export_branch_files, healthcheck, history, import_file_versions,
+    materialize, merge, new_branch, new_tag, prune, read_current_branch, read_latest_commit_hash,
+    ref_log, reflog_jump_back, rename_branch, restore, retention_policy, search, set_retention,
+    squash, start_watching, status, stop_watching, switch_branch, tags, undo, watched_files,
+    Watchers,
 };
 
 pub async fn serve() {
-    HttpServer::new(|| {
+    let watchers = web::Data::new(Watchers::default());
+
+    HttpServer::new(move || {
         App::new()
+            .app_data(watchers.clone())
             .service(healthcheck)
             .service(commit)
+            .service(amend)
+            .service(undo)
+            .service(squash)
+            .service(retention_policy)
+            .service(set_retention)
+            .service(prune)
             .service(checkpoints)
             .service(restore)
+            .service(materialize)
+            .service(ref_log)
+            .service(reflog_jump_back)
             .service(branches)
             .service(new_branch)
+            .service(branch_info)
+            .service(rename_branch)
+            .service(merge)
+            .service(cherry_pick_commit)
             .service(switch_branch)
             .service(read_current_branch)
             .service(read_latest_commit_hash)
+            .service(tags)
+            .service(commit_metadata)
+            .service(checkpoints_with_metadata)
+            .service(history)
+            .service(import_file_versions)
+            .service(export_branch_files)
+            .service(search)
+            .service(status)
+            .service(start_watching)
+            .service(stop_watching)
+            .service(watched_files)
+            .service(new_tag)
     })
     .bind(("127.0.0.1", 8080))
     .expect("Cannot bind to 127.0.0.1:8080")
@@ -0,0 +1,294 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    api::common::hash_new_commit,
+    db::{
+        db_ops::{DBError, Persistence, DB},
+        structs::{Commit, CommitMetadata, Intent},
+    },
+};
+
+/// Reads the tip of `branch_name`, as long as it can be rewritten without
+/// breaking anything that refers to it: it has to be committed on the branch,
+/// with nothing built on top of it, not tagged, and not shared with other
+/// timelines.
+fn read_rewritable_tip(db: &Persistence, branch_name: &str) -> Result<Commit, DBError> {
+    let tip = db
+        .read_branch_tip(branch_name)?
+        .ok_or(DBError::Error(format!("No such branch: {}", branch_name)))?;
+
+    let commit = db
+        .read_commit(&tip)?
+        .ok_or(DBError::Consistency(format!("No such commit: {}", tip)))?;
+
+    if commit.branch != branch_name {
+        return Err(DBError::Error(format!(
+            "Branch {} has no checkpoints of its own",
+            branch_name
+        )));
+    }
+
+    if db.read_descendants_of_commit(&tip)?.len() > 1 {
+        return Err(DBError::Error(format!(
+            "Checkpoint {} has checkpoints built on top of it",
+            tip
+        )));
+    }
+
+    for other_branch in db.read_all_branches()? {
+        if other_branch != branch_name && db.read_branch_tip(&other_branch)? == Some(tip.clone()) {
+            return Err(DBError::Error(format!(
+                "Checkpoint {} is also the tip of {}",
+                tip, other_branch
+            )));
+        }
+    }
+
+    if let Some(tag) = db.read_all_tags()?.into_iter().find(|tag| tag.hash == tip) {
+        return Err(DBError::Error(format!(
+            "Checkpoint {} is tagged as {}",
+            tip, tag.name
+        )));
+    }
+
+    if matches!(db.read_remote_branch_tip(branch_name), Ok(remote_tip) if remote_tip == tip) {
+        return Err(DBError::Error(format!(
+            "Checkpoint {} is shared with other timelines",
+            tip
+        )));
+    }
+
+    Ok(commit)
+}
+
+/// Replaces the message of the tip of `branch_name` if `message` is given, and
+/// sets the entries of `metadata` on it. Since the hash of a commit covers
+/// both, the amended commit gets a new hash, which is returned. The old hash
+/// stays an alias of the new one.
+pub fn amend_latest_commit(
+    db_path: &str,
+    branch_name: &str,
+    message: Option<String>,
+    metadata: HashMap<String, String>,
+) -> Result<String, DBError> {
+    let mut db = Persistence::open(db_path)?;
+
+    let commit = read_rewritable_tip(&db, branch_name)?;
+    let old_hash = commit.hash.clone();
+
+    // entries that were left out of the hash stay out of it, unless they are
+    // set again
+    let (hashed, unhashed): (Vec<CommitMetadata>, Vec<CommitMetadata>) = db
+        .read_commit_metadata(&old_hash)?
+        .into_iter()
+        .filter(|entry| !metadata.contains_key(&entry.key))
+        .partition(|entry| entry.hashed);
+    let mut hashed_metadata: BTreeMap<String, String> = hashed
+        .into_iter()
+        .map(|entry| (entry.key, entry.value))
+        .collect();
+    hashed_metadata.extend(metadata);
+
+    let mut amended = Commit {
+        message: message.unwrap_or(commit.message.clone()),
+        ..commit
+    };
+    let mut metadata = hash_new_commit(&mut amended, hashed_metadata, db.read_hash_algorithm()?);
+    metadata.extend(unhashed.into_iter().map(|entry| CommitMetadata {
+        hash: amended.hash.clone(),
+        ..entry
+    }));
+
+    if amended.hash == old_hash {
+        return Ok(old_hash);
+    }
+
+    if db.commit_exists(&amended.hash)? {
+        return Err(DBError::Error(format!(
+            "Checkpoint {} already exists",
+            amended.hash
+        )));
+    }
+
+    let new_hash = amended.hash.clone();
+    let is_current = db.read_current_commit_pointer().ok() == Some(old_hash.clone());
+    let intent = Intent::new("amend", vec![new_hash.clone()], vec![]);
+
+    db.execute_in_transaction(|tx| Persistence::write_intent(tx, &intent))?;
+
+    db.write_blocks_str(&new_hash, &amended.blocks)?;
+    db.sync_blocks()?;
+
+    db.execute_in_transaction(|tx| {
+        Persistence::delete_commit(tx, &old_hash)?;
+        Persistence::write_commit(tx, amended)?;
+
+        for entry in metadata.iter() {
+            Persistence::write_commit_metadata(tx, entry)?;
+        }
+
+        Persistence::write_branch_tip(tx, branch_name, &new_hash, "amend")?;
+        if is_current {
+            Persistence::write_current_commit_pointer(tx, &new_hash, "amend")?;
+        }
+        // the old hash keeps resolving to the amended commit
+        Persistence::rename_commit_hash(tx, &old_hash, &new_hash)?;
+
+        Persistence::delete_intent(tx, &intent.id)
+    })?;
+
+    db.delete_blocks_str(&old_hash)?;
+
+    Ok(new_hash)
+}
+
+/// Removes the tip of `branch_name`, moving the branch back to its parent, and
+/// returns the new tip. The file being versioned is left alone, so it can be
+/// fixed and committed again. The blocks of the removed commit are kept, since
+/// other commits can share them.
+pub fn undo_latest_commit(db_path: &str, branch_name: &str) -> Result<String, DBError> {
+    let mut db = Persistence::open(db_path)?;
+
+    let commit = read_rewritable_tip(&db, branch_name)?;
+
+    if !db.commit_exists(&commit.prev_commit_hash)? {
+        return Err(DBError::Error(format!(
+            "Cannot undo the first checkpoint of {}",
+            branch_name
+        )));
+    }
+
+    let is_current = db.read_current_commit_pointer().ok() == Some(commit.hash.clone());
+
+    db.execute_in_transaction(|tx| {
+        Persistence::delete_commit(tx, &commit.hash)?;
+        Persistence::write_branch_tip(tx, branch_name, &commit.prev_commit_hash, "undo")?;
+        if is_current {
+            Persistence::write_current_commit_pointer(tx, &commit.prev_commit_hash, "undo")?;
+        }
+        Ok(())
+    })?;
+
+    db.delete_blocks_str(&commit.hash)?;
+
+    Ok(commit.prev_commit_hash)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tempfile::TempDir;
+
+    use crate::{
+        api::{
+            common::{read_latest_commit_hash_on_branch, resolve_commit_hash},
+            init_command::MAIN_BRANCH_NAME,
+            metadata_command::set_commit_metadata,
+            tag_command::create_tag,
+            test_utils,
+        },
+        db::db_ops::{Persistence, DB},
+    };
+
+    use super::{amend_latest_commit, undo_latest_commit};
+
+    #[test]
+    fn test_amend_latest_commit() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_db_path, "Cmomit", "data/untitled_2.blend");
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        let old_tip = read_latest_commit_hash_on_branch(&db, MAIN_BRANCH_NAME).unwrap();
+        drop(db);
+        set_commit_metadata(tmp_db_path, &old_tip, "reviewed", "yes").unwrap();
+
+        let new_tip = amend_latest_commit(
+            tmp_db_path,
+            MAIN_BRANCH_NAME,
+            Some("Commit".to_owned()),
+            HashMap::from([("ticket".to_owned(), "PRJ-1".to_owned())]),
+        )
+        .unwrap();
+        assert_ne!(new_tip, old_tip);
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        assert!(!db.commit_exists(&old_tip).unwrap());
+        assert_eq!(
+            read_latest_commit_hash_on_branch(&db, MAIN_BRANCH_NAME).unwrap(),
+            new_tip
+        );
+        assert_eq!(db.read_current_commit_pointer().unwrap(), new_tip);
+
+        let amended = db.read_commit(&new_tip).unwrap().unwrap();
+        assert_eq!(amended.message, "Commit");
+
+        // The metadata is added to what the commit already had, and entries
+        // that were set later stay out of the hash
+        let metadata = db.read_commit_metadata(&new_tip).unwrap();
+        let keys: Vec<(&str, bool)> = metadata
+            .iter()
+            .map(|entry| (entry.key.as_str(), entry.hashed))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("blender_version", true),
+                ("reviewed", false),
+                ("ticket", true)
+            ]
+        );
+
+        // The old hash still leads to the commit
+        assert_eq!(resolve_commit_hash(&db, &old_tip).unwrap(), new_tip);
+        drop(db);
+
+        assert_eq!(
+            test_utils::list_checkpoints(tmp_db_path, MAIN_BRANCH_NAME).len(),
+            2
+        );
+
+        // Tags never move, so tagged commits stay as they are
+        create_tag(tmp_db_path, "v1", &new_tip).unwrap();
+        assert!(amend_latest_commit(tmp_db_path, MAIN_BRANCH_NAME, None, HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_undo_latest_commit() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
+
+        let checkpoints = test_utils::list_checkpoints(tmp_db_path, MAIN_BRANCH_NAME);
+        let (tip, parent) = (&checkpoints[0].hash, &checkpoints[1].hash);
+
+        let new_tip = undo_latest_commit(tmp_db_path, MAIN_BRANCH_NAME).unwrap();
+        assert_eq!(&new_tip, parent);
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        assert!(!db.commit_exists(tip).unwrap());
+        assert_eq!(db.read_current_commit_pointer().unwrap(), *parent);
+        drop(db);
+
+        // The first commit of the timeline cannot be undone
+        assert!(undo_latest_commit(tmp_db_path, MAIN_BRANCH_NAME).is_err());
+
+        // The same state can be committed again
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
+        assert_eq!(
+            test_utils::list_checkpoints(tmp_db_path, MAIN_BRANCH_NAME)[0].hash,
+            *tip
+        );
+
+        // Commits that other branches build on stay
+        test_utils::new_branch(tmp_db_path, "dev");
+        test_utils::commit(tmp_db_path, "Commit on dev", "data/untitled_3.blend");
+        assert!(undo_latest_commit(tmp_db_path, MAIN_BRANCH_NAME).is_err());
+        assert!(undo_latest_commit(tmp_db_path, "dev").is_ok());
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{
+        commit_command::create_merge_commit,
+        common::{
+            read_blend_blocks, read_current_commit_hash, read_latest_commit_hash_on_branch,
+            resolve_commit_hash, write_blend_blocks,
+        },
+        status_command::ensure_no_uncommitted_changes,
+    },
+    blend::merge::{changed_datablocks, merge_blend_blocks, MergeConflict, MergeError, MergeSide},
+    db::db_ops::{DBError, Persistence, DB},
+};
+
+/// Set on cherry-picked commits to the hash of the commit they were picked from
+pub const CHERRY_PICKED_FROM_KEY: &str = "cherry_picked_from";
+
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub enum CherryPickOutcome {
+    /// A commit with the picked changes was created on the current branch
+    Picked(String),
+    /// The current branch already has the picked changes
+    Empty,
+    /// Nothing was written, these datablocks need a side picked for them
+    Conflicts(Vec<MergeConflict>),
+}
+
+/// Applies the changes `hash` made to its parent onto the tip of the current
+/// branch, writes the result to `file_path` and commits it with the message of
+/// `hash`. If `datablocks` is given, only the changes to those datablocks are
+/// applied. Datablocks changed on the current branch as well are reported as
+/// conflicts unless `resolutions` picks a side for each of them, `Theirs`
+/// being the picked commit. The file cannot have changes that are not
+/// committed, since it is overwritten.
+pub fn cherry_pick(
+    file_path: &str,
+    db_path: &str,
+    hash: &str,
+    datablocks: Option<&[String]>,
+    resolutions: &HashMap<String, MergeSide>,
+) -> Result<CherryPickOutcome, DBError> {
+    let mut conn = Persistence::open(db_path)?;
+
+    let picked_hash = resolve_commit_hash(&conn, hash)?;
+    let picked = conn
+        .read_commit(&picked_hash)?
+        .ok_or(DBError::Error(format!("No such commit: {}", hash)))?;
+
+    if !conn.commit_exists(&picked.prev_commit_hash)? {
+        return Err(DBError::Error(
+            "The first checkpoint of the timeline cannot be cherry-picked".to_owned(),
+        ));
+    }
+
+    let current_branch_name = conn.read_current_branch_name()?;
+    let our_tip = read_latest_commit_hash_on_branch(&conn, &current_branch_name)?;
+    if read_current_commit_hash(&conn)? != our_tip {
+        return Err(DBError::Error(format!(
+            "An older checkpoint of {} is restored, switch to its tip first",
+            current_branch_name
+        )));
+    }
+
+    if conn
+        .read_ancestors_of_commit(&our_tip)?
+        .iter()
+        .any(|commit| commit.hash == picked_hash)
+    {
+        return Err(DBError::Error(format!(
+            "Checkpoint {} is already on {}",
+            picked_hash, current_branch_name
+        )));
+    }
+
+    let base_blocks = read_blend_blocks(&conn, &picked.prev_commit_hash)?;
+    let our_blocks = read_blend_blocks(&conn, &our_tip)?;
+    let their_blocks = read_blend_blocks(&conn, &picked_hash)?;
+
+    // the changes to the other datablocks are left out by keeping our side
+    let mut resolutions = resolutions.clone();
+    if let Some(datablocks) = datablocks {
+        let changed = changed_datablocks(base_blocks.clone(), their_blocks.clone())
+            .map_err(|reason| DBError::Error(format!("Cannot cherry-pick: {}", reason)))?;
+        for name in changed {
+            if !datablocks.contains(&name) {
+                resolutions.entry(name).or_insert(MergeSide::Ours);
+            }
+        }
+    }
+
+    let merged =
+        match merge_blend_blocks(base_blocks, our_blocks.clone(), their_blocks, &resolutions) {
+            Ok(merged) => merged,
+            Err(MergeError::Conflicts(conflicts)) => {
+                return Ok(CherryPickOutcome::Conflicts(conflicts))
+            }
+            Err(MergeError::Unsupported(reason)) => {
+                return Err(DBError::Error(format!("Cannot cherry-pick: {}", reason)))
+            }
+        };
+
+    let is_empty = changed_datablocks(our_blocks, merged.clone())
+        .map_err(|reason| DBError::Error(format!("Cannot cherry-pick: {}", reason)))?
+        .is_empty();
+    if is_empty {
+        return Ok(CherryPickOutcome::Empty);
+    }
+
+    ensure_no_uncommitted_changes(&conn, file_path)?;
+    write_blend_blocks(file_path, &merged)?;
+
+    // committed through the same connection, so the tip cannot move in between
+    create_merge_commit(
+        &mut conn,
+        file_path,
+        picked.message,
+        HashMap::from([(CHERRY_PICKED_FROM_KEY.to_owned(), picked_hash)]),
+        vec![],
+    )
+    .map(CherryPickOutcome::Picked)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tempfile::{NamedTempFile, TempDir};
+
+    use crate::{
+        api::{
+            common::{read_blend_blocks, read_latest_commit_hash_on_branch},
+            init_command::MAIN_BRANCH_NAME,
+            switch_command::switch_branches,
+            test_utils,
+        },
+        blend::merge::changed_datablocks,
+        db::db_ops::{Persistence, DB},
+    };
+
+    use super::{cherry_pick, CherryPickOutcome, CHERRY_PICKED_FROM_KEY};
+
+    #[test]
+    fn test_cherry_pick() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+        let tmp_blend_path = NamedTempFile::new().expect("Cannot create temp file");
+        let tmp_blend_path = tmp_blend_path.path().to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::new_branch(tmp_db_path, "experiment");
+        test_utils::commit(tmp_db_path, "New material", "data/untitled_2.blend");
+        switch_branches(tmp_db_path, MAIN_BRANCH_NAME, tmp_blend_path, false).unwrap();
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        let initial = read_latest_commit_hash_on_branch(&db, MAIN_BRANCH_NAME).unwrap();
+        let experiment = db.read_branch_tip("experiment").unwrap().unwrap();
+        drop(db);
+
+        // Only the material and the world are ported
+        let datablocks = vec!["MAMaterial".to_owned(), "WOWorld".to_owned()];
+        let outcome = cherry_pick(
+            tmp_blend_path,
+            tmp_db_path,
+            &experiment,
+            Some(&datablocks),
+            &HashMap::new(),
+        )
+        .unwrap();
+        let CherryPickOutcome::Picked(picked) = outcome else {
+            panic!("Expected a new commit, got {:?}", outcome);
+        };
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        assert_eq!(
+            read_latest_commit_hash_on_branch(&db, MAIN_BRANCH_NAME).unwrap(),
+            picked
+        );
+        let commit = db.read_commit(&picked).unwrap().unwrap();
+        assert_eq!(commit.message, "New material");
+        assert_eq!(commit.prev_commit_hash, initial);
+        assert!(commit.merge_parents.is_empty());
+        assert!(db
+            .read_commit_metadata(&picked)
+            .unwrap()
+            .iter()
+            .any(|entry| entry.key == CHERRY_PICKED_FROM_KEY && entry.value == experiment));
+
+        let changed = changed_datablocks(
+            read_blend_blocks(&db, &initial).unwrap(),
+            read_blend_blocks(&db, &picked).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(changed, vec!["WOWorld", "MAMaterial"]);
+        drop(db);
+
+        // The rest of the commit
+        let outcome = cherry_pick(
+            tmp_blend_path,
+            tmp_db_path,
+            &experiment,
+            None,
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert!(matches!(outcome, CherryPickOutcome::Picked(_)));
+
+        let outcome = cherry_pick(
+            tmp_blend_path,
+            tmp_db_path,
+            &experiment,
+            None,
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(outcome, CherryPickOutcome::Empty);
+
+        // Commits of the current branch are already there
+        assert!(cherry_pick(tmp_blend_path, tmp_db_path, &picked, None, &HashMap::new()).is_err());
+    }
+}
@@ -1,34 +1,170 @@
 use crate::{
     api::{
-        common::{blend_file_data_from_file, read_latest_commit_hash_on_branch},
+        common::{
+            blend_file_data_from_file, hash_unique_commit, metadata_for_new_commit,
+            read_current_commit_hash, read_latest_commit_hash_on_branch,
+        },
         utils::{block_hash_diff, timestamp},
     },
     db::{
         db_ops::{DBError, Persistence, DB},
-        structs::{hash_list, Commit},
+        structs::{hash_list, BranchInfo, Commit, Intent},
     },
     measure_time,
     printer_parser::printerparser::PrinterParser,
 };
 
-use std::time::Instant;
+use std::{collections::HashMap, time::Instant};
 
+/// Commits the file on top of the current commit.
+///
+/// If an older checkpoint was restored, the current commit is not the tip of
+/// the current branch. The commit then starts a new branch called
+/// `new_branch_name`, or one named after the current branch and commit if it
+/// is not given, and that becomes the current branch. Returns the hash of the
+/// commit.
 pub fn create_new_commit(
     file_path: &str,
     db_path: &str,
     message: Option<String>,
+    metadata: HashMap<String, String>,
+    new_branch_name: Option<String>,
+) -> Result<String, DBError> {
+    create_commit_with_parents(
+        file_path,
+        db_path,
+        message,
+        metadata,
+        vec![],
+        new_branch_name,
+        timestamp(),
+    )
+}
+
+/// Commits the file on top of the current commit as if it was committed at
+/// `date`, like versions of the file saved before it was versioned
+pub(crate) fn create_commit_at(
+    file_path: &str,
+    db_path: &str,
+    message: String,
+    metadata: HashMap<String, String>,
+    date: u64,
 ) -> Result<(), DBError> {
+    create_commit_with_parents(
+        file_path,
+        db_path,
+        Some(message),
+        metadata,
+        vec![],
+        None,
+        date,
+    )
+    .map(|_| ())
+}
+
+/// Creates a commit on top of the current branch that also has
+/// `merge_parents` as its parents, through `conn`, so that the caller can
+/// write the file and commit it without letting other writers in between.
+/// Returns the hash of the commit.
+pub(crate) fn create_merge_commit(
+    conn: &mut Persistence,
+    file_path: &str,
+    message: String,
+    metadata: HashMap<String, String>,
+    merge_parents: Vec<String>,
+) -> Result<String, DBError> {
+    commit_file(
+        conn,
+        file_path,
+        Some(message),
+        metadata,
+        merge_parents,
+        None,
+        timestamp(),
+    )
+}
+
+/// Names the branch for a commit made on top of `hash`, an older commit of
+/// `branch_name`, like `main-1a2b3c4d`
+fn detached_branch_name(
+    conn: &Persistence,
+    branch_name: &str,
+    hash: &str,
+) -> Result<String, DBError> {
+    let short_hash: String = hash.chars().take(8).collect();
+    let base_name = format!("{}-{}", branch_name, short_hash);
+
+    let mut name = base_name.clone();
+    let mut attempt = 1;
+    while conn.read_branch_tip(&name)?.is_some() {
+        attempt += 1;
+        name = format!("{}-{}", base_name, attempt);
+    }
+
+    Ok(name)
+}
+
+fn create_commit_with_parents(
+    file_path: &str,
+    db_path: &str,
+    message: Option<String>,
+    metadata: HashMap<String, String>,
+    merge_parents: Vec<String>,
+    new_branch_name: Option<String>,
+    date: u64,
+) -> Result<String, DBError> {
     let mut conn = Persistence::open(db_path)?;
+    commit_file(
+        &mut conn,
+        file_path,
+        message,
+        metadata,
+        merge_parents,
+        new_branch_name,
+        date,
+    )
+}
 
+fn commit_file(
+    conn: &mut Persistence,
+    file_path: &str,
+    message: Option<String>,
+    metadata: HashMap<String, String>,
+    merge_parents: Vec<String>,
+    new_branch_name: Option<String>,
+    date: u64,
+) -> Result<String, DBError> {
     let start_commit_command = Instant::now();
-    let blend_data = blend_file_data_from_file(file_path)
+    let hash_algorithm = conn.read_hash_algorithm()?;
+    let blend_data = blend_file_data_from_file(file_path, hash_algorithm)
         .map_err(|e| DBError::Error(format!("Error parsing blend file: {}", e)))?;
 
-    println!("Hash: {}", &blend_data.hash);
-
     let current_branch_name = conn.read_current_branch_name()?;
 
-    let latest_commit_hash = read_latest_commit_hash_on_branch(&conn, &current_branch_name)?;
+    let latest_commit_hash = read_current_commit_hash(conn)?;
+
+    // Committing on top of an older commit starts a new branch
+    let new_branch =
+        if read_latest_commit_hash_on_branch(conn, &current_branch_name)? != latest_commit_hash {
+            let name = match new_branch_name {
+                Some(name) if conn.read_branch_tip(&name)?.is_some() => {
+                    return Err(DBError::Error(format!("Branch {} already exists", name)))
+                }
+                Some(name) => name,
+                None => detached_branch_name(conn, &current_branch_name, &latest_commit_hash)?,
+            };
+            Some(BranchInfo {
+                name,
+                base: latest_commit_hash.clone(),
+                created: timestamp(),
+                description: Some(format!(
+                    "Committed on top of an older checkpoint of {}",
+                    current_branch_name
+                )),
+            })
+        } else {
+            None
+        };
 
     let latest_commit = conn.read_commit(&latest_commit_hash).ok().flatten();
 
@@ -40,45 +176,84 @@ pub fn create_new_commit(
         }
     };
 
+    let project_id = conn.read_project_id()?;
+
+    let name = conn.read_name()?.unwrap_or("Anon".to_owned());
+
+    let mut commit = Commit {
+        hash: String::new(),
+        snapshot: blend_data.snapshot,
+        prev_commit_hash: latest_commit_hash,
+        merge_parents,
+        project_id,
+        branch: new_branch
+            .as_ref()
+            .map_or(current_branch_name, |info| info.name.clone()),
+        message: message.unwrap_or_default(),
+        author: name,
+        date,
+        header: blend_data.header_bytes,
+        blocks: blend_data.blocks,
+    };
+    let metadata = hash_unique_commit(
+        conn,
+        &mut commit,
+        metadata_for_new_commit(&blend_data.blender_version, metadata),
+        hash_algorithm,
+    )?;
+
+    println!("Hash: {}", &commit.hash);
+    let hash = commit.hash.clone();
+
+    let block_hashes: Vec<String> = blocks_from_latest.iter().map(|b| b.hash.clone()).collect();
+    let intent = Intent::new(
+        "commit",
+        vec![commit.hash.clone()],
+        conn.read_missing_block_hashes(&block_hashes)?,
+    );
+
+    conn.execute_in_transaction(|tx| Persistence::write_intent(tx, &intent))?;
+
     measure_time!(format!("Writing blocks {:?}", file_path), {
         conn.write_blocks(&blocks_from_latest[..])?
     });
 
-    let project_id = conn.read_project_id()?;
-
-    let name = conn.read_name()?.unwrap_or("Anon".to_owned());
+    conn.write_blocks_str(&commit.hash, &commit.blocks)?;
 
-    conn.write_blocks_str(&blend_data.hash, &blend_data.blocks)?;
+    conn.sync_blocks()?;
 
     conn.execute_in_transaction(|tx| {
-        Persistence::write_branch_tip(tx, &current_branch_name, &blend_data.hash)?;
-
-        let commit = Commit {
-            hash: blend_data.hash,
-            prev_commit_hash: latest_commit_hash,
-            project_id,
-            branch: current_branch_name,
-            message: message.unwrap_or_default(),
-            author: name,
-            date: timestamp(),
-            header: blend_data.header_bytes,
-            blocks: blend_data.blocks,
-        };
+        if let Some(info) = &new_branch {
+            Persistence::write_remote_branch_tip(tx, &info.name, &info.base)?;
+            Persistence::write_branch_info(tx, info)?;
+            Persistence::write_current_branch_name(tx, &info.name, "commit")?;
+        }
 
-        Persistence::write_commit(tx, commit)
+        Persistence::write_branch_tip(tx, &commit.branch, &commit.hash, "commit")?;
+        Persistence::write_current_commit_pointer(tx, &commit.hash, "commit")?;
+
+        for entry in metadata.iter() {
+            Persistence::write_commit_metadata(tx, entry)?;
+        }
+
+        Persistence::write_commit(tx, commit)?;
+        Persistence::delete_intent(tx, &intent.id)
     })?;
 
     println!("Committing took {:?}", start_commit_command.elapsed());
-    Ok(())
+    Ok(hash)
 }
 
 #[cfg(test)]
 mod test {
+    use std::collections::HashMap;
+
     use tempfile::TempDir;
 
     use crate::{
         api::{
-            common::read_latest_commit_hash_on_branch, init_command::MAIN_BRANCH_NAME, test_utils,
+            common::read_latest_commit_hash_on_branch, init_command::MAIN_BRANCH_NAME,
+            restore_command::restore_checkpoint, test_utils,
         },
         db::db_ops::{Persistence, DB},
     };
@@ -102,6 +277,8 @@ mod test {
             "data/untitled_2.blend",
             tmp_path,
             Some("Initial checkpoint".to_owned()),
+            HashMap::from([("ticket".to_owned(), "PRJ-12".to_owned())]),
+            None,
         )
         .unwrap();
 
@@ -114,7 +291,7 @@ mod test {
         let db = Persistence::open(tmp_path).expect("Cannot open test DB");
 
         let commit = db
-            .read_commit("b637ec695e10bed0ce06279d1dc46717")
+            .read_commit("64a48d7f102886bb63c549e8d1b965e9b57cce74be005bc6d66d3e4df6fd3ed7")
             .unwrap()
             .unwrap();
 
@@ -123,9 +300,19 @@ mod test {
         // commit.header omitted, not interesting enough
         assert_eq!(commit.author, "Anon");
         assert_eq!(commit.branch, MAIN_BRANCH_NAME);
-        assert_eq!(commit.hash, "b637ec695e10bed0ce06279d1dc46717");
+        assert_eq!(
+            commit.hash,
+            "64a48d7f102886bb63c549e8d1b965e9b57cce74be005bc6d66d3e4df6fd3ed7"
+        );
+        assert_eq!(
+            commit.snapshot,
+            "2db2d69f659354ca6f82fef2d94a2338f089d5d2f9c107b4cd437eb59211d272"
+        );
         assert_eq!(commit.message, "Initial checkpoint");
-        assert_eq!(commit.prev_commit_hash, "a5f92d0a988085ed66c9dcdccc7b9c90");
+        assert_eq!(
+            commit.prev_commit_hash,
+            "35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb"
+        );
         assert_eq!(commit.project_id, "my-cool-project");
 
         let current_branch_name = db
@@ -139,11 +326,35 @@ mod test {
             .expect("Cannot read latest commit");
 
         // The latest commit hash is updated to the hash of the new commit
-        assert_eq!(latest_commit_hash, "b637ec695e10bed0ce06279d1dc46717");
+        assert_eq!(
+            latest_commit_hash,
+            "64a48d7f102886bb63c549e8d1b965e9b57cce74be005bc6d66d3e4df6fd3ed7"
+        );
 
         // The tip of `main` is updated to the hash of the new commit
         let main_tip = db.read_branch_tip(MAIN_BRANCH_NAME).unwrap().unwrap();
-        assert_eq!(main_tip, "b637ec695e10bed0ce06279d1dc46717");
+        assert_eq!(
+            main_tip,
+            "64a48d7f102886bb63c549e8d1b965e9b57cce74be005bc6d66d3e4df6fd3ed7"
+        );
+        // Nothing is left to roll back
+        assert_eq!(db.read_intents().unwrap(), vec![]);
+
+        let metadata: Vec<(String, String)> = db
+            .read_commit_metadata(
+                "64a48d7f102886bb63c549e8d1b965e9b57cce74be005bc6d66d3e4df6fd3ed7",
+            )
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.key, entry.value))
+            .collect();
+        assert_eq!(
+            metadata,
+            vec![
+                ("blender_version".to_owned(), "3.3".to_owned()),
+                ("ticket".to_owned(), "PRJ-12".to_owned())
+            ]
+        );
     }
 
     #[test]
@@ -157,12 +368,16 @@ mod test {
             "data/untitled_2.blend",
             tmp_path,
             Some("Message".to_owned()),
+            HashMap::new(),
+            None,
         )
         .unwrap();
         create_new_commit(
             "data/untitled_3.blend",
             tmp_path,
             Some("Message".to_owned()),
+            HashMap::new(),
+            None,
         )
         .unwrap();
 
@@ -184,10 +399,168 @@ mod test {
             .expect("Cannot read latest commit");
 
         // The latest commit hash is updated to the hash of the new commit
-        assert_eq!(latest_commit_hash, "d9e8eb09f8270ad5326de946d951433a");
+        assert_eq!(
+            latest_commit_hash,
+            "24a9b858a509e42de9114d1963bfabc82d01e3bc024d421c60dd3a6f2e808c2a"
+        );
 
         // The tip of `main` is updated to the hash of the new commit
         let main_tip = db.read_branch_tip(MAIN_BRANCH_NAME).unwrap().unwrap();
-        assert_eq!(main_tip, "d9e8eb09f8270ad5326de946d951433a");
+        assert_eq!(
+            main_tip,
+            "24a9b858a509e42de9114d1963bfabc82d01e3bc024d421c60dd3a6f2e808c2a"
+        );
+    }
+
+    #[test]
+    fn test_commit_same_contents_again() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        test_utils::init_db_from_file(tmp_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_path, "Change", "data/untitled_2.blend");
+        test_utils::commit(tmp_path, "Save again", "data/untitled_2.blend");
+        test_utils::new_branch(tmp_path, "dev");
+        test_utils::commit(tmp_path, "Save on dev", "data/untitled_2.blend");
+
+        let checkpoints = test_utils::list_checkpoints(tmp_path, "dev");
+        assert_eq!(checkpoints.len(), 4);
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        let commits: Vec<_> = checkpoints[..3]
+            .iter()
+            .map(|checkpoint| db.read_commit(&checkpoint.hash).unwrap().unwrap())
+            .collect();
+
+        // Every commit has its own hash, but they share the snapshot
+        assert_ne!(commits[0].hash, commits[1].hash);
+        assert_ne!(commits[1].hash, commits[2].hash);
+        assert!(commits
+            .iter()
+            .all(|commit| commit.snapshot == commits[0].snapshot));
+    }
+
+    #[test]
+    fn test_commit_same_contents_on_same_parent() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+        let tmp_blend = tmp_dir.path().join("restored.blend");
+        let tmp_blend = tmp_blend.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_path, "Change", "data/untitled_2.blend");
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        let main_tip = db.read_branch_tip(MAIN_BRANCH_NAME).unwrap().unwrap();
+        let initial_commit = db.read_commit(&main_tip).unwrap().unwrap().prev_commit_hash;
+        drop(db);
+
+        // The same file with the same message on top of the same commit, twice
+        for branch_name in ["again", "once more"] {
+            restore_checkpoint(tmp_blend, tmp_path, &initial_commit, false).unwrap();
+            create_new_commit(
+                "data/untitled_2.blend",
+                tmp_path,
+                Some("Change".to_owned()),
+                HashMap::new(),
+                Some(branch_name.to_owned()),
+            )
+            .unwrap();
+        }
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        let again_tip = db.read_branch_tip("again").unwrap().unwrap();
+        let once_more_tip = db.read_branch_tip("once more").unwrap().unwrap();
+        assert_ne!(again_tip, main_tip);
+        assert_ne!(once_more_tip, main_tip);
+        assert_ne!(again_tip, once_more_tip);
+
+        for tip in [&main_tip, &again_tip, &once_more_tip] {
+            let commit = db.read_commit(tip).unwrap().unwrap();
+            assert_eq!(commit.prev_commit_hash, initial_commit);
+        }
+    }
+
+    #[test]
+    fn test_commit_on_older_checkpoint() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+        let tmp_blend = tmp_dir.path().join("restored.blend");
+        let tmp_blend = tmp_blend.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_path, "Commit", "data/untitled_2.blend");
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        let main_tip = db.read_branch_tip(MAIN_BRANCH_NAME).unwrap().unwrap();
+        let initial_commit = db.read_commit(&main_tip).unwrap().unwrap().prev_commit_hash;
+        drop(db);
+
+        restore_checkpoint(tmp_blend, tmp_path, &initial_commit, false).unwrap();
+        create_new_commit(
+            "data/untitled_3.blend",
+            tmp_path,
+            Some("Commit on the old one".to_owned()),
+            HashMap::new(),
+            None,
+        )
+        .unwrap();
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        let branch_name = format!("{}-{}", MAIN_BRANCH_NAME, &initial_commit[..8]);
+        assert_eq!(db.read_current_branch_name().unwrap(), branch_name);
+        assert_eq!(
+            db.read_branch_tip(MAIN_BRANCH_NAME).unwrap().unwrap(),
+            main_tip
+        );
+
+        let new_tip = read_latest_commit_hash_on_branch(&db, &branch_name).unwrap();
+        let commit = db.read_commit(&new_tip).unwrap().unwrap();
+        assert_eq!(commit.prev_commit_hash, initial_commit);
+        assert_eq!(commit.branch, branch_name);
+        assert_eq!(db.read_current_commit_pointer().unwrap(), new_tip);
+    }
+
+    #[test]
+    fn test_commit_on_older_checkpoint_with_branch_name() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+        let tmp_blend = tmp_dir.path().join("restored.blend");
+        let tmp_blend = tmp_blend.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_path, "Commit", "data/untitled_2.blend");
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        let main_tip = db.read_branch_tip(MAIN_BRANCH_NAME).unwrap().unwrap();
+        let initial_commit = db.read_commit(&main_tip).unwrap().unwrap().prev_commit_hash;
+        drop(db);
+
+        restore_checkpoint(tmp_blend, tmp_path, &initial_commit, false).unwrap();
+
+        // The branch to start must not exist yet
+        let result = create_new_commit(
+            "data/untitled_3.blend",
+            tmp_path,
+            None,
+            HashMap::new(),
+            Some(MAIN_BRANCH_NAME.to_owned()),
+        );
+        assert!(result.is_err());
+
+        create_new_commit(
+            "data/untitled_3.blend",
+            tmp_path,
+            None,
+            HashMap::new(),
+            Some("alternative".to_owned()),
+        )
+        .unwrap();
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        assert_eq!(db.read_current_branch_name().unwrap(), "alternative");
+        let checkpoints = test_utils::list_checkpoints(tmp_path, "alternative");
+        assert_eq!(checkpoints.len(), 2);
+        assert_eq!(checkpoints[1].hash, initial_commit);
     }
 }
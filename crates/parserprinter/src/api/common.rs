@@ -1,21 +1,31 @@
-use flate2::{write::GzEncoder, Compression};
+use flate2::{
+    write::{GzDecoder, GzEncoder},
+    Compression,
+};
 use rayon::prelude::*;
 
 use crate::{
     blend::{
         blend_file::{Endianness, PointerSize},
+        merge::BlendBlocks,
         parsers::{blend, block, header as pheader, BlendFileParseState},
-        utils::from_file,
+        utils::{from_file, to_file_transactional},
     },
     db::{
         db_ops::{DBError, Persistence, DB},
-        structs::{hash_list, BlockRecord},
+        structs::{hash_list, BlockRecord, Commit, CommitMetadata, HashAlgorithm, Intent},
     },
     measure_time,
     printer_parser::printerparser::PrinterParser,
 };
 
-use std::io::Write;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Write,
+    iter,
+};
+
+use super::metadata_command::{BLENDER_VERSION_KEY, NONCE_KEY};
 
 pub fn read_latest_commit_hash_on_branch(
     conn: &Persistence,
@@ -25,8 +35,222 @@ pub fn read_latest_commit_hash_on_branch(
         .and_then(|tip| tip.ok_or(DBError::Error("Branch tip does not exist".to_owned())))
 }
 
+/// The commit the file being versioned was last committed as or restored
+/// from. Timelines that never recorded it are at the tip of the current branch.
+pub fn read_current_commit_hash(conn: &Persistence) -> Result<String, DBError> {
+    match conn.read_current_commit_pointer() {
+        Err(DBError::Consistency(_)) => {
+            read_latest_commit_hash_on_branch(conn, &conn.read_current_branch_name()?)
+        }
+        result => result,
+    }
+}
+
+/// Returns the current hash of a commit referred to by a tag, a hash, or an
+/// alias left behind by a hash migration or an amend. Anything else is
+/// returned as it is.
+pub fn resolve_commit_hash(conn: &Persistence, hash: &str) -> Result<String, DBError> {
+    if let Some(tagged) = conn.read_tag(hash)? {
+        return Ok(tagged);
+    }
+
+    // an amended commit can be made again, eg. after undoing the amend
+    if conn.commit_exists(hash)? {
+        return Ok(hash.to_owned());
+    }
+
+    conn.read_hash_alias(hash)
+        .map(|alias| alias.unwrap_or(hash.to_owned()))
+}
+
+/// The metadata a new commit is created with. Entries in `metadata` take
+/// precedence over the ones filled in automatically.
+pub fn metadata_for_new_commit(
+    blender_version: &str,
+    metadata: HashMap<String, String>,
+) -> BTreeMap<String, String> {
+    let mut all_metadata: BTreeMap<String, String> = BTreeMap::new();
+    all_metadata.insert(BLENDER_VERSION_KEY.to_owned(), blender_version.to_owned());
+    all_metadata.extend(metadata);
+    all_metadata
+}
+
+/// Fills in the hash of a commit that is about to be written, and returns the
+/// metadata entries to write along with it
+pub fn hash_new_commit(
+    commit: &mut Commit,
+    metadata: BTreeMap<String, String>,
+    hash_algorithm: HashAlgorithm,
+) -> Vec<CommitMetadata> {
+    commit.hash = commit.compute_hash(&metadata, hash_algorithm);
+
+    metadata
+        .into_iter()
+        .map(|(key, value)| CommitMetadata {
+            hash: commit.hash.clone(),
+            key,
+            value,
+            hashed: true,
+        })
+        .collect()
+}
+
+/// Like `hash_new_commit`, but gives the commit a random nonce when it would
+/// get the hash of a commit that already exists
+pub fn hash_unique_commit(
+    conn: &Persistence,
+    commit: &mut Commit,
+    mut metadata: BTreeMap<String, String>,
+    hash_algorithm: HashAlgorithm,
+) -> Result<Vec<CommitMetadata>, DBError> {
+    loop {
+        let entries = hash_new_commit(commit, metadata.clone(), hash_algorithm);
+        if !conn.commit_exists(&commit.hash)? {
+            return Ok(entries);
+        }
+        metadata.insert(NONCE_KEY.to_owned(), uuid::Uuid::new_v4().to_string());
+    }
+}
+
+/// The metadata entries covered by the hash of a commit
+pub fn hashed_metadata(metadata: &[CommitMetadata]) -> BTreeMap<String, String> {
+    metadata
+        .iter()
+        .filter(|entry| entry.hashed)
+        .map(|entry| (entry.key.clone(), entry.value.clone()))
+        .collect()
+}
+
+/// Gives new hashes to `commits` after the history they are built on was
+/// changed. Parents that are keys of `renamed` are replaced with the value,
+/// and every rehashed commit is added to it, so parents are rehashed before
+/// their descendants. Returns the rehashed commits with their old hashes, and
+/// the metadata to write along with them.
+pub fn rehash_commits(
+    conn: &Persistence,
+    commits: Vec<Commit>,
+    renamed: &mut HashMap<String, String>,
+    hash_algorithm: HashAlgorithm,
+) -> Result<(Vec<(String, Commit)>, Vec<CommitMetadata>), DBError> {
+    let waiting_hashes: HashSet<String> = commits.iter().map(|c| c.hash.clone()).collect();
+    let mut waiting = commits;
+    let mut rehashed: Vec<(String, Commit)> = vec![];
+    let mut metadata: Vec<CommitMetadata> = vec![];
+
+    while !waiting.is_empty() {
+        let (ready, rest): (Vec<Commit>, Vec<Commit>) = waiting.into_iter().partition(|commit| {
+            iter::once(&commit.prev_commit_hash)
+                .chain(commit.merge_parents.iter())
+                .all(|parent| !waiting_hashes.contains(parent) || renamed.contains_key(parent))
+        });
+
+        if ready.is_empty() {
+            return Err(DBError::Consistency(
+                "The history of the commits has a cycle".to_owned(),
+            ));
+        }
+
+        for commit in ready {
+            let rename = |hash: &String| renamed.get(hash).unwrap_or(hash).clone();
+            let old_hash = commit.hash.clone();
+            let old_metadata = conn.read_commit_metadata(&old_hash)?;
+            let mut descendant = Commit {
+                prev_commit_hash: rename(&commit.prev_commit_hash),
+                merge_parents: commit.merge_parents.iter().map(rename).collect(),
+                ..commit
+            };
+            descendant.hash =
+                descendant.compute_hash(&hashed_metadata(&old_metadata), hash_algorithm);
+            metadata.extend(old_metadata.into_iter().map(|entry| CommitMetadata {
+                hash: descendant.hash.clone(),
+                ..entry
+            }));
+
+            renamed.insert(old_hash.clone(), descendant.hash.clone());
+            rehashed.push((old_hash, descendant));
+        }
+
+        waiting = rest;
+    }
+
+    Ok((rehashed, metadata))
+}
+
+/// Commits that replace others in the history, see `rewrite_history`
+pub struct HistoryRewrite {
+    /// Deleted with their metadata, but not their blocks
+    pub removed: Vec<String>,
+    pub written: Vec<Commit>,
+    pub metadata: Vec<CommitMetadata>,
+    /// Everything that refers to the first hash is moved to the second one,
+    /// and the first one is kept as an alias
+    pub renamed: Vec<(String, String)>,
+}
+
+/// Writes `rewrite` and moves the tip of `branch_name` to `new_tip`, recording
+/// it in the reflog under `operation`
+pub fn rewrite_history(
+    conn: &mut Persistence,
+    operation: &str,
+    branch_name: &str,
+    new_tip: &str,
+    rewrite: HistoryRewrite,
+) -> Result<(), DBError> {
+    let intent = Intent::new(
+        operation,
+        rewrite.written.iter().map(|c| c.hash.clone()).collect(),
+        vec![],
+    );
+
+    conn.execute_in_transaction(|tx| Persistence::write_intent(tx, &intent))?;
+
+    for commit in rewrite.written.iter() {
+        conn.write_blocks_str(&commit.hash, &commit.blocks)?;
+    }
+    conn.sync_blocks()?;
+
+    conn.execute_in_transaction(|tx| {
+        Persistence::write_branch_tip(tx, branch_name, new_tip, operation)?;
+
+        for hash in rewrite.removed.iter() {
+            Persistence::delete_commit(tx, hash)?;
+        }
+        for commit in rewrite.written.into_iter() {
+            Persistence::write_commit(tx, commit)?;
+        }
+        for entry in rewrite.metadata.iter() {
+            Persistence::write_commit_metadata(tx, entry)?;
+        }
+        for (old_hash, new_hash) in rewrite.renamed.iter() {
+            Persistence::rename_commit_hash(tx, old_hash, new_hash)?;
+        }
+
+        Persistence::delete_intent(tx, &intent.id)
+    })?;
+
+    for hash in rewrite.removed.iter() {
+        conn.delete_blocks_str(hash)?;
+    }
+
+    Ok(())
+}
+
+/// Formats the version in the file header, eg. `306` becomes `3.6`
+fn format_blender_version(version: [u8; 3]) -> String {
+    let digits = String::from_utf8_lossy(&version).into_owned();
+    match (
+        digits.get(..1),
+        digits.get(1..).map(|minor| minor.parse::<u32>()),
+    ) {
+        (Some(major), Some(Ok(minor))) => format!("{}.{}", major, minor),
+        _ => digits,
+    }
+}
+
 pub struct BlendFileDataForCheckpoint {
-    pub hash: String,
+    /// The hash of `blocks`
+    pub snapshot: String,
+    pub blender_version: String,
     pub header_bytes: Vec<u8>,
     pub blocks: String,
     pub block_data: Vec<BlockRecord>,
@@ -34,6 +258,7 @@ pub struct BlendFileDataForCheckpoint {
 
 pub fn blend_file_data_from_file(
     path_to_blend: &str,
+    hash_algorithm: HashAlgorithm,
 ) -> Result<BlendFileDataForCheckpoint, String> {
     let blend_bytes = measure_time!(format!("Reading {:?}", path_to_blend), {
         from_file(path_to_blend).map_err(|_| "Cannot unpack blend file".to_owned())
@@ -46,8 +271,10 @@ pub fn blend_file_data_from_file(
     };
 
     let (_, (header, blocks)) = measure_time!(format!("Parsing blocks {:?}", path_to_blend), {
-        blend().read(&blend_bytes, &mut parse_state).unwrap()
-    });
+        blend()
+            .read(&blend_bytes, &mut parse_state)
+            .map_err(|e| format!("Cannot parse blend file: {:?}", e))
+    })?;
 
     println!("Number of blocks: {:?}", blocks.len());
 
@@ -61,7 +288,7 @@ pub fn blend_file_data_from_file(
                         .write(parsed_block, &mut state)
                         .map_err(|e| format!("Cannot write block: {:?}", e))?;
 
-                    let hash = md5::compute(&block_blob);
+                    let hash = hash_algorithm.hash(&block_blob);
 
                     let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
                     encoder
@@ -72,7 +299,7 @@ pub fn blend_file_data_from_file(
                         .map_err(|e| format!("Cannot encode: {:?}", e))?;
 
                     Ok(BlockRecord {
-                        hash: format!("{:x}", hash),
+                        hash,
                         data: compressed,
                     })
                 })
@@ -92,14 +319,99 @@ pub fn blend_file_data_from_file(
         hash_list().print(&block_hashes, &mut ()).unwrap()
     });
 
-    let blend_hash = measure_time!(format!("Hashing {:?}", path_to_blend), {
-        md5::compute(&blocks_str)
+    let snapshot = measure_time!(format!("Hashing {:?}", path_to_blend), {
+        hash_algorithm.hash(blocks_str.as_bytes())
     });
 
     Ok(BlendFileDataForCheckpoint {
-        hash: format!("{:x}", blend_hash),
+        snapshot,
+        blender_version: format_blender_version(header.version),
         header_bytes: header_data,
         blocks: blocks_str,
         block_data: block_records,
     })
 }
+
+/// Reads a blend file as parsed blocks.
+pub fn read_blend_file(path: &str) -> Result<BlendBlocks, DBError> {
+    let blend_bytes =
+        from_file(path).map_err(|_| DBError::Error("Cannot unpack blend file".to_owned()))?;
+
+    let mut parse_state = BlendFileParseState {
+        pointer_size: PointerSize::Bits32,
+        endianness: Endianness::Little,
+        current_block_size: 0,
+    };
+
+    blend()
+        .read(&blend_bytes, &mut parse_state)
+        .map(|(_, blend_blocks)| blend_blocks)
+        .map_err(|_| DBError::Error("Cannot parse blend file".to_owned()))
+}
+
+/// Reads the file stored in a commit as parsed blocks.
+pub fn read_blend_blocks(conn: &Persistence, hash: &str) -> Result<BlendBlocks, DBError> {
+    let commit = conn
+        .read_commit(hash)?
+        .ok_or(DBError::Consistency(format!("No such commit: {}", hash)))?;
+
+    let block_hashes = hash_list()
+        .parse(&commit.blocks, &mut ())
+        .map_err(|_| DBError::Fundamental("Cannot parse blocks".to_owned()))?
+        .1;
+
+    let mut parse_state = BlendFileParseState {
+        pointer_size: PointerSize::Bits32,
+        endianness: Endianness::Little,
+        current_block_size: 0,
+    };
+
+    let (_, header) = pheader()
+        .read(&commit.header, &mut parse_state)
+        .map_err(|_| DBError::Fundamental("Cannot parse header".to_owned()))?;
+
+    let blocks = conn
+        .read_blocks(block_hashes)?
+        .par_iter()
+        .map(|record| {
+            let mut deflater = GzDecoder::new(Vec::new());
+            deflater
+                .write_all(&record.data)
+                .and_then(|_| deflater.finish())
+                .map_err(|_| DBError::Fundamental("Cannot decompress block".to_owned()))
+                .and_then(|block_data| {
+                    block()
+                        .read(&block_data, &mut parse_state.clone())
+                        .map(|(_, parsed)| parsed)
+                        .map_err(|_| DBError::Fundamental("Cannot parse block".to_owned()))
+                })
+        })
+        .collect::<Result<Vec<_>, DBError>>()?;
+
+    Ok((header, blocks))
+}
+
+/// Writes parsed blocks to a blend file, replacing it if it exists.
+pub fn write_blend_blocks(path: &str, (header, blocks): &BlendBlocks) -> Result<(), DBError> {
+    let mut parse_state = BlendFileParseState {
+        pointer_size: header.pointer_size,
+        endianness: header.endianness,
+        current_block_size: 0,
+    };
+
+    let header_data = pheader()
+        .write(header, &mut parse_state)
+        .map_err(|_| DBError::Fundamental("Cannot write header".to_owned()))?;
+
+    let block_data = blocks
+        .iter()
+        .map(|parsed_block| {
+            block()
+                .write(parsed_block, &mut parse_state)
+                .map_err(|e| DBError::Fundamental(format!("Cannot write block: {:?}", e)))
+        })
+        .collect::<Result<Vec<Vec<u8>>, DBError>>()?;
+
+    to_file_transactional(path, header_data, block_data, b"ENDB".to_vec())
+        .map_err(|_| DBError::Fundamental("Cannot write to file".to_owned()))
+}
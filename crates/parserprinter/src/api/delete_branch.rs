@@ -1,7 +1,16 @@
-use crate::db::db_ops::{DBError, Persistence, DB};
+use std::collections::HashSet;
 
-use super::init_command::MAIN_BRANCH_NAME;
+use crate::db::{
+    db_ops::{DBError, Persistence, DB},
+    structs::HistoryQuery,
+};
 
+use super::{common::read_current_commit_hash, init_command::MAIN_BRANCH_NAME};
+
+/// Deletes the branch and the commits that were made on it. Commits that are
+/// still reachable from another branch, its base or remote tip, a tag or the
+/// current commit are kept, like the commits another branch was started from
+/// or merged from.
 pub fn delete_branch(db_path: &str, branch_name: &str) -> Result<(), DBError> {
     let mut db = Persistence::open(db_path)?;
     let current_branch_name = db.read_current_branch_name()?;
@@ -20,7 +29,161 @@ pub fn delete_branch(db_path: &str, branch_name: &str) -> Result<(), DBError> {
         return Err(DBError::Error("Cannot delete non-existent branch".to_owned()));
     }
 
-    db.execute_in_transaction(|tx| Persistence::delete_branch_with_commits(tx, branch_name))?;
+    let reachable = reachable_without_branch(&db, branch_name)?;
+    let deleted: Vec<String> = db
+        .read_history(&HistoryQuery {
+            branch: Some(branch_name.to_owned()),
+            ..Default::default()
+        })?
+        .into_iter()
+        .map(|commit| commit.hash)
+        .filter(|hash| !reachable.contains(hash))
+        .collect();
+
+    db.execute_in_transaction(|tx| {
+        Persistence::delete_branch_with_commits(tx, branch_name, &deleted)
+    })?;
+
+    for hash in deleted.iter() {
+        db.delete_blocks_str(hash)?;
+    }
 
     Ok(())
 }
+
+/// The commits that something other than `branch_name` refers to, along with
+/// all of their ancestors
+fn reachable_without_branch(
+    db: &Persistence,
+    branch_name: &str,
+) -> Result<HashSet<String>, DBError> {
+    let mut roots: Vec<String> = vec![read_current_commit_hash(db)?];
+    roots.extend(db.read_all_tags()?.into_iter().map(|tag| tag.hash));
+    for other_branch in db.read_all_branches()? {
+        if other_branch == branch_name {
+            continue;
+        }
+        roots.extend(db.read_branch_tip(&other_branch)?);
+        roots.extend(db.read_remote_branch_tip(&other_branch).ok());
+        roots.extend(db.read_branch_info(&other_branch)?.map(|info| info.base));
+    }
+
+    let mut reachable: HashSet<String> = HashSet::new();
+    for root in roots {
+        if reachable.contains(&root) {
+            continue;
+        }
+        reachable.extend(
+            db.read_ancestors_of_commit(&root)?
+                .into_iter()
+                .map(|commit| commit.hash),
+        );
+    }
+
+    Ok(reachable)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tempfile::TempDir;
+
+    use crate::{
+        api::{
+            init_command::MAIN_BRANCH_NAME,
+            merge_command::{merge_branch, MergeOutcome},
+            new_branch_command::create_new_branch,
+            switch_command::switch_branches,
+            test_utils,
+        },
+        blend::merge::MergeSide,
+        db::db_ops::{Persistence, DB},
+    };
+
+    use super::delete_branch;
+
+    #[test]
+    fn test_delete_branch_keeps_commits_of_other_branches() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+        let tmp_blend = tmp_dir.path().join("file.blend");
+        let tmp_blend = tmp_blend.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_path, "my-cool-project", "data/untitled.blend");
+        test_utils::new_branch(tmp_path, "dev");
+        test_utils::commit(tmp_path, "Dev 1", "data/untitled_2.blend");
+        test_utils::commit(tmp_path, "Dev 2", "data/untitled_3.blend");
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        let dev_2 = db.read_branch_tip("dev").unwrap().unwrap();
+        let dev_1 = db.read_commit(&dev_2).unwrap().unwrap().prev_commit_hash;
+        drop(db);
+
+        // `feature` starts from a commit of `dev`
+        create_new_branch(
+            tmp_path,
+            "feature",
+            Some(dev_1.as_str()),
+            None,
+            Some(tmp_blend),
+            false,
+        )
+        .unwrap();
+        switch_branches(tmp_path, MAIN_BRANCH_NAME, tmp_blend, false).unwrap();
+
+        delete_branch(tmp_path, "dev").unwrap();
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        assert_eq!(db.read_branch_tip("dev").unwrap(), None);
+        assert!(db.read_commit(&dev_1).unwrap().is_some());
+        assert!(!db.commit_exists(&dev_2).unwrap());
+        assert_eq!(db.read_branch_info("feature").unwrap().unwrap().base, dev_1);
+        drop(db);
+
+        let checkpoints = test_utils::list_checkpoints(tmp_path, "feature");
+        assert_eq!(checkpoints.len(), 2);
+        assert_eq!(checkpoints[0].hash, dev_1);
+    }
+
+    #[test]
+    fn test_delete_merged_branch() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+        let tmp_blend = tmp_dir.path().join("file.blend");
+        let tmp_blend = tmp_blend.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_path, "my-cool-project", "data/untitled.blend");
+        test_utils::new_branch(tmp_path, "feature");
+        test_utils::commit(tmp_path, "Add a torus", "data/untitled_3.blend");
+        switch_branches(tmp_path, MAIN_BRANCH_NAME, tmp_blend, false).unwrap();
+        std::fs::copy("data/untitled_2.blend", tmp_blend).unwrap();
+        test_utils::commit(tmp_path, "Change the cube", tmp_blend);
+
+        let resolutions = HashMap::from([
+            ("SCScene".to_owned(), MergeSide::Ours),
+            ("OBCube".to_owned(), MergeSide::Theirs),
+            ("MECube".to_owned(), MergeSide::Theirs),
+        ]);
+        let outcome = merge_branch(tmp_blend, tmp_path, "feature", &resolutions).unwrap();
+        assert_eq!(outcome, MergeOutcome::Merged);
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        let feature_tip = db.read_branch_tip("feature").unwrap().unwrap();
+        drop(db);
+
+        delete_branch(tmp_path, "feature").unwrap();
+
+        // The merged commits stay in the history of `main`
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        let main_tip = db.read_branch_tip(MAIN_BRANCH_NAME).unwrap().unwrap();
+        let merge_commit = db.read_commit(&main_tip).unwrap().unwrap();
+        assert_eq!(merge_commit.merge_parents, vec![feature_tip.clone()]);
+        assert!(db.read_commit(&feature_tip).unwrap().is_some());
+        drop(db);
+
+        let checkpoints = test_utils::list_checkpoints(tmp_path, MAIN_BRANCH_NAME);
+        assert_eq!(checkpoints.len(), 4);
+        assert!(checkpoints.iter().any(|commit| commit.hash == feature_tip));
+    }
+}
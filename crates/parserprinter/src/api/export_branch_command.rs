@@ -0,0 +1,215 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{common::resolve_commit_hash, restore_command::write_commit_to_file},
+    db::db_ops::{DBError, Persistence, DB},
+};
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub struct ExportedCheckpoint {
+    /// The name of the file in the export folder
+    pub file_name: String,
+    pub hash: String,
+    pub branch: String,
+    pub message: String,
+    pub author: String,
+    pub date: u64,
+}
+
+/// Written next to the exported files, so they can be told apart without our
+/// tools
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub struct ExportManifest {
+    pub project_id: String,
+    pub branch: String,
+    /// Oldest first, in the order of the file names
+    pub checkpoints: Vec<ExportedCheckpoint>,
+}
+
+/// The history of the branch following first parents, newest first
+fn read_first_parents(conn: &Persistence, tip: &str) -> Result<Vec<String>, DBError> {
+    let mut hashes: Vec<String> = vec![];
+    let mut hash = tip.to_owned();
+    while conn.commit_exists(&hash)? {
+        let commit = conn
+            .read_commit(&hash)?
+            .ok_or(DBError::Consistency(format!("No such commit: {}", hash)))?;
+        hashes.push(hash);
+        hash = commit.prev_commit_hash;
+    }
+    Ok(hashes)
+}
+
+/// Writes the checkpoints of `branch_name` from `from` to `to` into `dir` as
+/// numbered blend files, like `main_0001.blend`, along with a manifest
+/// describing them. The range defaults to the whole history of the branch,
+/// following the first parent of merges. Nothing in the DB changes, so the
+/// current branch and commit stay as they are.
+pub fn export_branch(
+    db_path: &str,
+    branch_name: &str,
+    dir: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<ExportManifest, DBError> {
+    let conn = Persistence::open_read_only(db_path)?;
+
+    let tip = conn
+        .read_branch_tip(branch_name)?
+        .ok_or(DBError::Error(format!("No such branch: {}", branch_name)))?;
+    let mut history = read_first_parents(&conn, &tip)?;
+
+    if let Some(to) = to {
+        let to = resolve_commit_hash(&conn, to)?;
+        let position = history
+            .iter()
+            .position(|hash| *hash == to)
+            .ok_or(DBError::Error(format!("{} is not on {}", to, branch_name)))?;
+        history.drain(..position);
+    }
+    if let Some(from) = from {
+        let from = resolve_commit_hash(&conn, from)?;
+        let position = history
+            .iter()
+            .position(|hash| *hash == from)
+            .ok_or(DBError::Error(format!(
+                "{} is not on {} before the end of the range",
+                from, branch_name
+            )))?;
+        history.truncate(position + 1);
+    }
+    history.reverse();
+
+    fs::create_dir_all(dir)
+        .map_err(|e| DBError::Error(format!("Cannot create {}: {:?}", dir, e)))?;
+
+    let file_prefix = branch_name.replace(['/', '\\'], "_");
+    let mut checkpoints: Vec<ExportedCheckpoint> = vec![];
+    for (index, hash) in history.iter().enumerate() {
+        let file_name = format!("{}_{:04}.blend", file_prefix, index + 1);
+        let file_path = Path::new(dir).join(&file_name);
+
+        let commit = write_commit_to_file(&conn, hash, &file_path.to_string_lossy(), false)?;
+        checkpoints.push(ExportedCheckpoint {
+            file_name,
+            hash: commit.hash,
+            branch: commit.branch,
+            message: commit.message,
+            author: commit.author,
+            date: commit.date,
+        });
+    }
+
+    let manifest = ExportManifest {
+        project_id: conn.read_project_id()?,
+        branch: branch_name.to_owned(),
+        checkpoints,
+    };
+
+    let manifest_json = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| DBError::Error(format!("Cannot write manifest: {:?}", e)))?;
+    fs::write(Path::new(dir).join(MANIFEST_FILE_NAME), manifest_json)
+        .map_err(|e| DBError::Error(format!("Cannot write manifest: {:?}", e)))?;
+
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use tempfile::TempDir;
+
+    use crate::{
+        api::{
+            init_command::MAIN_BRANCH_NAME, restore_command::restore_checkpoint,
+            status_command::working_file_status, test_utils,
+        },
+        db::db_ops::{Persistence, DB},
+    };
+
+    use super::{export_branch, ExportManifest, MANIFEST_FILE_NAME};
+
+    #[test]
+    fn test_export_branch() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().join("db");
+        let tmp_db_path = tmp_db_path.to_str().unwrap();
+        let export_dir = tmp_dir.path().join("export");
+        let export_dir = export_dir.to_str().unwrap();
+        let tmp_blend_path = tmp_dir.path().join("working.blend");
+        let tmp_blend_path = tmp_blend_path.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
+        test_utils::commit(tmp_db_path, "Commit 2", "data/untitled_3.blend");
+
+        let checkpoints = test_utils::list_checkpoints(tmp_db_path, MAIN_BRANCH_NAME);
+        restore_checkpoint(tmp_blend_path, tmp_db_path, &checkpoints[1].hash, false).unwrap();
+
+        let manifest =
+            export_branch(tmp_db_path, MAIN_BRANCH_NAME, export_dir, None, None).unwrap();
+
+        let file_names: Vec<&str> = manifest
+            .checkpoints
+            .iter()
+            .map(|checkpoint| checkpoint.file_name.as_str())
+            .collect();
+        assert_eq!(
+            file_names,
+            vec!["main_0001.blend", "main_0002.blend", "main_0003.blend"]
+        );
+        assert_eq!(manifest.checkpoints[2].hash, checkpoints[0].hash);
+        assert_eq!(manifest.checkpoints[2].message, "Commit 2");
+
+        let written: ExportManifest = serde_json::from_str(
+            &fs::read_to_string(Path::new(export_dir).join(MANIFEST_FILE_NAME)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(written, manifest);
+
+        // The current commit did not move
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        assert_eq!(
+            db.read_current_commit_pointer().unwrap(),
+            checkpoints[1].hash
+        );
+        drop(db);
+
+        let exported = Path::new(export_dir).join("main_0002.blend");
+        let status = working_file_status(exported.to_str().unwrap(), tmp_db_path).unwrap();
+        assert!(!status.dirty);
+
+        // Only a range
+        let range_dir = tmp_dir.path().join("range");
+        let manifest = export_branch(
+            tmp_db_path,
+            MAIN_BRANCH_NAME,
+            range_dir.to_str().unwrap(),
+            Some(&checkpoints[2].hash),
+            Some(&checkpoints[1].hash),
+        )
+        .unwrap();
+        let hashes: Vec<&str> = manifest
+            .checkpoints
+            .iter()
+            .map(|checkpoint| checkpoint.hash.as_str())
+            .collect();
+        assert_eq!(hashes, vec![&checkpoints[2].hash, &checkpoints[1].hash]);
+        assert!(!range_dir.join("main_0003.blend").exists());
+
+        // `from` has to come before `to`
+        assert!(export_branch(
+            tmp_db_path,
+            MAIN_BRANCH_NAME,
+            range_dir.to_str().unwrap(),
+            Some(&checkpoints[0].hash),
+            Some(&checkpoints[1].hash),
+        )
+        .is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashSet;
 
 use crate::{
+    api::common::resolve_commit_hash,
     db::{
         db_ops::{DBError, Persistence, DB},
         structs::hash_list,
@@ -13,8 +14,9 @@ pub fn export_descendants_of_commit(
     db_path: &str,
     starting_from_commit_hash: &str,
 ) -> Result<Exchange, DBError> {
-    let db = Persistence::open(db_path)?;
-    let commits = db.read_descendants_of_commit(starting_from_commit_hash)?;
+    let db = Persistence::open_read_only(db_path)?;
+    let starting_from_commit_hash = resolve_commit_hash(&db, starting_from_commit_hash)?;
+    let commits = db.read_descendants_of_commit(&starting_from_commit_hash)?;
     let mut block_hashes: HashSet<String> = HashSet::new();
 
     for commit in commits.iter() {
@@ -32,7 +34,25 @@ pub fn export_descendants_of_commit(
 
     let blocks = db.read_blocks(block_hashes.into_iter().collect())?;
 
-    Ok(Exchange { commits, blocks })
+    let tags = db
+        .read_all_tags()?
+        .into_iter()
+        .filter(|tag| commits.iter().any(|commit| commit.hash == tag.hash))
+        .collect();
+
+    let mut metadata = vec![];
+    for commit in commits.iter() {
+        metadata.extend(db.read_commit_metadata(&commit.hash)?);
+    }
+
+    Ok(Exchange {
+        hash_algorithm: db.read_hash_algorithm()?,
+        commits,
+        blocks,
+        tags,
+        metadata,
+        branch_renames: db.read_branch_renames()?,
+    })
 }
 
 #[cfg(test)]
@@ -0,0 +1,140 @@
+use crate::db::{
+    db_ops::{DBError, Persistence, DB},
+    structs::{CommitInfo, HistoryQuery},
+};
+
+pub fn query_history(db_path: &str, query: &HistoryQuery) -> Result<Vec<CommitInfo>, DBError> {
+    Persistence::open_read_only(db_path).and_then(|db| db.read_history(query))
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use crate::{
+        api::{
+            init_command::{INITIAL_COMMIT_HASH, MAIN_BRANCH_NAME},
+            test_utils::{init_db_from_simple_timeline, SimpleCommit, SimpleTimeline},
+        },
+        db::structs::{HistoryOrder, HistoryQuery},
+    };
+
+    use super::query_history;
+
+    fn hashes(db_path: &str, query: HistoryQuery) -> Vec<String> {
+        query_history(db_path, &query)
+            .expect("Cannot query history")
+            .into_iter()
+            .map(|commit| commit.hash)
+            .collect()
+    }
+
+    #[test]
+    fn test_query_history() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        // dates start at 314 and increase by one with every commit
+        init_db_from_simple_timeline(
+            tmp_db_path,
+            SimpleTimeline {
+                project_id: String::from("a"),
+                author: "test".to_owned(),
+                blocks: vec![String::from("aaa")],
+                commits: vec![
+                    SimpleCommit {
+                        hash: "1".to_owned(),
+                        prev_hash: String::from(INITIAL_COMMIT_HASH),
+                        branch: String::from(MAIN_BRANCH_NAME),
+                        message: "Block out the scene".to_owned(),
+                        blocks: "aaa".to_owned(),
+                    },
+                    SimpleCommit {
+                        hash: "2".to_owned(),
+                        prev_hash: "1".to_owned(),
+                        branch: String::from(MAIN_BRANCH_NAME),
+                        message: "Add lights".to_owned(),
+                        blocks: "aaa".to_owned(),
+                    },
+                    SimpleCommit {
+                        hash: "a".to_owned(),
+                        prev_hash: "2".to_owned(),
+                        branch: "lighting".to_owned(),
+                        message: "Warmer lights, 100% brighter".to_owned(),
+                        blocks: "aaa".to_owned(),
+                    },
+                ],
+            },
+        );
+
+        assert_eq!(
+            hashes(tmp_db_path, HistoryQuery::default()),
+            vec!["a", "2", "1"]
+        );
+
+        assert_eq!(
+            hashes(
+                tmp_db_path,
+                HistoryQuery {
+                    order: HistoryOrder::OldestFirst,
+                    limit: Some(2),
+                    offset: 1,
+                    ..Default::default()
+                }
+            ),
+            vec!["2", "a"]
+        );
+
+        assert_eq!(
+            hashes(
+                tmp_db_path,
+                HistoryQuery {
+                    message_contains: Some("LIGHTS".to_owned()),
+                    branch: Some(MAIN_BRANCH_NAME.to_owned()),
+                    ..Default::default()
+                }
+            ),
+            vec!["2"]
+        );
+
+        // wildcards are matched literally
+        assert_eq!(
+            hashes(
+                tmp_db_path,
+                HistoryQuery {
+                    message_contains: Some("100%".to_owned()),
+                    ..Default::default()
+                }
+            ),
+            vec!["a"]
+        );
+
+        assert_eq!(
+            hashes(
+                tmp_db_path,
+                HistoryQuery {
+                    since: Some(315),
+                    until: Some(315),
+                    ..Default::default()
+                }
+            ),
+            vec!["2"]
+        );
+
+        assert_eq!(
+            hashes(
+                tmp_db_path,
+                HistoryQuery {
+                    author: Some("someone else".to_owned()),
+                    ..Default::default()
+                }
+            ),
+            Vec::<String>::new()
+        );
+
+        let commits = query_history(tmp_db_path, &HistoryQuery::default()).unwrap();
+        assert_eq!(commits[0].author, "test");
+        assert_eq!(commits[0].date, 316);
+        assert_eq!(commits[0].prev_commit_hash, "2");
+    }
+}
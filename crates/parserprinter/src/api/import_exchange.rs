@@ -1,27 +1,116 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
-    db::db_ops::{DBError, Persistence, DB},
+    db::{
+        db_ops::{DBError, Persistence, DB},
+        structs::{BranchRename, Commit, CommitMetadata, Intent, Tag},
+    },
     exchange::structs::Exchange,
 };
 
+/// The name of the branch called `name` after `renames`, ordered oldest first
+fn renamed_branch(renames: &[BranchRename], name: &str) -> String {
+    renames.iter().fold(name.to_owned(), |name, rename| {
+        if name == rename.old_name {
+            rename.new_name.clone()
+        } else {
+            name
+        }
+    })
+}
+
 // TODO: rename conflicting commits
-pub fn import_exchange(db_path: &str, exchange: Exchange) -> Result<(), DBError> {
+/// Imports the commits, blocks, tags and renames of `exchange`. Returns the
+/// names of the tags that were skipped, because a local tag with the same name
+/// points to another commit.
+pub fn import_exchange(db_path: &str, mut exchange: Exchange) -> Result<Vec<String>, DBError> {
+    let mut db = Persistence::open(db_path)?;
+
+    // mixing hashes of different algorithms would break content addressing
+    let hash_algorithm = db.read_hash_algorithm()?;
+    if exchange.hash_algorithm != hash_algorithm {
+        return Err(DBError::Error(format!(
+            "The exchange uses {}, but the timeline uses {}",
+            exchange.hash_algorithm.name(),
+            hash_algorithm.name()
+        )));
+    }
+
+    // renames made on either side apply to the commits of the other side, so
+    // that branches don't come back under their old names
+    let local_renames = db.read_branch_renames()?;
+    let mut new_renames: Vec<BranchRename> = exchange
+        .branch_renames
+        .into_iter()
+        .filter(|rename| !local_renames.contains(rename))
+        .collect();
+    new_renames.sort_by_key(|rename| rename.date);
+    let mut all_renames: Vec<BranchRename> = local_renames
+        .into_iter()
+        .chain(new_renames.clone())
+        .collect();
+    all_renames.sort_by_key(|rename| rename.date);
+
+    for commit in exchange.commits.iter_mut() {
+        commit.branch = renamed_branch(&all_renames, &commit.branch);
+    }
+
+    // local branches that were renamed on the other side
+    let mut local_branches: HashSet<String> = db.read_all_branches()?.into_iter().collect();
+    let mut branches_to_rename: Vec<(String, String)> = vec![];
+    for rename in new_renames.iter() {
+        if local_branches.contains(&rename.old_name) && !local_branches.contains(&rename.new_name) {
+            local_branches.remove(&rename.old_name);
+            local_branches.insert(rename.new_name.clone());
+            branches_to_rename.push((rename.old_name.clone(), rename.new_name.clone()));
+        }
+    }
+
     let mut branches_to_commits: HashMap<String, String> = HashMap::new();
 
     for commit in &exchange.commits {
         branches_to_commits.insert(commit.branch.clone(), commit.hash.clone());
     }
 
-    let mut db = Persistence::open(db_path)?;
+    // commits that are already known are left alone, so that rolling back an
+    // interrupted import never removes them
+    let mut new_commits: Vec<Commit> = vec![];
+    for commit in exchange.commits.into_iter() {
+        if !db.commit_exists(&commit.hash)? {
+            new_commits.push(commit);
+        }
+    }
+
+    let block_hashes: Vec<String> = exchange.blocks.iter().map(|b| b.hash.clone()).collect();
+    let intent = Intent::new(
+        "import",
+        new_commits.iter().map(|c| c.hash.clone()).collect(),
+        db.read_missing_block_hashes(&block_hashes)?,
+    );
+
+    db.execute_in_transaction(|tx| Persistence::write_intent(tx, &intent))?;
+
     db.write_blocks(&exchange.blocks)?;
 
-    for commit in &exchange.commits {
+    for commit in &new_commits {
         db.write_blocks_str(&commit.hash, &commit.blocks)?;
     }
 
+    db.sync_blocks()?;
+
+    // metadata of commits that are already known is left as it is locally
+    let new_metadata: Vec<CommitMetadata> = exchange
+        .metadata
+        .into_iter()
+        .filter(|entry| new_commits.iter().any(|commit| commit.hash == entry.hash))
+        .collect();
+
     db.execute_in_transaction(|tx| {
-        for commit in exchange.commits.into_iter() {
+        for entry in new_metadata.iter() {
+            Persistence::write_commit_metadata(tx, entry)?;
+        }
+
+        for commit in new_commits.into_iter() {
             Persistence::write_commit(tx, commit)?;
         }
 
@@ -31,21 +120,50 @@ pub fn import_exchange(db_path: &str, exchange: Exchange) -> Result<(), DBError>
     let mut branches_to_tips: HashMap<String, String> = HashMap::new();
 
     for (branch, commit_hash) in branches_to_commits.into_iter() {
+        // descendants can continue on other branches through merges
         let tip = db
             .read_descendants_of_commit(&commit_hash)?
-            .last()
+            .iter()
+            .rev()
+            .find(|c| c.branch == branch)
             .map(|c| c.hash.clone())
             .unwrap_or(commit_hash);
         branches_to_tips.insert(branch, tip);
     }
 
+    // tags never move, so the local one wins if the names clash
+    let mut new_tags: Vec<Tag> = vec![];
+    let mut skipped_tags: Vec<String> = vec![];
+    for tag in exchange.tags.into_iter() {
+        match db.read_tag(&tag.name)? {
+            Some(hash) if hash != tag.hash => skipped_tags.push(tag.name),
+            Some(_) => {}
+            None if db.commit_exists(&tag.hash)? => new_tags.push(tag),
+            None => {}
+        }
+    }
+
     db.execute_in_transaction(|tx| {
+        for (old_name, new_name) in branches_to_rename.iter() {
+            Persistence::rename_branch(tx, old_name, new_name)?;
+        }
+
+        for rename in new_renames.iter() {
+            Persistence::write_branch_rename(tx, rename)?;
+        }
+
         for (branch, tip) in branches_to_tips.into_iter() {
-            Persistence::write_branch_tip(tx, &branch, &tip)?;
+            Persistence::write_branch_tip(tx, &branch, &tip, "import")?;
         }
 
-        Ok(())
-    })
+        for tag in new_tags.iter() {
+            Persistence::write_tag(tx, tag)?;
+        }
+
+        Persistence::delete_intent(tx, &intent.id)
+    })?;
+
+    Ok(skipped_tags)
 }
 
 /*
@@ -62,11 +180,12 @@ mod test {
     use crate::{
         api::{
             init_command::{INITIAL_COMMIT_HASH, MAIN_BRANCH_NAME},
+            rename_branch_command::rename_branch,
             test_utils::{init_db_from_simple_timeline, SimpleCommit, SimpleTimeline},
         },
         db::{
             db_ops::{Persistence, DB},
-            structs::{BlockRecord, Commit},
+            structs::{BlockRecord, BranchRename, Commit, CommitMetadata, HashAlgorithm, Tag},
         },
         exchange::structs::Exchange,
     };
@@ -128,10 +247,14 @@ mod test {
         );
 
         let exchange = Exchange {
+            // timelines that never recorded their hash algorithm use MD5
+            hash_algorithm: HashAlgorithm::Md5,
             commits: vec![
                 Commit {
                     hash: "4".to_owned(),
+                    snapshot: "4".to_owned(),
                     prev_commit_hash: "3".to_owned(),
+                    merge_parents: vec![],
                     project_id: "a".to_owned(),
                     branch: String::from(MAIN_BRANCH_NAME),
                     message: "hi".to_owned(),
@@ -142,7 +265,9 @@ mod test {
                 },
                 Commit {
                     hash: "a".to_owned(),
+                    snapshot: "a".to_owned(),
                     prev_commit_hash: "1".to_owned(),
+                    merge_parents: vec![],
                     project_id: "a".to_owned(),
                     branch: "ab".to_owned(),
                     message: "hi".to_owned(),
@@ -153,7 +278,9 @@ mod test {
                 },
                 Commit {
                     hash: "b".to_owned(),
+                    snapshot: "b".to_owned(),
                     prev_commit_hash: "a".to_owned(),
+                    merge_parents: vec![],
                     project_id: "a".to_owned(),
                     branch: "ab".to_owned(),
                     message: "hi".to_owned(),
@@ -164,7 +291,9 @@ mod test {
                 },
                 Commit {
                     hash: "x".to_owned(),
+                    snapshot: "x".to_owned(),
                     prev_commit_hash: "3".to_owned(),
+                    merge_parents: vec![],
                     project_id: "a".to_owned(),
                     branch: "xs".to_owned(),
                     message: "hi".to_owned(),
@@ -200,9 +329,63 @@ mod test {
                     data: vec![7, 7, 7],
                 },
             ],
+            tags: vec![
+                Tag {
+                    name: "approved".to_owned(),
+                    hash: "b".to_owned(),
+                },
+                Tag {
+                    name: "dangling".to_owned(),
+                    hash: "not-a-commit".to_owned(),
+                },
+                Tag {
+                    name: "release".to_owned(),
+                    hash: "4".to_owned(),
+                },
+            ],
+            metadata: vec![
+                CommitMetadata {
+                    hash: "a".to_owned(),
+                    key: "ticket".to_owned(),
+                    value: "PRJ-1".to_owned(),
+                    hashed: true,
+                },
+                CommitMetadata {
+                    hash: "1".to_owned(),
+                    key: "ticket".to_owned(),
+                    value: "PRJ-1".to_owned(),
+                    hashed: true,
+                },
+            ],
+            branch_renames: vec![],
+        };
+
+        // hashes from another algorithm are not mixed in
+        let rejected = Exchange {
+            hash_algorithm: HashAlgorithm::Blake3,
+            ..exchange.clone()
         };
+        assert!(import_exchange(tmp_db_path, rejected).is_err());
+        assert!(!Persistence::open(tmp_db_path)
+            .unwrap()
+            .commit_exists("4")
+            .unwrap());
+
+        let mut db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        db.execute_in_transaction(|tx| {
+            Persistence::write_tag(
+                tx,
+                &Tag {
+                    name: "release".to_owned(),
+                    hash: "2".to_owned(),
+                },
+            )
+        })
+        .expect("Cannot write tag");
+        drop(db);
 
-        import_exchange(tmp_db_path, exchange).expect("Cannot import exchange");
+        let skipped_tags = import_exchange(tmp_db_path, exchange).expect("Cannot import exchange");
+        assert_eq!(skipped_tags, vec!["release"]);
         let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
 
         let all_commits = db
@@ -238,5 +421,126 @@ mod test {
             .expect("Cannot read tip for branch 'xs'")
             .expect("Branch 'xs' should have a tip");
         assert_eq!(main_tip, "x");
+
+        // only tags of known commits are imported, and local tags stay
+        let tags = db.read_all_tags().expect("Cannot read tags");
+        assert_eq!(
+            tags,
+            vec![
+                Tag {
+                    name: "approved".to_owned(),
+                    hash: "b".to_owned(),
+                },
+                Tag {
+                    name: "release".to_owned(),
+                    hash: "2".to_owned(),
+                }
+            ]
+        );
+
+        // only metadata of new commits is imported
+        let tagged: Vec<String> = db
+            .read_commits_with_metadata("ticket", "PRJ-1")
+            .expect("Cannot read metadata")
+            .into_iter()
+            .map(|commit| commit.hash)
+            .collect();
+        assert_eq!(tagged, vec!["a"]);
+    }
+
+    fn init_lightnig_timeline(db_path: &str) {
+        init_db_from_simple_timeline(
+            db_path,
+            SimpleTimeline {
+                project_id: "a".to_owned(),
+                author: "test".to_owned(),
+                blocks: vec!["aaa".to_owned()],
+                commits: vec![
+                    SimpleCommit {
+                        hash: "1".to_owned(),
+                        prev_hash: String::from(INITIAL_COMMIT_HASH),
+                        branch: String::from(MAIN_BRANCH_NAME),
+                        message: "hi".to_owned(),
+                        blocks: "aaa".to_owned(),
+                    },
+                    SimpleCommit {
+                        hash: "a".to_owned(),
+                        prev_hash: "1".to_owned(),
+                        branch: "lightnig".to_owned(),
+                        message: "hi".to_owned(),
+                        blocks: "aaa".to_owned(),
+                    },
+                ],
+            },
+        );
+    }
+
+    fn lighting_commit(hash: &str, branch: &str) -> Commit {
+        Commit {
+            hash: hash.to_owned(),
+            snapshot: hash.to_owned(),
+            prev_commit_hash: "a".to_owned(),
+            merge_parents: vec![],
+            project_id: "a".to_owned(),
+            branch: branch.to_owned(),
+            message: "hi".to_owned(),
+            author: "test".to_owned(),
+            date: 1000,
+            header: vec![],
+            blocks: "aaa".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_import_renamed_branch() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let renamed_here = tmp_dir.path().join("here");
+        let renamed_here = renamed_here.to_str().unwrap();
+        let renamed_there = tmp_dir.path().join("there");
+        let renamed_there = renamed_there.to_str().unwrap();
+
+        // the branch was renamed here, the other side still uses the old name
+        init_lightnig_timeline(renamed_here);
+        rename_branch(renamed_here, "lightnig", "lighting").unwrap();
+
+        import_exchange(
+            renamed_here,
+            Exchange {
+                hash_algorithm: HashAlgorithm::Md5,
+                commits: vec![lighting_commit("b", "lightnig")],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let db = Persistence::open(renamed_here).unwrap();
+        assert_eq!(db.read_all_branches().unwrap(), vec!["lighting", "main"]);
+        assert_eq!(db.read_branch_tip("lighting").unwrap().unwrap(), "b");
+        assert_eq!(db.read_commit("b").unwrap().unwrap().branch, "lighting");
+        drop(db);
+
+        // the branch was renamed on the other side
+        init_lightnig_timeline(renamed_there);
+
+        import_exchange(
+            renamed_there,
+            Exchange {
+                hash_algorithm: HashAlgorithm::Md5,
+                commits: vec![lighting_commit("c", "lighting")],
+                branch_renames: vec![BranchRename {
+                    old_name: "lightnig".to_owned(),
+                    new_name: "lighting".to_owned(),
+                    date: 500,
+                }],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let db = Persistence::open(renamed_there).unwrap();
+        assert_eq!(db.read_all_branches().unwrap(), vec!["lighting", "main"]);
+        assert_eq!(db.read_branch_tip("lighting").unwrap().unwrap(), "c");
+        assert_eq!(db.read_commit("a").unwrap().unwrap().branch, "lighting");
+        assert_eq!(db.read_branch_renames().unwrap().len(), 1);
     }
 }
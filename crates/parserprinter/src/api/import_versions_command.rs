@@ -0,0 +1,406 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{
+        commit_command::create_commit_at, common::read_current_commit_hash,
+        init_command::init_db_at, status_command::working_file_status,
+    },
+    db::{
+        db_ops::{DBError, Persistence, DB},
+        structs::HashAlgorithm,
+    },
+};
+
+/// Set on imported commits that were saved before the commit they follow, to
+/// the time they were saved
+pub const SAVED_AT_KEY: &str = "saved_at";
+
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionOrder {
+    /// Oldest modification time first
+    Mtime,
+    /// By the number in the file name, `scene.blend32` to `scene.blend1` and
+    /// then `scene.blend` for backups, `shot_v001.blend` upwards otherwise
+    Number,
+}
+
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub struct ImportedVersion {
+    pub file_path: String,
+    /// `None` if the file is the same as the version before it
+    pub hash: Option<String>,
+}
+
+fn modified_at(path: &Path) -> Result<u64, DBError> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(|modified| {
+            modified
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_secs())
+        })
+        .map_err(|e| DBError::Error(format!("Cannot read {}: {:?}", path.display(), e)))
+}
+
+/// The number at the end of the name of a version, like 12 for `shot_v012`
+fn version_number(stem: &str) -> Option<i64> {
+    let digits: String = stem
+        .chars()
+        .rev()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<Vec<char>>()
+        .into_iter()
+        .rev()
+        .collect();
+    digits.parse().ok()
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>, DBError> {
+    fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<PathBuf>, _>>()
+        })
+        .map_err(|e| DBError::Error(format!("Cannot read {}: {:?}", dir.display(), e)))
+}
+
+/// The versions to import from `path` with their numbers, newer versions
+/// having higher numbers. `path` is either a folder of `.blend` files, or a
+/// `.blend` file and the backups Blender keeps next to it, `scene.blend1`
+/// being the newest of them.
+fn collect_versions(path: &Path) -> Result<Vec<(PathBuf, Option<i64>)>, DBError> {
+    if path.is_dir() {
+        return Ok(read_dir(path)?
+            .into_iter()
+            .filter(|file| file.is_file() && file.extension().is_some_and(|ext| ext == "blend"))
+            .map(|file| {
+                let number = file
+                    .file_stem()
+                    .and_then(|stem| version_number(&stem.to_string_lossy()));
+                (file, number)
+            })
+            .collect());
+    }
+
+    if !path.is_file() {
+        return Err(DBError::Error(format!("No such file: {}", path.display())));
+    }
+
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let mut versions = vec![(path.to_path_buf(), Some(0))];
+    for file in read_dir(dir)? {
+        let backup_number = file
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .and_then(|name| name.strip_prefix(&file_name).map(str::to_owned))
+            .filter(|suffix| !suffix.is_empty() && suffix.chars().all(|c| c.is_ascii_digit()))
+            .and_then(|suffix| suffix.parse::<i64>().ok());
+
+        if let Some(backup_number) = backup_number {
+            versions.push((file, Some(-backup_number)));
+        }
+    }
+
+    Ok(versions)
+}
+
+/// Builds a linear timeline out of versions of a file saved before it was
+/// versioned, see `collect_versions`. The versions are committed in `order`
+/// on top of the current commit, each with the time it was saved. Versions
+/// saved before the commit they follow get its time instead, so that the
+/// history never goes back in time, and keep theirs in `SAVED_AT_KEY`. If the
+/// DB is not initialized yet, the oldest version becomes its first commit, for
+/// a new project with `project_id` and `hash_algorithm`. Versions that are the
+/// same as the one before them are skipped.
+pub fn import_versions(
+    db_path: &str,
+    path: &str,
+    order: VersionOrder,
+    project_id: &str,
+    hash_algorithm: HashAlgorithm,
+) -> Result<Vec<ImportedVersion>, DBError> {
+    let mut versions: Vec<(PathBuf, Option<i64>, u64)> = collect_versions(Path::new(path))?
+        .into_iter()
+        .map(|(file, number)| modified_at(&file).map(|date| (file, number, date)))
+        .collect::<Result<_, DBError>>()?;
+
+    match order {
+        VersionOrder::Mtime => {
+            versions.sort_by(|(a, _, a_date), (b, _, b_date)| (a_date, a).cmp(&(b_date, b)))
+        }
+        VersionOrder::Number => {
+            versions.sort_by(|(a, a_number, _), (b, b_number, _)| (a_number, a).cmp(&(b_number, b)))
+        }
+    }
+
+    if versions.is_empty() {
+        return Err(DBError::Error(format!("No versions found in {}", path)));
+    }
+
+    let is_initialized = match Persistence::open(db_path)?.read_current_branch_name() {
+        Ok(_) => true,
+        Err(DBError::Consistency(_)) => false,
+        Err(err) => return Err(err),
+    };
+
+    let mut imported: Vec<ImportedVersion> = vec![];
+    for (index, (file, _, date)) in versions.into_iter().enumerate() {
+        let file_path = file.to_string_lossy().into_owned();
+        let file_name = file.file_name().map_or(file_path.clone(), |name| {
+            name.to_string_lossy().into_owned()
+        });
+
+        let is_new = if index == 0 && !is_initialized {
+            init_db_at(db_path, project_id, &file_path, hash_algorithm, date)?;
+            true
+        } else if working_file_status(&file_path, db_path)?.dirty {
+            let parent_date = {
+                let conn = Persistence::open_read_only(db_path)?;
+                let parent = read_current_commit_hash(&conn)?;
+                conn.read_commit(&parent)?.map_or(0, |commit| commit.date)
+            };
+
+            let mut metadata: HashMap<String, String> = HashMap::new();
+            if date < parent_date {
+                metadata.insert(SAVED_AT_KEY.to_owned(), date.to_string());
+            }
+
+            create_commit_at(
+                &file_path,
+                db_path,
+                format!("Imported {}", file_name),
+                metadata,
+                date.max(parent_date),
+            )?;
+            true
+        } else {
+            false
+        };
+
+        let hash = if is_new {
+            let conn = Persistence::open_read_only(db_path)?;
+            Some(read_current_commit_hash(&conn)?)
+        } else {
+            None
+        };
+        imported.push(ImportedVersion { file_path, hash });
+    }
+
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::{self, File},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use tempfile::TempDir;
+
+    use crate::{
+        api::{init_command::MAIN_BRANCH_NAME, test_utils},
+        db::{
+            db_ops::{Persistence, DB},
+            structs::{HashAlgorithm, HistoryOrder, HistoryQuery},
+        },
+    };
+
+    use super::{import_versions, version_number, VersionOrder, SAVED_AT_KEY};
+
+    fn copy_saved_at(from: &str, to: &str, date: u64) {
+        fs::copy(from, to).unwrap();
+        File::options()
+            .write(true)
+            .open(to)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(date))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_version_number() {
+        assert_eq!(version_number("shot_v012"), Some(12));
+        assert_eq!(version_number("shot_v2"), Some(2));
+        assert_eq!(version_number("shot_final"), None);
+    }
+
+    #[test]
+    fn test_import_backups() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().join("db");
+        let tmp_db_path = tmp_db_path.to_str().unwrap();
+        let scene = tmp_dir.path().join("scene.blend");
+        let scene = scene.to_str().unwrap();
+
+        copy_saved_at("data/untitled_3.blend", scene, 3000);
+        copy_saved_at("data/untitled_2.blend", &format!("{}1", scene), 2000);
+        copy_saved_at("data/untitled_2.blend", &format!("{}2", scene), 1500);
+        copy_saved_at("data/untitled.blend", &format!("{}3", scene), 1000);
+
+        let imported = import_versions(
+            tmp_db_path,
+            scene,
+            VersionOrder::Number,
+            "my-cool-project",
+            HashAlgorithm::default(),
+        )
+        .unwrap();
+
+        let files: Vec<&str> = imported
+            .iter()
+            .map(|version| version.file_path.rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                "scene.blend3",
+                "scene.blend2",
+                "scene.blend1",
+                "scene.blend"
+            ]
+        );
+        // `scene.blend1` is the same as `scene.blend2`
+        assert!(imported[2].hash.is_none());
+
+        let checkpoints = test_utils::list_checkpoints(tmp_db_path, MAIN_BRANCH_NAME);
+        let messages: Vec<&str> = checkpoints.iter().map(|c| c.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Imported scene.blend",
+                "Imported scene.blend2",
+                "Initial checkpoint"
+            ]
+        );
+        assert_eq!(Some(&checkpoints[0].hash), imported[3].hash.as_ref());
+
+        // Each version keeps the time it was saved
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        let dates: Vec<u64> = checkpoints
+            .iter()
+            .map(|checkpoint| db.read_commit(&checkpoint.hash).unwrap().unwrap().date)
+            .collect();
+        assert_eq!(dates, vec![3000, 1500, 1000]);
+    }
+
+    #[test]
+    fn test_import_folder() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().join("db");
+        let tmp_db_path = tmp_db_path.to_str().unwrap();
+        let versions = tmp_dir.path().join("versions");
+        fs::create_dir(&versions).unwrap();
+        let version = |name: &str| versions.join(name).to_str().unwrap().to_owned();
+
+        copy_saved_at("data/untitled.blend", &version("shot_v2.blend"), 3000);
+        copy_saved_at("data/untitled_2.blend", &version("shot_v10.blend"), 1000);
+        copy_saved_at("data/untitled_3.blend", &version("shot_v3.blend"), 2000);
+        fs::write(version("notes.txt"), "not a blend file").unwrap();
+
+        let imported = import_versions(
+            tmp_db_path,
+            versions.to_str().unwrap(),
+            VersionOrder::Number,
+            "my-cool-project",
+            HashAlgorithm::default(),
+        )
+        .unwrap();
+        let files: Vec<&str> = imported
+            .iter()
+            .map(|version| version.file_path.rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(
+            files,
+            vec!["shot_v2.blend", "shot_v3.blend", "shot_v10.blend"]
+        );
+
+        // Each version follows the one before it, whenever it was saved
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        for (prev, version) in imported.iter().zip(imported.iter().skip(1)) {
+            let commit = db
+                .read_commit(version.hash.as_ref().unwrap())
+                .unwrap()
+                .unwrap();
+            assert_eq!(Some(&commit.prev_commit_hash), prev.hash.as_ref());
+        }
+
+        // The history lists them in the same order, and the time they were
+        // saved is kept
+        let history: Vec<(String, u64)> = db
+            .read_history(&HistoryQuery {
+                order: HistoryOrder::OldestFirst,
+                ..Default::default()
+            })
+            .unwrap()
+            .into_iter()
+            .map(|commit| (commit.hash, commit.date))
+            .collect();
+        let hashes: Vec<&String> = history.iter().map(|(hash, _)| hash).collect();
+        let dates: Vec<u64> = history.iter().map(|(_, date)| *date).collect();
+        assert_eq!(
+            hashes,
+            imported
+                .iter()
+                .map(|version| version.hash.as_ref().unwrap())
+                .collect::<Vec<&String>>()
+        );
+        assert_eq!(dates, vec![3000, 3000, 3000]);
+
+        let saved_at: Vec<Option<String>> = imported
+            .iter()
+            .map(|version| {
+                db.read_commit_metadata(version.hash.as_ref().unwrap())
+                    .unwrap()
+                    .into_iter()
+                    .find(|entry| entry.key == SAVED_AT_KEY)
+                    .map(|entry| entry.value)
+            })
+            .collect();
+        assert_eq!(
+            saved_at,
+            vec![None, Some("2000".to_owned()), Some("1000".to_owned())]
+        );
+        drop(db);
+
+        // Importing into an existing timeline, by the time of the saves
+        let imported = import_versions(
+            tmp_db_path,
+            versions.to_str().unwrap(),
+            VersionOrder::Mtime,
+            "my-cool-project",
+            HashAlgorithm::default(),
+        )
+        .unwrap();
+        let files: Vec<&str> = imported
+            .iter()
+            .map(|version| version.file_path.rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(
+            files,
+            vec!["shot_v10.blend", "shot_v3.blend", "shot_v2.blend"]
+        );
+        assert!(imported[0].hash.is_none());
+        assert_eq!(
+            test_utils::list_checkpoints(tmp_db_path, MAIN_BRANCH_NAME).len(),
+            5
+        );
+    }
+}
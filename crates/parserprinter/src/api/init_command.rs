@@ -1,53 +1,110 @@
 use crate::db::{
     db_ops::{DBError, Persistence, DB},
-    structs::Commit,
+    structs::{BranchInfo, Commit, HashAlgorithm, Intent},
 };
 
-use super::{common::blend_file_data_from_file, utils::timestamp};
+use super::{
+    common::{blend_file_data_from_file, hash_new_commit, metadata_for_new_commit},
+    utils::timestamp,
+};
+
+use std::collections::HashMap;
 
 pub const INITIAL_COMMIT_HASH: &str = "initial";
 pub const MAIN_BRANCH_NAME: &str = "main";
 
-pub fn init_db(db_path: &str, project_id: &str, path_to_blend: &str) -> Result<(), DBError> {
-    let blend_data = blend_file_data_from_file(path_to_blend)
+pub fn init_db(
+    db_path: &str,
+    project_id: &str,
+    path_to_blend: &str,
+    hash_algorithm: HashAlgorithm,
+) -> Result<(), DBError> {
+    init_db_at(
+        db_path,
+        project_id,
+        path_to_blend,
+        hash_algorithm,
+        timestamp(),
+    )
+}
+
+/// Initializes the DB with a first commit made at `date`
+pub(crate) fn init_db_at(
+    db_path: &str,
+    project_id: &str,
+    path_to_blend: &str,
+    hash_algorithm: HashAlgorithm,
+    date: u64,
+) -> Result<(), DBError> {
+    let blend_data = blend_file_data_from_file(path_to_blend, hash_algorithm)
         .map_err(|e| DBError::Error(format!("Error parsing blend file: {}", e)))?;
 
     let mut db = Persistence::open(db_path)?;
 
     let name = db.read_name()?.unwrap_or("Anon".to_owned());
 
-    let hash = blend_data.hash.clone();
-
-    db.write_blocks_str(&blend_data.hash, &blend_data.blocks)?;
+    let mut commit = Commit {
+        hash: String::new(),
+        snapshot: blend_data.snapshot,
+        prev_commit_hash: String::from(INITIAL_COMMIT_HASH),
+        merge_parents: vec![],
+        project_id: String::from(project_id),
+        branch: String::from(MAIN_BRANCH_NAME),
+        message: String::from("Initial checkpoint"),
+        author: name,
+        date,
+        header: blend_data.header_bytes,
+        blocks: blend_data.blocks,
+    };
+    let metadata = hash_new_commit(
+        &mut commit,
+        metadata_for_new_commit(&blend_data.blender_version, HashMap::new()),
+        hash_algorithm,
+    );
+
+    let block_hashes: Vec<String> = blend_data
+        .block_data
+        .iter()
+        .map(|b| b.hash.clone())
+        .collect();
+    let intent = Intent::new(
+        "init",
+        vec![commit.hash.clone()],
+        db.read_missing_block_hashes(&block_hashes)?,
+    );
+
+    db.execute_in_transaction(|tx| Persistence::write_intent(tx, &intent))?;
+
+    db.write_blocks_str(&commit.hash, &commit.blocks)?;
 
     db.write_blocks(&blend_data.block_data)?;
 
-    db.execute_in_transaction(|tx| {
-        Persistence::write_branch_tip(tx, MAIN_BRANCH_NAME, &blend_data.hash)?;
-
-        let commit = Commit {
-            hash: blend_data.hash,
-            prev_commit_hash: String::from(INITIAL_COMMIT_HASH),
-            project_id: String::from(project_id),
-            branch: String::from(MAIN_BRANCH_NAME),
-            message: String::from("Initial checkpoint"),
-            author: name,
-            date: timestamp(),
-            header: blend_data.header_bytes,
-            blocks: blend_data.blocks,
-        };
-
-        Persistence::write_commit(tx, commit)
-    })?;
+    db.sync_blocks()?;
 
     db.execute_in_transaction(|tx| {
-        Persistence::write_branch_tip(tx, MAIN_BRANCH_NAME, &hash)?;
-        Persistence::write_remote_branch_tip(tx, MAIN_BRANCH_NAME, &hash)?;
-        Persistence::write_current_branch_name(tx, MAIN_BRANCH_NAME)?;
+        Persistence::write_branch_tip(tx, MAIN_BRANCH_NAME, &commit.hash, "init")?;
+        Persistence::write_remote_branch_tip(tx, MAIN_BRANCH_NAME, &commit.hash)?;
+        Persistence::write_current_branch_name(tx, MAIN_BRANCH_NAME, "init")?;
+        Persistence::write_current_commit_pointer(tx, &commit.hash, "init")?;
         Persistence::write_project_id(tx, project_id)?;
-        Ok(())
-    })?;
-    Ok(())
+        Persistence::write_hash_algorithm(tx, hash_algorithm)?;
+        Persistence::write_branch_info(
+            tx,
+            &BranchInfo {
+                name: String::from(MAIN_BRANCH_NAME),
+                base: commit.hash.clone(),
+                created: timestamp(),
+                description: None,
+            },
+        )?;
+
+        for entry in metadata.iter() {
+            Persistence::write_commit_metadata(tx, entry)?;
+        }
+
+        Persistence::write_commit(tx, commit)?;
+        Persistence::delete_intent(tx, &intent.id)
+    })
 }
 
 #[cfg(test)]
@@ -56,7 +113,10 @@ mod test {
 
     use crate::{
         api::init_command::MAIN_BRANCH_NAME,
-        db::db_ops::{Persistence, DB},
+        db::{
+            db_ops::{Persistence, DB},
+            structs::HashAlgorithm,
+        },
     };
 
     use super::init_db;
@@ -65,7 +125,13 @@ mod test {
     fn test_post_init_state() {
         let tmp_dir = TempDir::new().expect("Cannot create temp dir");
         let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
-        init_db(tmp_path, "my amazing project", "data/untitled.blend").expect("Cannot init DB");
+        init_db(
+            tmp_path,
+            "my amazing project",
+            "data/untitled.blend",
+            HashAlgorithm::Sha256,
+        )
+        .expect("Cannot init DB");
 
         let db = Persistence::open(tmp_path).expect("Cannot open db");
         let current_branch_name = db
@@ -74,6 +140,11 @@ mod test {
         assert_eq!(current_branch_name, MAIN_BRANCH_NAME);
 
         let project_id = db.read_project_id().expect("Cannot read project id");
-        assert_eq!(project_id, "my amazing project")
+        assert_eq!(project_id, "my amazing project");
+
+        let hash_algorithm = db
+            .read_hash_algorithm()
+            .expect("Cannot read hash algorithm");
+        assert_eq!(hash_algorithm, HashAlgorithm::Sha256);
     }
 }
@@ -16,6 +16,11 @@ pub fn init_from_import_command(
     file_path: &str,
 ) -> Result<(), DBError> {
     let hash = {
+        // the clone keeps hashing the way the timeline it was cloned from does
+        Persistence::open(db_path)?.execute_in_transaction(|tx| {
+            Persistence::write_hash_algorithm(tx, exchange.hash_algorithm)
+        })?;
+
         import_exchange(db_path, exchange)?;
         let mut db = Persistence::open(db_path)?;
 
@@ -38,5 +43,62 @@ pub fn init_from_import_command(
         db.read_branch_tip(MAIN_BRANCH_NAME)?.unwrap() // TODO
     };
 
-    restore_checkpoint(file_path, db_path, &hash)
+    restore_checkpoint(file_path, db_path, &hash, false)
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use crate::{
+        api::{
+            export_descendants_of_commit::export_descendants_of_commit,
+            status_command::working_file_status, test_utils,
+        },
+        db::{
+            db_ops::{Persistence, DB},
+            structs::HashAlgorithm,
+        },
+    };
+
+    use super::init_from_import_command;
+
+    #[test]
+    fn test_clone_keeps_hash_algorithm() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let origin_path = tmp_dir.path().join("origin");
+        let origin_path = origin_path.to_str().unwrap();
+        let clone_path = tmp_dir.path().join("clone");
+        let clone_path = clone_path.to_str().unwrap();
+        let tmp_blend_path = tmp_dir.path().join("working.blend");
+        let tmp_blend_path = tmp_blend_path.to_str().unwrap();
+
+        test_utils::init_db_from_file(origin_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(origin_path, "Commit", "data/untitled_2.blend");
+
+        let db = Persistence::open_read_only(origin_path).unwrap();
+        let tip = db.read_current_commit_pointer().unwrap();
+        let root = db
+            .read_ancestors_of_commit(&tip)
+            .unwrap()
+            .pop()
+            .unwrap()
+            .hash;
+        drop(db);
+        let exchange = export_descendants_of_commit(origin_path, &root).unwrap();
+        assert_eq!(exchange.hash_algorithm, HashAlgorithm::Blake3);
+
+        init_from_import_command(clone_path, exchange, tmp_blend_path).unwrap();
+
+        let db = Persistence::open_read_only(clone_path).unwrap();
+        assert_eq!(db.read_hash_algorithm().unwrap(), HashAlgorithm::Blake3);
+        drop(db);
+
+        // The restored file hashes to the cloned tip
+        assert!(
+            !working_file_status(tmp_blend_path, clone_path)
+                .unwrap()
+                .dirty
+        );
+    }
 }
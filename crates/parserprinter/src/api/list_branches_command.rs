@@ -1,5 +1,14 @@
-use crate::db::db_ops::{DBError, Persistence, DB};
+use crate::db::{
+    db_ops::{DBError, Persistence, DB},
+    structs::BranchInfo,
+};
 
 pub fn list_braches(db_path: &str) -> Result<Vec<String>, DBError> {
-    Persistence::open(db_path).and_then(|db| db.read_all_branches())
+    Persistence::open_read_only(db_path).and_then(|db| db.read_all_branches())
+}
+
+/// Branches that were created by an older version, or imported from another
+/// timeline, have no info
+pub fn get_branch_info(db_path: &str, branch_name: &str) -> Result<Option<BranchInfo>, DBError> {
+    Persistence::open_read_only(db_path).and_then(|db| db.read_branch_info(branch_name))
 }
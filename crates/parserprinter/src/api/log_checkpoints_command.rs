@@ -4,7 +4,7 @@ pub fn list_checkpoints(
     db_path: &str,
     branch_name: &str,
 ) -> Result<Vec<ShortCommitRecord>, DBError> {
-    let conn = Persistence::open(db_path)?;
+    let conn = Persistence::open_read_only(db_path)?;
     let tip = conn
         .read_branch_tip(branch_name)?
         .ok_or(DBError::Consistency(format!(
@@ -0,0 +1,225 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{
+        commit_command::create_merge_commit,
+        common::{
+            read_blend_blocks, read_current_commit_hash, read_latest_commit_hash_on_branch,
+            write_blend_blocks,
+        },
+        status_command::ensure_no_uncommitted_changes,
+    },
+    blend::merge::{merge_blend_blocks, MergeConflict, MergeError, MergeSide},
+    db::db_ops::{DBError, Persistence, DB},
+};
+
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub enum MergeOutcome {
+    /// A merge commit was created on the current branch
+    Merged,
+    /// The current branch had nothing the other branch didn't, so it was
+    /// moved to the tip of the other branch
+    FastForward,
+    /// The other branch has nothing to merge
+    UpToDate,
+    /// Nothing was written, these datablocks need a side picked for them
+    Conflicts(Vec<MergeConflict>),
+}
+
+fn find_merge_base(
+    conn: &Persistence,
+    ours: &str,
+    theirs: &str,
+) -> Result<Option<String>, DBError> {
+    let their_ancestors: HashSet<String> = conn
+        .read_ancestors_of_commit(theirs)?
+        .into_iter()
+        .map(|commit| commit.hash)
+        .collect();
+
+    Ok(conn
+        .read_ancestors_of_commit(ours)?
+        .into_iter()
+        .map(|commit| commit.hash)
+        .find(|hash| their_ancestors.contains(hash)))
+}
+
+/// Merges `branch_name` into the current branch and writes the result to
+/// `file_path`. Conflicting datablocks are reported unless `resolutions`
+/// picks a side for each of them. The file cannot have changes that are not
+/// committed, since it is overwritten.
+pub fn merge_branch(
+    file_path: &str,
+    db_path: &str,
+    branch_name: &str,
+    resolutions: &HashMap<String, MergeSide>,
+) -> Result<MergeOutcome, DBError> {
+    let mut conn = Persistence::open(db_path)?;
+
+    let current_branch_name = conn.read_current_branch_name()?;
+    if current_branch_name == branch_name {
+        return Err(DBError::Error(
+            "Cannot merge a branch into itself".to_owned(),
+        ));
+    }
+
+    let our_tip = read_latest_commit_hash_on_branch(&conn, &current_branch_name)?;
+    if read_current_commit_hash(&conn)? != our_tip {
+        return Err(DBError::Error(format!(
+            "An older checkpoint of {} is restored, switch to its tip first",
+            current_branch_name
+        )));
+    }
+    let their_tip = conn
+        .read_branch_tip(branch_name)?
+        .ok_or(DBError::Error(format!("No such branch: {}", branch_name)))?;
+
+    let base = find_merge_base(&conn, &our_tip, &their_tip)?
+        .ok_or(DBError::Consistency("No common ancestor found".to_owned()))?;
+
+    if base == their_tip {
+        return Ok(MergeOutcome::UpToDate);
+    }
+
+    ensure_no_uncommitted_changes(&conn, file_path)?;
+
+    if base == our_tip {
+        write_blend_blocks(file_path, &read_blend_blocks(&conn, &their_tip)?)?;
+
+        conn.execute_in_transaction(|tx| {
+            Persistence::write_branch_tip(tx, &current_branch_name, &their_tip, "merge")?;
+            Persistence::write_current_commit_pointer(tx, &their_tip, "merge")
+        })?;
+
+        return Ok(MergeOutcome::FastForward);
+    }
+
+    let merged = merge_blend_blocks(
+        read_blend_blocks(&conn, &base)?,
+        read_blend_blocks(&conn, &our_tip)?,
+        read_blend_blocks(&conn, &their_tip)?,
+        resolutions,
+    );
+
+    match merged {
+        Ok(merged) => write_blend_blocks(file_path, &merged)?,
+        Err(MergeError::Conflicts(conflicts)) => return Ok(MergeOutcome::Conflicts(conflicts)),
+        Err(MergeError::Unsupported(reason)) => {
+            return Err(DBError::Error(format!("Cannot merge: {}", reason)))
+        }
+    }
+
+    // committed through the same connection, so the tip cannot move in between
+    create_merge_commit(
+        &mut conn,
+        file_path,
+        format!("Merge {} into {}", branch_name, current_branch_name),
+        HashMap::new(),
+        vec![their_tip],
+    )?;
+
+    Ok(MergeOutcome::Merged)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use tempfile::{NamedTempFile, TempDir};
+
+    use crate::{
+        api::{
+            common::read_latest_commit_hash_on_branch, init_command::MAIN_BRANCH_NAME,
+            switch_command::switch_branches, test_utils,
+        },
+        blend::merge::MergeSide,
+        db::db_ops::{Persistence, DB},
+    };
+
+    use super::{merge_branch, MergeOutcome};
+
+    #[test]
+    fn test_merge_branch() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+        let tmp_blend_path = NamedTempFile::new().expect("Cannot create temp file");
+        let tmp_blend_path = tmp_blend_path.path().to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::new_branch(tmp_db_path, "dev");
+        test_utils::commit(tmp_db_path, "Add a torus", "data/untitled_3.blend");
+        switch_branches(tmp_db_path, MAIN_BRANCH_NAME, tmp_blend_path, false).unwrap();
+        std::fs::copy("data/untitled_2.blend", tmp_blend_path).unwrap();
+        test_utils::commit(tmp_db_path, "Change the cube", tmp_blend_path);
+
+        // The cube and the scene changed on both branches
+        let outcome = merge_branch(tmp_blend_path, tmp_db_path, "dev", &HashMap::new()).unwrap();
+        let MergeOutcome::Conflicts(conflicts) = outcome else {
+            panic!("Expected conflicts, got {:?}", outcome);
+        };
+        let conflicting: Vec<&str> = conflicts.iter().map(|c| c.datablock.as_str()).collect();
+        assert_eq!(conflicting, vec!["SCScene", "OBCube", "MECube"]);
+        assert_eq!(
+            test_utils::list_checkpoints(tmp_db_path, MAIN_BRANCH_NAME).len(),
+            2
+        );
+
+        let resolutions = HashMap::from([
+            ("SCScene".to_owned(), MergeSide::Ours),
+            ("OBCube".to_owned(), MergeSide::Theirs),
+            ("MECube".to_owned(), MergeSide::Theirs),
+        ]);
+        let outcome = merge_branch(tmp_blend_path, tmp_db_path, "dev", &resolutions).unwrap();
+        assert_eq!(outcome, MergeOutcome::Merged);
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        let dev_tip = db.read_branch_tip("dev").unwrap().unwrap();
+
+        // The log of `main` follows both parents of the merge commit
+        let checkpoints = test_utils::list_checkpoints(tmp_db_path, MAIN_BRANCH_NAME);
+        assert_eq!(checkpoints.len(), 4);
+        assert_eq!(checkpoints[0].message, "Merge dev into main");
+        assert!(checkpoints.iter().any(|commit| commit.hash == dev_tip));
+
+        let merge_commit = db.read_commit(&checkpoints[0].hash).unwrap().unwrap();
+        assert_eq!(merge_commit.merge_parents, vec![dev_tip]);
+        drop(db);
+
+        // The merged branch is now part of `main`
+        let outcome = merge_branch(tmp_blend_path, tmp_db_path, "dev", &HashMap::new()).unwrap();
+        assert_eq!(outcome, MergeOutcome::UpToDate);
+    }
+
+    #[test]
+    fn test_fast_forward() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+        let tmp_blend_path = NamedTempFile::new().expect("Cannot create temp file");
+        let tmp_blend_path = tmp_blend_path.path().to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::new_branch(tmp_db_path, "dev");
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
+        switch_branches(tmp_db_path, MAIN_BRANCH_NAME, tmp_blend_path, false).unwrap();
+
+        // changes that are not committed are not overwritten
+        std::fs::copy("data/untitled_3.blend", tmp_blend_path).unwrap();
+        assert!(merge_branch(tmp_blend_path, tmp_db_path, "dev", &HashMap::new()).is_err());
+        switch_branches(tmp_db_path, MAIN_BRANCH_NAME, tmp_blend_path, false).unwrap();
+
+        let outcome = merge_branch(tmp_blend_path, tmp_db_path, "dev", &HashMap::new()).unwrap();
+        assert_eq!(outcome, MergeOutcome::FastForward);
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        assert_eq!(
+            read_latest_commit_hash_on_branch(&db, MAIN_BRANCH_NAME).unwrap(),
+            db.read_branch_tip("dev").unwrap().unwrap()
+        );
+        drop(db);
+
+        let outcome = merge_branch(tmp_blend_path, tmp_db_path, "dev", &HashMap::new()).unwrap();
+        assert_eq!(outcome, MergeOutcome::UpToDate);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::db::{
+    db_ops::{DBError, Persistence, ShortCommitRecord, DB},
+    structs::CommitMetadata,
+};
+
+use super::common::resolve_commit_hash;
+
+/// Set on every new commit, to the version of Blender that saved the file
+pub const BLENDER_VERSION_KEY: &str = "blender_version";
+
+/// Set on a new commit that would otherwise get the hash of an existing one,
+/// like the same file committed twice on top of the same checkpoint
+pub const NONCE_KEY: &str = "nonce";
+
+/// Commits with this set to `true` are never pruned
+pub const PROTECTED_KEY: &str = "protected";
+
+/// Sets `key` to `value` on an existing commit, overwriting the previous value.
+/// Entries the commit was made with are covered by its hash, and cannot be changed.
+pub fn set_commit_metadata(
+    db_path: &str,
+    hash: &str,
+    key: &str,
+    value: &str,
+) -> Result<(), DBError> {
+    let mut db = Persistence::open(db_path)?;
+    let hash = resolve_commit_hash(&db, hash)?;
+
+    if !db.commit_exists(&hash)? {
+        return Err(DBError::Error(format!("No such commit: {}", hash)));
+    }
+
+    let hashed = db
+        .read_commit_metadata(&hash)?
+        .into_iter()
+        .any(|entry| entry.hashed && entry.key == key);
+
+    if hashed {
+        return Err(DBError::Error(format!(
+            "{} was set when {} was committed and cannot be changed",
+            key, hash
+        )));
+    }
+
+    db.execute_in_transaction(|tx| {
+        Persistence::write_commit_metadata(
+            tx,
+            &CommitMetadata {
+                hash,
+                key: key.to_owned(),
+                value: value.to_owned(),
+                hashed: false,
+            },
+        )
+    })
+}
+
+pub fn read_commit_metadata(db_path: &str, hash: &str) -> Result<Vec<CommitMetadata>, DBError> {
+    let db = Persistence::open_read_only(db_path)?;
+    let hash = resolve_commit_hash(&db, hash)?;
+    db.read_commit_metadata(&hash)
+}
+
+/// Lists the commits where `key` is set to `value`, oldest first
+pub fn find_commits_by_metadata(
+    db_path: &str,
+    key: &str,
+    value: &str,
+) -> Result<Vec<ShortCommitRecord>, DBError> {
+    Persistence::open_read_only(db_path).and_then(|db| db.read_commits_with_metadata(key, value))
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use crate::{
+        api::{common::hashed_metadata, test_utils},
+        db::db_ops::{Persistence, DB},
+    };
+
+    use super::{find_commits_by_metadata, read_commit_metadata, set_commit_metadata};
+
+    #[test]
+    fn test_set_and_find_metadata() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
+
+        let first = "35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb";
+        let second = "7d4ef4ea6028e3baf19af1a81b3f0046c3a598cfb2d741a99068cb9e876fa64e";
+
+        set_commit_metadata(tmp_db_path, first, "ticket", "PRJ-1").unwrap();
+        set_commit_metadata(tmp_db_path, second, "ticket", "PRJ-2").unwrap();
+        set_commit_metadata(tmp_db_path, second, "ticket", "PRJ-1").unwrap();
+
+        assert!(set_commit_metadata(tmp_db_path, "not-a-commit", "ticket", "PRJ-1").is_err());
+
+        // later values overwrite earlier ones
+        let metadata = read_commit_metadata(tmp_db_path, second).unwrap();
+        let ticket = metadata.iter().find(|entry| entry.key == "ticket").unwrap();
+        assert_eq!(ticket.value, "PRJ-1");
+
+        let hashes: Vec<String> = find_commits_by_metadata(tmp_db_path, "ticket", "PRJ-1")
+            .unwrap()
+            .into_iter()
+            .map(|commit| commit.hash)
+            .collect();
+        assert_eq!(hashes, vec![first, second]);
+
+        // every commit records the Blender version
+        let hashes = find_commits_by_metadata(tmp_db_path, "blender_version", "3.3").unwrap();
+        assert_eq!(hashes.len(), 2);
+
+        // entries the commit was made with are part of its hash
+        assert!(set_commit_metadata(tmp_db_path, second, "blender_version", "4.0").is_err());
+
+        let db = Persistence::open_read_only(tmp_db_path).unwrap();
+        let commit = db.read_commit(second).unwrap().unwrap();
+        let metadata = db.read_commit_metadata(second).unwrap();
+        assert_eq!(
+            commit.compute_hash(
+                &hashed_metadata(&metadata),
+                db.read_hash_algorithm().unwrap()
+            ),
+            second
+        );
+    }
+}
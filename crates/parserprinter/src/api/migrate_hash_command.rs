@@ -0,0 +1,291 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    iter,
+};
+
+use flate2::write::GzDecoder;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    db::{
+        db_ops::{DBError, Persistence, DB},
+        structs::{hash_list, BlockRecord, Commit, HashAlgorithm, Intent},
+    },
+    measure_time,
+    printer_parser::printerparser::PrinterParser,
+};
+
+use super::common::hashed_metadata;
+
+/// How many blocks are moved to their new hashes at once
+const MOVED_BLOCKS_BATCH_SIZE: usize = 4096;
+
+/// Rehashes every block and commit of the timeline with `hash_algorithm`.
+///
+/// The old commit hashes are kept as aliases, so they can still be used to
+/// refer to the same commits. Timelines that are synced with each other should
+/// all be migrated, since the new hashes are unknown to the other parties.
+pub fn migrate_hash_algorithm(db_path: &str, hash_algorithm: HashAlgorithm) -> Result<(), DBError> {
+    let mut db = Persistence::open(db_path)?;
+
+    if db.read_hash_algorithm()? == hash_algorithm {
+        return Ok(());
+    }
+
+    let commits = db.read_all_commits()?;
+
+    let mut new_block_hashes: HashMap<String, String> = HashMap::new();
+    let mut rehashed_commits: Vec<(Commit, String, String)> = vec![];
+
+    for commit in commits {
+        let block_hashes = hash_list()
+            .parse(&commit.blocks, &mut ())
+            .map_err(|_| DBError::Fundamental("Cannot parse blocks".to_owned()))?
+            .1;
+
+        let unseen_block_hashes: Vec<String> = block_hashes
+            .iter()
+            .filter(|hash| !new_block_hashes.contains_key(*hash))
+            .cloned()
+            .collect::<HashSet<String>>()
+            .into_iter()
+            .collect();
+
+        let rehashed_blocks = measure_time!(format!("Rehashing blocks of {:?}", commit.hash), {
+            db.read_blocks(unseen_block_hashes.clone())?
+                .par_iter()
+                .map(|record| {
+                    let mut deflater = GzDecoder::new(Vec::new());
+                    deflater
+                        .write_all(&record.data)
+                        .and_then(|_| deflater.finish())
+                        .map(|block_blob| hash_algorithm.hash(&block_blob))
+                        .map_err(|e| DBError::Fundamental(format!("Cannot decode block: {:?}", e)))
+                })
+                .collect::<Result<Vec<String>, DBError>>()
+        })?;
+
+        for (old_hash, new_hash) in unseen_block_hashes.into_iter().zip(rehashed_blocks) {
+            new_block_hashes.insert(old_hash, new_hash);
+        }
+
+        let rehashed_block_hashes: Vec<String> = block_hashes
+            .iter()
+            .map(|hash| new_block_hashes[hash].clone())
+            .collect();
+
+        let blocks_str = hash_list()
+            .print(&rehashed_block_hashes, &mut ())
+            .map_err(|_| DBError::Fundamental("Cannot print hash list".to_owned()))?;
+
+        let snapshot = hash_algorithm.hash(blocks_str.as_bytes());
+        rehashed_commits.push((commit, snapshot, blocks_str));
+    }
+
+    let known_hashes: HashSet<String> = rehashed_commits
+        .iter()
+        .map(|(commit, _, _)| commit.hash.clone())
+        .collect();
+    let mut renamed: HashMap<String, String> = HashMap::new();
+    let mut new_commit_hashes: Vec<(String, String, String, String)> = vec![];
+
+    // the hash of a commit covers the hashes of its parents, so the parents
+    // are rehashed first
+    while !rehashed_commits.is_empty() {
+        let (ready, waiting): (Vec<_>, Vec<_>) =
+            rehashed_commits.into_iter().partition(|(commit, _, _)| {
+                iter::once(&commit.prev_commit_hash)
+                    .chain(commit.merge_parents.iter())
+                    .all(|parent| !known_hashes.contains(parent) || renamed.contains_key(parent))
+            });
+
+        if ready.is_empty() {
+            return Err(DBError::Consistency(
+                "The history of the commits has a cycle".to_owned(),
+            ));
+        }
+
+        for (commit, snapshot, blocks_str) in ready {
+            let metadata = hashed_metadata(&db.read_commit_metadata(&commit.hash)?);
+            let rename = |hash: &String| renamed.get(hash).unwrap_or(hash).clone();
+            let rehashed = Commit {
+                snapshot: snapshot.clone(),
+                prev_commit_hash: rename(&commit.prev_commit_hash),
+                merge_parents: commit.merge_parents.iter().map(rename).collect(),
+                ..commit
+            };
+            let new_commit_hash = rehashed.compute_hash(&metadata, hash_algorithm);
+
+            renamed.insert(rehashed.hash.clone(), new_commit_hash.clone());
+            new_commit_hashes.push((rehashed.hash, new_commit_hash, snapshot, blocks_str));
+        }
+
+        rehashed_commits = waiting;
+    }
+
+    let (old_block_hashes, new_block_hashes): (Vec<String>, Vec<String>) =
+        new_block_hashes.into_iter().unzip();
+
+    // if the process stops before the hashes are switched, everything stored
+    // under the new hashes is rolled back on the next open
+    let intent = Intent::new(
+        "migrate_hash",
+        new_commit_hashes
+            .iter()
+            .map(|(_, new_hash, _, _)| new_hash.clone())
+            .collect(),
+        db.read_missing_block_hashes(&new_block_hashes)?,
+    );
+
+    db.execute_in_transaction(|tx| Persistence::write_intent(tx, &intent))?;
+
+    // the compressed data of the blocks stays the same, only their keys change
+    for (old_hashes, new_hashes) in old_block_hashes
+        .chunks(MOVED_BLOCKS_BATCH_SIZE)
+        .zip(new_block_hashes.chunks(MOVED_BLOCKS_BATCH_SIZE))
+    {
+        let moved: Vec<BlockRecord> = db
+            .read_blocks(old_hashes.to_vec())?
+            .into_iter()
+            .zip(new_hashes)
+            .map(|(record, new_hash)| BlockRecord {
+                hash: new_hash.clone(),
+                data: record.data,
+            })
+            .collect();
+        db.write_blocks(&moved)?;
+    }
+
+    for (_, new_hash, _, blocks_str) in new_commit_hashes.iter() {
+        db.write_blocks_str(new_hash, blocks_str)?;
+    }
+    db.sync_blocks()?;
+
+    // nothing refers to the data stored under the old hashes once they are
+    // switched. Deleting it is queued like an unfinished operation, so that
+    // it is retried on the next open if the process stops halfway.
+    let cleanup = Intent::new(
+        "migrate_hash_cleanup",
+        new_commit_hashes
+            .iter()
+            .map(|(old_hash, _, _, _)| old_hash.clone())
+            .collect(),
+        old_block_hashes,
+    );
+
+    db.execute_in_transaction(|tx| {
+        for (old_hash, new_hash, snapshot, _) in new_commit_hashes.iter() {
+            Persistence::rename_commit_hash(tx, old_hash, new_hash)?;
+            Persistence::write_commit_snapshot(tx, new_hash, snapshot)?;
+        }
+
+        Persistence::write_hash_algorithm(tx, hash_algorithm)?;
+        Persistence::write_intent(tx, &cleanup)?;
+        Persistence::delete_intent(tx, &intent.id)
+    })?;
+
+    db.delete_blocks(&cleanup.block_hashes)?;
+    for hash in cleanup.commit_hashes.iter() {
+        db.delete_blocks_str(hash)?;
+    }
+    db.sync_blocks()?;
+
+    db.execute_in_transaction(|tx| Persistence::delete_intent(tx, &cleanup.id))
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::{NamedTempFile, TempDir};
+
+    use crate::{
+        api::{
+            common::{
+                blend_file_data_from_file, read_latest_commit_hash_on_branch, resolve_commit_hash,
+            },
+            init_command::{init_db, MAIN_BRANCH_NAME},
+            restore_command::restore_checkpoint,
+            test_utils,
+        },
+        db::{
+            db_ops::{Persistence, DB},
+            structs::HashAlgorithm,
+        },
+    };
+
+    use super::migrate_hash_algorithm;
+
+    #[test]
+    fn test_migrate_md5_timeline() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        init_db(
+            tmp_db_path,
+            "my-cool-project",
+            "data/untitled.blend",
+            HashAlgorithm::Md5,
+        )
+        .expect("Cannot init DB");
+
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
+        test_utils::commit(tmp_db_path, "Commit 2", "data/untitled_3.blend");
+
+        let (old_tip, old_previous) = {
+            let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+            let tip = read_latest_commit_hash_on_branch(&db, MAIN_BRANCH_NAME).unwrap();
+            let previous = db.read_commit(&tip).unwrap().unwrap().prev_commit_hash;
+            (tip, previous)
+        };
+        assert_eq!(old_tip.len(), 32);
+
+        migrate_hash_algorithm(tmp_db_path, HashAlgorithm::Blake3).expect("Cannot migrate");
+
+        {
+            let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+
+            assert_eq!(db.read_hash_algorithm().unwrap(), HashAlgorithm::Blake3);
+
+            // the commits are rehashed
+            let main_tip = read_latest_commit_hash_on_branch(&db, MAIN_BRANCH_NAME).unwrap();
+            assert_eq!(main_tip.len(), 64);
+
+            // the old hashes resolve to the new ones
+            let resolved = resolve_commit_hash(&db, &old_tip).unwrap();
+            assert_eq!(resolved, main_tip);
+
+            let main_tip_commit = db.read_commit(&main_tip).unwrap().unwrap();
+            let previous = resolve_commit_hash(&db, &old_previous).unwrap();
+            assert_eq!(main_tip_commit.prev_commit_hash, previous);
+
+            // the data under the old hashes is cleaned up
+            assert_eq!(db.read_intents().unwrap(), vec![]);
+            assert!(db.read_commit(&old_tip).is_err());
+        }
+
+        let checkpoints = test_utils::list_checkpoints(tmp_db_path, MAIN_BRANCH_NAME);
+        assert_eq!(checkpoints.len(), 3);
+
+        // old hashes can still be restored
+        let tmp_blend_path = NamedTempFile::new().expect("Cannot create temp file");
+        restore_checkpoint(
+            tmp_blend_path.path().to_str().unwrap(),
+            tmp_db_path,
+            &old_previous,
+            true,
+        )
+        .expect("Cannot restore checkpoint");
+
+        // hashing the restored file gives the snapshot of the migrated commit
+        let blend_data = blend_file_data_from_file(
+            tmp_blend_path.path().to_str().unwrap(),
+            HashAlgorithm::Blake3,
+        )
+        .expect("Cannot read restored file");
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        let previous = resolve_commit_hash(&db, &old_previous).unwrap();
+        let previous_commit = db.read_commit(&previous).unwrap().unwrap();
+        assert_eq!(blend_data.snapshot, previous_commit.snapshot);
+    }
+}
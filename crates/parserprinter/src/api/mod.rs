@@ -1,19 +1,36 @@
+pub mod amend_command;
+pub mod cherry_pick_command;
 pub mod commit_command;
 pub mod delete_branch;
+pub mod export_branch_command;
 pub mod export_descendants_of_commit;
 pub mod get_current_branch;
 pub mod get_latest_commit;
+pub mod history_command;
 pub mod import_exchange;
+pub mod import_versions_command;
 pub mod init_command;
 pub mod init_from_import_command;
 pub mod list_branches_command;
 pub mod log_checkpoints_command;
+pub mod merge_command;
+pub mod metadata_command;
+pub mod migrate_hash_command;
 pub mod new_branch_command;
 pub mod prepare_sync;
+pub mod prune_command;
+pub mod recover_command;
+pub mod ref_log_command;
+pub mod rename_branch_command;
 pub mod restore_command;
+pub mod search_command;
+pub mod squash_command;
+pub mod status_command;
 pub mod switch_command;
+pub mod tag_command;
 pub mod test_command;
 pub mod utils;
+pub mod watch_command;
 
 pub mod test_utils;
 
@@ -1,29 +1,84 @@
-use crate::db::db_ops::{DBError, Persistence, DB};
+use crate::db::{
+    db_ops::{DBError, Persistence, DB},
+    structs::BranchInfo,
+};
 
-use super::{common::read_latest_commit_hash_on_branch, init_command::MAIN_BRANCH_NAME};
+use super::{
+    common::{read_current_commit_hash, resolve_commit_hash},
+    restore_command::write_commit_to_file,
+    utils::timestamp,
+};
 
-pub fn create_new_branch(db_path: &str, new_branch_name: &str) -> Result<(), DBError> {
+/// Creates a branch starting from `from`, which can be the name of a branch or
+/// anything that `restore_checkpoint` accepts, and makes it the current branch.
+/// Without `from`, the branch starts from the current commit.
+///
+/// If the branch starts from another commit than the current one, that commit
+/// is restored to `file_path`, so that the next commit on the branch is made
+/// from it. Otherwise the file is not touched, and `file_path` is not needed.
+/// With `verify`, the restored file is checked against the commit, see
+/// `write_commit_to_file`.
+pub fn create_new_branch(
+    db_path: &str,
+    new_branch_name: &str,
+    from: Option<&str>,
+    description: Option<&str>,
+    file_path: Option<&str>,
+    verify: bool,
+) -> Result<(), DBError> {
     let mut db = Persistence::open(db_path)?;
 
-    let current_brach_name = db.read_current_branch_name()?;
+    if db.read_branch_tip(new_branch_name)?.is_some() {
+        return Err(DBError::Error(format!(
+            "Branch {} already exists",
+            new_branch_name
+        )));
+    }
+
+    let base = match from {
+        None => read_current_commit_hash(&db)?,
+        Some(from) => match db.read_branch_tip(from)? {
+            Some(tip) => tip,
+            None => resolve_commit_hash(&db, from)?,
+        },
+    };
 
-    if current_brach_name != MAIN_BRANCH_NAME {
-        return Err(DBError::Error(
-            "New branches can only be created if main is the current branch".to_owned(),
-        ));
+    if !db.commit_exists(&base)? {
+        return Err(DBError::Error(format!("No such commit: {}", base)));
     }
 
-    let tip = read_latest_commit_hash_on_branch(&db, &current_brach_name)?;
+    let is_current = base == read_current_commit_hash(&db)?;
+    let restore_path = match (is_current, file_path) {
+        (true, _) => None,
+        (false, Some(file_path)) => Some(file_path),
+        (false, None) => {
+            return Err(DBError::Error(format!(
+                "{} has to be restored to start a branch from it, but no file was given",
+                base
+            )))
+        }
+    };
 
-    db.execute_in_transaction(|tx| {
-        Persistence::write_branch_tip(tx, new_branch_name, &tip)?;
-        Persistence::write_remote_branch_tip(tx, new_branch_name, &tip)?;
+    let info = BranchInfo {
+        name: new_branch_name.to_owned(),
+        base: base.clone(),
+        created: timestamp(),
+        description: description.map(|d| d.to_owned()),
+    };
 
-        Persistence::write_current_branch_name(tx, new_branch_name)?;
-        Ok(())
-    })?;
+    // the file is written first, so a branch is only created once it can be
+    // committed to
+    if let Some(file_path) = restore_path {
+        write_commit_to_file(&db, &base, file_path, verify)?;
+    }
 
-    Ok(())
+    db.execute_in_transaction(|tx| {
+        Persistence::write_branch_tip(tx, new_branch_name, &base, "new_branch")?;
+        Persistence::write_remote_branch_tip(tx, new_branch_name, &base)?;
+        Persistence::write_branch_info(tx, &info)?;
+        Persistence::write_current_branch_name(tx, new_branch_name, "new_branch")?;
+        Persistence::write_current_commit_pointer(tx, &base, "new_branch")
+    })
 }
 
 #[cfg(test)]
@@ -31,7 +86,10 @@ mod test {
     use tempfile::TempDir;
 
     use crate::{
-        api::{common::read_latest_commit_hash_on_branch, test_utils},
+        api::{
+            common::read_latest_commit_hash_on_branch, status_command::working_file_status,
+            test_utils,
+        },
         db::db_ops::{Persistence, DB},
     };
 
@@ -44,7 +102,7 @@ mod test {
 
         test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
 
-        create_new_branch(tmp_db_path, "dev").unwrap();
+        create_new_branch(tmp_db_path, "dev", None, None, None, false).unwrap();
 
         assert_eq!(test_utils::list_checkpoints(tmp_db_path, "dev").len(), 1);
 
@@ -64,7 +122,10 @@ mod test {
             .expect("Cannot read latest commit");
 
         // the latest commit hash stays the same
-        assert_eq!(latest_commit_name, "a5f92d0a988085ed66c9dcdccc7b9c90");
+        assert_eq!(
+            latest_commit_name,
+            "35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb"
+        );
     }
 
     #[test]
@@ -77,7 +138,7 @@ mod test {
         // a commit to `main`
         test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
 
-        create_new_branch(tmp_db_path, "dev").unwrap();
+        create_new_branch(tmp_db_path, "dev", None, None, None, false).unwrap();
 
         // a commit to `dev`
         test_utils::commit(tmp_db_path, "Commit 2", "data/untitled_3.blend");
@@ -93,4 +154,109 @@ mod test {
         assert_eq!(commits.get(1).unwrap().message, "Commit");
         assert_eq!(commits.get(2).unwrap().branch, "main");
     }
+
+    #[test]
+    fn test_branch_from_old_commit() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().join("db");
+        let tmp_db_path = tmp_db_path.to_str().unwrap();
+        let tmp_blend_path = tmp_dir.path().join("working.blend");
+        let tmp_blend_path = tmp_blend_path.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
+        test_utils::new_branch(tmp_db_path, "dev");
+
+        // the starting point has to be restored somewhere
+        assert!(create_new_branch(
+            tmp_db_path,
+            "tuesday",
+            Some("35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb"),
+            None,
+            None,
+            false,
+        )
+        .is_err());
+        assert!(Persistence::open_read_only(tmp_db_path)
+            .unwrap()
+            .read_branch_tip("tuesday")
+            .unwrap()
+            .is_none());
+
+        // branches can start from anywhere, not just from `main`
+        create_new_branch(
+            tmp_db_path,
+            "tuesday",
+            Some("35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb"),
+            Some("Try the version from Tuesday"),
+            Some(tmp_blend_path),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            test_utils::list_checkpoints(tmp_db_path, "tuesday").len(),
+            1
+        );
+
+        // the next commit is made from the starting point of the branch
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        assert_eq!(
+            db.read_current_commit_pointer().unwrap(),
+            "35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb"
+        );
+        drop(db);
+        assert!(
+            !working_file_status(tmp_blend_path, tmp_db_path)
+                .unwrap()
+                .dirty
+        );
+
+        // nothing is created if the starting point cannot be restored
+        let unwritable_path = tmp_dir.path().join("missing").join("working.blend");
+        assert!(create_new_branch(
+            tmp_db_path,
+            "dev-2",
+            Some("dev"),
+            None,
+            Some(unwritable_path.to_str().unwrap()),
+            false,
+        )
+        .is_err());
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        assert!(db.read_branch_tip("dev-2").unwrap().is_none());
+        assert!(db.read_branch_info("dev-2").unwrap().is_none());
+        assert_eq!(db.read_current_branch_name().unwrap(), "tuesday");
+        drop(db);
+
+        // or from other branches
+        create_new_branch(
+            tmp_db_path,
+            "dev-2",
+            Some("dev"),
+            None,
+            Some(tmp_blend_path),
+            false,
+        )
+        .unwrap();
+        assert_eq!(test_utils::list_checkpoints(tmp_db_path, "dev-2").len(), 2);
+
+        assert!(create_new_branch(tmp_db_path, "dev", None, None, None, false).is_err());
+        assert!(
+            create_new_branch(tmp_db_path, "nope", Some("not-a-commit"), None, None, false)
+                .is_err()
+        );
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        let info = db.read_branch_info("tuesday").unwrap().unwrap();
+        assert_eq!(
+            info.base,
+            "35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb"
+        );
+        assert_eq!(
+            info.description.as_deref(),
+            Some("Try the version from Tuesday")
+        );
+        assert_eq!(db.read_current_branch_name().unwrap(), "dev-2");
+    }
 }
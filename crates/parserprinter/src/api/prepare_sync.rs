@@ -10,7 +10,7 @@ use crate::{
 };
 
 pub fn prepare_sync(db_path: &str) -> Result<Sync, DBError> {
-    let db = Persistence::open(db_path)?;
+    let db = Persistence::open_read_only(db_path)?;
 
     let branches = db.read_all_branches()?;
     let mut local_tips: Vec<String> = vec![];
@@ -25,12 +25,17 @@ pub fn prepare_sync(db_path: &str) -> Result<Sync, DBError> {
     }
 
     let mut all_commits: Vec<Commit> = vec![];
+    let mut commit_hashes: HashSet<String> = HashSet::new();
     let mut block_hashes: HashSet<String> = HashSet::new();
 
     for hash in remote_tips {
         let commits = db.read_descendants_of_commit(&hash)?;
 
-        for commit in commits.into_iter() {
+        // branches that share history, or were merged, have common descendants
+        for commit in commits
+            .into_iter()
+            .filter(|commit| commit_hashes.insert(commit.hash.clone()))
+        {
             let blocks_of_this_commit = hash_list()
                 .parse(&commit.blocks, &mut ())
                 .expect("Corrupted hash list")
@@ -45,6 +50,11 @@ pub fn prepare_sync(db_path: &str) -> Result<Sync, DBError> {
         }
     }
 
+    let mut all_metadata = vec![];
+    for commit in all_commits.iter() {
+        all_metadata.extend(db.read_commit_metadata(&commit.hash)?);
+    }
+
     let mut all_blocks: HashMap<String, BlockRecord> = HashMap::new();
     for block in db.read_blocks(block_hashes.into_iter().collect())? {
         all_blocks.insert(block.hash.clone(), block);
@@ -55,8 +65,13 @@ pub fn prepare_sync(db_path: &str) -> Result<Sync, DBError> {
     Ok(Sync {
         local_tips,
         exchange: Exchange {
+            hash_algorithm: db.read_hash_algorithm()?,
             commits: all_commits,
             blocks: all_blocks_vec,
+            // tags are few, so all of them are sent
+            tags: db.read_all_tags()?,
+            metadata: all_metadata,
+            branch_renames: db.read_branch_renames()?,
         },
     })
 }
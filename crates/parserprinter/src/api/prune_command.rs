@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    api::{
+        common::{read_current_commit_hash, rehash_commits, rewrite_history, HistoryRewrite},
+        metadata_command::PROTECTED_KEY,
+        utils::timestamp,
+    },
+    db::{
+        db_ops::{DBError, Persistence, DB},
+        structs::{Commit, RetentionPolicy},
+    },
+};
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+
+pub fn set_retention_policy(
+    db_path: &str,
+    branch_name: &str,
+    policy: RetentionPolicy,
+) -> Result<(), DBError> {
+    let mut db = Persistence::open(db_path)?;
+
+    if db.read_branch_tip(branch_name)?.is_none() {
+        return Err(DBError::Error(format!("No such branch: {}", branch_name)));
+    }
+
+    db.execute_in_transaction(|tx| Persistence::write_retention_policy(tx, branch_name, &policy))
+}
+
+pub fn get_retention_policy(
+    db_path: &str,
+    branch_name: &str,
+) -> Result<Option<RetentionPolicy>, DBError> {
+    Persistence::open_read_only(db_path).and_then(|db| db.read_retention_policy(branch_name))
+}
+
+/// The hashes of the commits `policy` keeps at `now`, from `commits` ordered
+/// newest first
+fn retained_by_policy(commits: &[Commit], policy: &RetentionPolicy, now: u64) -> HashSet<String> {
+    let mut seen_periods: HashSet<(u64, u64)> = HashSet::new();
+
+    commits
+        .iter()
+        .filter(|commit| {
+            let age = now.saturating_sub(commit.date);
+            if age < policy.keep_all_for {
+                return true;
+            }
+
+            let period_len = if age < policy.keep_hourly_for {
+                HOUR
+            } else {
+                DAY
+            };
+            seen_periods.insert((period_len, commit.date / period_len))
+        })
+        .map(|commit| commit.hash.clone())
+        .collect()
+}
+
+fn parents_of(commit: &Commit) -> impl Iterator<Item = &String> {
+    std::iter::once(&commit.prev_commit_hash).chain(commit.merge_parents.iter())
+}
+
+/// Removes the checkpoints of `branch_name` that its retention policy does not
+/// keep, and returns their hashes. The commits after them are linked to the
+/// commits before them instead, and get new hashes. Blocks are left alone,
+/// since other commits can share them.
+///
+/// Whatever the policy says, the tip, merges, tagged or protected commits,
+/// the current commit, commits that other commits or branches are built on and
+/// commits that were already shared with other timelines are kept.
+pub fn prune_branch(db_path: &str, branch_name: &str) -> Result<Vec<String>, DBError> {
+    let mut db = Persistence::open(db_path)?;
+
+    let policy = db
+        .read_retention_policy(branch_name)?
+        .ok_or(DBError::Error(format!(
+            "No retention policy is set for {}",
+            branch_name
+        )))?;
+
+    let tip = db
+        .read_branch_tip(branch_name)?
+        .ok_or(DBError::Error(format!("No such branch: {}", branch_name)))?;
+
+    // the commits of the branch, newest first
+    let mut commits: Vec<Commit> = vec![];
+    let mut hash = tip.clone();
+    while db.commit_exists(&hash)? {
+        let commit = db
+            .read_commit(&hash)?
+            .ok_or(DBError::Consistency(format!("No such commit: {}", hash)))?;
+        if commit.branch != branch_name {
+            break;
+        }
+        hash = commit.prev_commit_hash.clone();
+        commits.push(commit);
+    }
+
+    let Some(oldest) = commits.last() else {
+        return Ok(vec![]);
+    };
+
+    let descendants = db.read_descendants_of_commit(&oldest.hash)?;
+    let mut children: HashMap<String, usize> = HashMap::new();
+    for commit in descendants.iter() {
+        for parent in parents_of(commit) {
+            *children.entry(parent.clone()).or_default() += 1;
+        }
+    }
+
+    let mut kept = retained_by_policy(&commits, &policy, timestamp());
+    kept.insert(tip.clone());
+    kept.insert(read_current_commit_hash(&db)?);
+    kept.extend(db.read_all_tags()?.into_iter().map(|tag| tag.hash));
+
+    // other timelines have everything up to the remote tips, and removing any
+    // of it would give the remote tips hashes they do not know
+    let mut shared: Vec<String> = vec![];
+    for other_branch in db.read_all_branches()? {
+        kept.extend(db.read_branch_tip(&other_branch)?);
+        shared.extend(db.read_remote_branch_tip(&other_branch).ok());
+    }
+    let by_hash: HashMap<&str, &Commit> = descendants
+        .iter()
+        .map(|commit| (commit.hash.as_str(), commit))
+        .collect();
+    let mut seen: HashSet<String> = HashSet::new();
+    while let Some(hash) = shared.pop() {
+        if let Some(commit) = by_hash.get(hash.as_str()) {
+            if seen.insert(hash.clone()) {
+                shared.extend(parents_of(commit).cloned());
+            }
+        }
+    }
+    kept.extend(seen);
+
+    for commit in commits.iter() {
+        let is_protected = db
+            .read_commit_metadata(&commit.hash)?
+            .iter()
+            .any(|entry| entry.key == PROTECTED_KEY && entry.value == "true");
+        let is_root = !db.commit_exists(&commit.prev_commit_hash)?;
+        let has_other_children = children.get(&commit.hash).copied().unwrap_or(0) > 1;
+
+        if is_protected || is_root || has_other_children || !commit.merge_parents.is_empty() {
+            kept.insert(commit.hash.clone());
+        }
+    }
+
+    // what the kept commits are linked to instead of a removed parent
+    let mut renamed: HashMap<String, String> = HashMap::new();
+    let mut removed: Vec<String> = vec![];
+    for commit in commits.iter().rev() {
+        if !kept.contains(&commit.hash) {
+            let parent = renamed
+                .get(&commit.prev_commit_hash)
+                .unwrap_or(&commit.prev_commit_hash)
+                .clone();
+            renamed.insert(commit.hash.clone(), parent);
+            removed.push(commit.hash.clone());
+        }
+    }
+
+    if removed.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // everything built on a removed commit gets a new hash, on any branch
+    let mut rewritten: Vec<Commit> = vec![];
+    let mut rewritten_hashes: HashSet<String> = HashSet::new();
+    for hash in removed.iter() {
+        for commit in db.read_descendants_of_commit(hash)? {
+            if !renamed.contains_key(&commit.hash) && rewritten_hashes.insert(commit.hash.clone()) {
+                rewritten.push(commit);
+            }
+        }
+    }
+
+    let (rehashed, metadata) =
+        rehash_commits(&db, rewritten, &mut renamed, db.read_hash_algorithm()?)?;
+
+    let new_tip = renamed.get(&tip).unwrap_or(&tip).clone();
+
+    let rewrite = HistoryRewrite {
+        removed: removed
+            .iter()
+            .cloned()
+            .chain(rehashed.iter().map(|(old_hash, _)| old_hash.clone()))
+            .collect(),
+        renamed: rehashed
+            .iter()
+            .map(|(old_hash, commit)| (old_hash.clone(), commit.hash.clone()))
+            .collect(),
+        written: rehashed.into_iter().map(|(_, commit)| commit).collect(),
+        metadata,
+    };
+    rewrite_history(&mut db, "prune", branch_name, &new_tip, rewrite)?;
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use crate::{
+        api::{
+            common::read_latest_commit_hash_on_branch,
+            init_command::{INITIAL_COMMIT_HASH, MAIN_BRANCH_NAME},
+            metadata_command::{set_commit_metadata, PROTECTED_KEY},
+            tag_command::create_tag,
+            test_utils::{init_db_from_simple_timeline, SimpleCommit, SimpleTimeline},
+        },
+        db::{
+            db_ops::{Persistence, DB},
+            structs::{Commit, RetentionPolicy},
+        },
+    };
+
+    use super::{prune_branch, retained_by_policy, set_retention_policy, DAY, HOUR};
+
+    fn commit_at(hash: &str, date: u64) -> Commit {
+        Commit {
+            hash: hash.to_owned(),
+            snapshot: hash.to_owned(),
+            prev_commit_hash: String::new(),
+            merge_parents: vec![],
+            project_id: String::new(),
+            branch: MAIN_BRANCH_NAME.to_owned(),
+            message: String::new(),
+            author: String::new(),
+            date,
+            header: vec![],
+            blocks: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_retained_by_policy() {
+        let now = 30 * DAY;
+        let commits = vec![
+            // the last day, everything is kept
+            commit_at("a", now - 10),
+            commit_at("b", now - 20),
+            // the last week, one per hour
+            commit_at("c", now - 3 * DAY - 10),
+            commit_at("d", now - 3 * DAY - 20),
+            commit_at("e", now - 3 * DAY - HOUR - 10),
+            // one per day
+            commit_at("f", 10 * DAY + 2 * HOUR),
+            commit_at("g", 10 * DAY + HOUR),
+            commit_at("h", 9 * DAY + HOUR),
+        ];
+
+        let mut kept: Vec<String> = retained_by_policy(&commits, &RetentionPolicy::default(), now)
+            .into_iter()
+            .collect();
+        kept.sort();
+
+        assert_eq!(kept, vec!["a", "b", "c", "e", "f", "h"]);
+    }
+
+    #[test]
+    fn test_prune_branch() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        let commit = |hash: &str, prev_hash: &str| SimpleCommit {
+            hash: hash.to_owned(),
+            prev_hash: prev_hash.to_owned(),
+            branch: MAIN_BRANCH_NAME.to_owned(),
+            message: format!("Autosave {}", hash),
+            blocks: "aaa".to_owned(),
+        };
+
+        // every commit is from the first day of 1970
+        init_db_from_simple_timeline(
+            tmp_path,
+            SimpleTimeline {
+                project_id: "p1".to_owned(),
+                author: "test".to_owned(),
+                blocks: vec!["aaa".to_owned()],
+                commits: vec![
+                    commit("1", INITIAL_COMMIT_HASH),
+                    commit("2", "1"),
+                    commit("3", "2"),
+                    commit("4", "3"),
+                    commit("5", "4"),
+                    commit("6", "5"),
+                ],
+            },
+        );
+
+        create_tag(tmp_path, "v1", "2").unwrap();
+        set_commit_metadata(tmp_path, "4", PROTECTED_KEY, "true").unwrap();
+
+        assert!(prune_branch(tmp_path, MAIN_BRANCH_NAME).is_err());
+        set_retention_policy(tmp_path, MAIN_BRANCH_NAME, RetentionPolicy::default()).unwrap();
+
+        // The whole branch is shared up to `6`
+        assert!(prune_branch(tmp_path, MAIN_BRANCH_NAME).unwrap().is_empty());
+
+        let mut db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        db.execute_in_transaction(|tx| {
+            Persistence::write_remote_branch_tip(tx, MAIN_BRANCH_NAME, "2")
+        })
+        .unwrap();
+        drop(db);
+
+        let removed = prune_branch(tmp_path, MAIN_BRANCH_NAME).unwrap();
+        assert_eq!(removed, vec!["3", "5"]);
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        let tip = read_latest_commit_hash_on_branch(&db, MAIN_BRANCH_NAME).unwrap();
+        let history: Vec<String> = db
+            .read_ancestors_of_commit(&tip)
+            .unwrap()
+            .into_iter()
+            .map(|commit| commit.message)
+            .collect();
+        assert_eq!(
+            history,
+            vec!["Autosave 6", "Autosave 4", "Autosave 2", "Autosave 1"]
+        );
+
+        // The tagged commit stays as it is
+        assert_eq!(db.read_tag("v1").unwrap(), Some("2".to_owned()));
+        drop(db);
+
+        // Nothing else to remove
+        assert!(prune_branch(tmp_path, MAIN_BRANCH_NAME).unwrap().is_empty());
+    }
+}
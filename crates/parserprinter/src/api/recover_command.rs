@@ -0,0 +1,8 @@
+use crate::db::db_ops::{DBError, Persistence, DB};
+
+/// Opens the timeline for writing, which rolls back the operations that were
+/// interrupted halfway, and returns their names. Every other operation that
+/// writes to the timeline does the same without reporting it.
+pub fn roll_back_unfinished_operations(db_path: &str) -> Result<Vec<String>, DBError> {
+    Persistence::open(db_path).map(|db| db.rolled_back_operations().to_vec())
+}
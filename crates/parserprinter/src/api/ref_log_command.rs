@@ -0,0 +1,245 @@
+use crate::db::{
+    db_ops::{DBError, Persistence, DB},
+    structs::{RefLogEntry, RefLogTarget},
+};
+
+use super::{
+    common::resolve_commit_hash, restore_command::write_commit_to_file,
+    status_command::ensure_no_uncommitted_changes,
+};
+
+/// Newest entries first
+pub fn list_ref_log(db_path: &str, limit: Option<usize>) -> Result<Vec<RefLogEntry>, DBError> {
+    Persistence::open_read_only(db_path).and_then(|db| db.read_ref_log(limit))
+}
+
+/// Undoes what the reflog entry `id` did, by moving its target back to the old
+/// value:
+/// - the current commit: the old commit is restored to `file_path`
+/// - the current branch: the tip of the old branch is restored to `file_path`
+/// - a branch tip: the branch points to the old commit again, which is also
+///   restored to `file_path` if it is the current branch
+///
+/// Refuses to overwrite uncommitted changes in `file_path`. With `verify`, the
+/// written file is checked against the commit, see `write_commit_to_file`. The
+/// jump is recorded in the reflog as well, so it can be undone the same way.
+pub fn jump_back(file_path: &str, db_path: &str, id: i64, verify: bool) -> Result<(), DBError> {
+    let mut db = Persistence::open(db_path)?;
+
+    let entry = db
+        .read_ref_log_entry(id)?
+        .ok_or(DBError::Error(format!("No such reflog entry: {}", id)))?;
+
+    let old_value = entry.old_value.ok_or(DBError::Error(format!(
+        "Reflog entry {} has nothing to go back to",
+        id
+    )))?;
+    // commits that were renamed since are found by their old hash
+    let old_value = match entry.target {
+        RefLogTarget::CurrentBranch => old_value,
+        _ => resolve_commit_hash(&db, &old_value)?,
+    };
+
+    let (hash, branch_name, moved_tip) = match entry.target {
+        RefLogTarget::CurrentCommit => (old_value, None, None),
+        RefLogTarget::CurrentBranch => {
+            let tip = db
+                .read_branch_tip(&old_value)?
+                .ok_or(DBError::Error(format!(
+                    "Branch {} no longer exists",
+                    old_value
+                )))?;
+            (tip, Some(old_value), None)
+        }
+        RefLogTarget::BranchTip(branch_name) => {
+            if !db.commit_exists(&old_value)? {
+                return Err(DBError::Error(format!(
+                    "Checkpoint {} no longer exists",
+                    old_value
+                )));
+            }
+
+            if db.read_current_branch_name()? != branch_name {
+                return db.execute_in_transaction(|tx| {
+                    Persistence::write_branch_tip(tx, &branch_name, &old_value, "reflog")
+                });
+            }
+            (old_value, Some(branch_name.clone()), Some(branch_name))
+        }
+    };
+
+    ensure_no_uncommitted_changes(&db, file_path)?;
+
+    let commit = write_commit_to_file(&db, &hash, file_path, verify)?;
+
+    db.execute_in_transaction(|tx| {
+        if let Some(branch_name) = &moved_tip {
+            Persistence::write_branch_tip(tx, branch_name, &commit.hash, "reflog")?;
+        }
+        Persistence::write_current_branch_name(
+            tx,
+            branch_name.as_deref().unwrap_or(&commit.branch),
+            "reflog",
+        )?;
+        Persistence::write_current_commit_pointer(tx, &commit.hash, "reflog")?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use crate::{
+        api::{
+            common::read_latest_commit_hash_on_branch, delete_branch::delete_branch,
+            init_command::MAIN_BRANCH_NAME, restore_command::restore_checkpoint,
+            switch_command::switch_branches, test_utils,
+        },
+        db::{
+            db_ops::{Persistence, DB},
+            structs::RefLogTarget,
+        },
+    };
+
+    use super::{jump_back, list_ref_log};
+
+    #[test]
+    fn test_ref_log() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+        let tmp_blend_path = tmp_dir.path().join("working.blend");
+        let tmp_blend_path = tmp_blend_path.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
+        test_utils::commit(tmp_db_path, "Commit 2", "data/untitled_3.blend");
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        let main_tip = read_latest_commit_hash_on_branch(&db, MAIN_BRANCH_NAME).unwrap();
+        let initial_commit = db
+            .read_ancestors_of_commit(&main_tip)
+            .unwrap()
+            .last()
+            .unwrap()
+            .hash
+            .clone();
+        drop(db);
+
+        // Oops, the wrong checkpoint
+        restore_checkpoint(tmp_blend_path, tmp_db_path, &initial_commit, false).unwrap();
+
+        let entries = list_ref_log(tmp_db_path, Some(1)).unwrap();
+        assert_eq!(entries.len(), 1);
+        let restore = &entries[0];
+        assert_eq!(restore.target, RefLogTarget::CurrentCommit);
+        assert_eq!(restore.operation, "restore");
+        assert_eq!(restore.old_value.as_ref(), Some(&main_tip));
+        assert_eq!(restore.new_value.as_ref(), Some(&initial_commit));
+
+        jump_back(tmp_blend_path, tmp_db_path, restore.id, false).unwrap();
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        assert_eq!(db.read_current_commit_pointer().unwrap(), main_tip);
+        let entries = db.read_ref_log(None).unwrap();
+        assert_eq!(entries[0].operation, "reflog");
+        assert!(entries.windows(2).all(|pair| pair[0].id > pair[1].id));
+
+        // Two commits and the initial checkpoint moved the tip of `main`
+        let main_tip_moves = entries
+            .iter()
+            .filter(|entry| entry.target == RefLogTarget::BranchTip(MAIN_BRANCH_NAME.to_owned()))
+            .count();
+        assert_eq!(main_tip_moves, 3);
+    }
+
+    #[test]
+    fn test_jump_back_keeps_uncommitted_changes() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+        let tmp_blend_path = tmp_dir.path().join("working.blend");
+        let tmp_blend_path = tmp_blend_path.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
+
+        let commit = list_ref_log(tmp_db_path, None)
+            .unwrap()
+            .into_iter()
+            .find(|entry| entry.operation == "commit")
+            .unwrap();
+
+        std::fs::copy("data/untitled_3.blend", tmp_blend_path).unwrap();
+        assert!(jump_back(tmp_blend_path, tmp_db_path, commit.id, false).is_err());
+
+        // neither the file nor the tip moved
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        assert_eq!(
+            db.read_branch_tip(MAIN_BRANCH_NAME).unwrap(),
+            commit.new_value
+        );
+        drop(db);
+        assert_eq!(
+            std::fs::read(tmp_blend_path).unwrap(),
+            std::fs::read("data/untitled_3.blend").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_jump_back_branch_tip() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+        let tmp_blend_path = tmp_dir.path().join("working.blend");
+        let tmp_blend_path = tmp_blend_path.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
+        test_utils::new_branch(tmp_db_path, "dev");
+        test_utils::commit(tmp_db_path, "Commit on dev", "data/untitled_3.blend");
+        switch_branches(tmp_db_path, MAIN_BRANCH_NAME, tmp_blend_path, false).unwrap();
+
+        let entries = list_ref_log(tmp_db_path, None).unwrap();
+        let switch = entries
+            .iter()
+            .find(|entry| entry.target == RefLogTarget::CurrentBranch)
+            .unwrap();
+        assert_eq!(switch.operation, "switch");
+        assert_eq!(switch.old_value.as_deref(), Some("dev"));
+        assert_eq!(switch.new_value.as_deref(), Some(MAIN_BRANCH_NAME));
+
+        let dev_commit = entries
+            .iter()
+            .find(|entry| entry.target == RefLogTarget::BranchTip("dev".to_owned()))
+            .unwrap();
+        assert_eq!(dev_commit.operation, "commit");
+
+        // The tip of `dev` goes back to where the branch was created
+        jump_back(tmp_blend_path, tmp_db_path, dev_commit.id, false).unwrap();
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        assert_eq!(db.read_current_branch_name().unwrap(), MAIN_BRANCH_NAME);
+        assert_eq!(
+            db.read_branch_tip("dev").unwrap(),
+            dev_commit.old_value.clone()
+        );
+        drop(db);
+
+        // Deleting the branch is logged too
+        delete_branch(tmp_db_path, "dev").unwrap();
+        let entries = list_ref_log(tmp_db_path, Some(1)).unwrap();
+        assert_eq!(entries[0].operation, "delete_branch");
+        assert_eq!(entries[0].target, RefLogTarget::BranchTip("dev".to_owned()));
+        assert_eq!(entries[0].new_value, None);
+
+        // The branch did not exist before it was created
+        let created = list_ref_log(tmp_db_path, None)
+            .unwrap()
+            .into_iter()
+            .find(|entry| {
+                entry.operation == "new_branch"
+                    && entry.target == RefLogTarget::BranchTip("dev".to_owned())
+            })
+            .unwrap();
+        assert!(jump_back(tmp_blend_path, tmp_db_path, created.id, false).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use crate::db::{
+    db_ops::{DBError, Persistence, DB},
+    structs::BranchRename,
+};
+
+use super::{init_command::MAIN_BRANCH_NAME, utils::timestamp};
+
+/// Renames a branch, along with the branch recorded on its commits. The rename
+/// is sent to other timelines with the next export or sync, see
+/// `import_exchange`.
+pub fn rename_branch(db_path: &str, old_name: &str, new_name: &str) -> Result<(), DBError> {
+    let mut db = Persistence::open(db_path)?;
+
+    if old_name == MAIN_BRANCH_NAME {
+        return Err(DBError::Error("Cannot rename the main branch".to_owned()));
+    }
+
+    if new_name.trim().is_empty() {
+        return Err(DBError::Error("Branch name cannot be empty".to_owned()));
+    }
+
+    if db.read_branch_tip(old_name)?.is_none() {
+        return Err(DBError::Error(format!("No such branch: {}", old_name)));
+    }
+
+    if db.read_branch_tip(new_name)?.is_some() {
+        return Err(DBError::Error(format!(
+            "Branch {} already exists",
+            new_name
+        )));
+    }
+
+    let rename = BranchRename {
+        old_name: old_name.to_owned(),
+        new_name: new_name.to_owned(),
+        date: timestamp(),
+    };
+
+    db.execute_in_transaction(|tx| {
+        Persistence::rename_branch(tx, old_name, new_name)?;
+        Persistence::write_branch_rename(tx, &rename)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use crate::{
+        api::{common::hashed_metadata, init_command::MAIN_BRANCH_NAME, test_utils},
+        db::db_ops::{Persistence, DB},
+    };
+
+    use super::rename_branch;
+
+    #[test]
+    fn test_rename_branch() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::new_branch(tmp_db_path, "lightnig");
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
+
+        rename_branch(tmp_db_path, "lightnig", "lighting").expect("Cannot rename branch");
+
+        let commits = test_utils::list_checkpoints(tmp_db_path, "lighting");
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].branch, "lighting");
+        assert_eq!(commits[1].branch, MAIN_BRANCH_NAME);
+
+        {
+            let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+            assert_eq!(db.read_current_branch_name().unwrap(), "lighting");
+            assert_eq!(db.read_all_branches().unwrap(), vec!["lighting", "main"]);
+            assert!(db.read_branch_info("lighting").unwrap().is_some());
+            assert_eq!(db.read_branch_renames().unwrap().len(), 1);
+            assert_eq!(
+                db.read_remote_branch_tip("lighting").unwrap(),
+                "35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb"
+            );
+
+            // the branch is not part of the hash
+            let renamed = db.read_commit(&commits[0].hash).unwrap().unwrap();
+            let metadata = db.read_commit_metadata(&renamed.hash).unwrap();
+            assert_eq!(
+                renamed.compute_hash(
+                    &hashed_metadata(&metadata),
+                    db.read_hash_algorithm().unwrap()
+                ),
+                renamed.hash
+            );
+        }
+
+        // new commits go to the renamed branch
+        test_utils::commit(tmp_db_path, "Commit 2", "data/untitled_3.blend");
+        assert_eq!(
+            test_utils::list_checkpoints(tmp_db_path, "lighting").len(),
+            3
+        );
+
+        assert!(rename_branch(tmp_db_path, MAIN_BRANCH_NAME, "trunk").is_err());
+        assert!(rename_branch(tmp_db_path, "lighting", MAIN_BRANCH_NAME).is_err());
+        assert!(rename_branch(tmp_db_path, "nope", "other").is_err());
+    }
+}
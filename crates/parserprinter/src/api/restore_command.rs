@@ -1,23 +1,93 @@
-use std::{io::Write, time::Instant};
+use std::{fs, io::Write, path::Path, time::Instant};
 
 use flate2::write::GzDecoder;
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use tempfile::NamedTempFile;
 
 use crate::{
+    api::common::{blend_file_data_from_file, resolve_commit_hash},
     blend::utils::to_file_transactional,
     db::{
         db_ops::{DBError, Persistence, DB},
-        structs::hash_list,
+        structs::{hash_list, Commit, HashAlgorithm},
     },
     measure_time,
     printer_parser::printerparser::PrinterParser,
 };
 
-pub fn restore_checkpoint(file_path: &str, db_path: &str, hash: &str) -> Result<(), DBError> {
-    let end_to_end_timer = Instant::now();
+/// With `verify`, the written file is checked against the commit, see
+/// `write_commit_to_file`
+pub fn restore_checkpoint(
+    file_path: &str,
+    db_path: &str,
+    hash: &str,
+    verify: bool,
+) -> Result<(), DBError> {
+    checkout_commit(file_path, db_path, hash, None, "restore", verify)
+}
 
-    let mut conn = Persistence::open(db_path)?;
+/// Writes the commit to `file_path` for reference, like opening an older
+/// version next to the current one. Unlike `restore_checkpoint`, the current
+/// branch and commit stay as they are, and nothing is recorded in the reflog.
+pub fn materialize_checkpoint(
+    file_path: &str,
+    db_path: &str,
+    hash: &str,
+    verify: bool,
+) -> Result<(), DBError> {
+    let conn = Persistence::open_read_only(db_path)?;
+    let hash = resolve_commit_hash(&conn, hash)?;
+    write_commit_to_file(&conn, &hash, file_path, verify).map(|_| ())
+}
+
+/// Parses the file at `file_path` again, and checks that it has the header and
+/// the blocks of `commit`
+fn verify_file(
+    file_path: &str,
+    commit: &Commit,
+    hash_algorithm: HashAlgorithm,
+) -> Result<(), DBError> {
+    let written = blend_file_data_from_file(file_path, hash_algorithm)
+        .map_err(|e| DBError::Consistency(format!("Cannot read back {}: {}", file_path, e)))?;
+
+    if written.header_bytes != commit.header || written.blocks != commit.blocks {
+        return Err(DBError::Consistency(format!(
+            "{} does not match checkpoint {}",
+            file_path, commit.hash
+        )));
+    }
+
+    Ok(())
+}
+
+/// Copies the file at `file_path` next to it, if there is one
+fn back_up_file(file_path: &str) -> Result<Option<NamedTempFile>, DBError> {
+    let path = Path::new(file_path);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let backup = NamedTempFile::new_in(dir)
+        .and_then(|backup| fs::copy(path, backup.path()).map(|_| backup))
+        .map_err(|e| DBError::Error(format!("Cannot back up {}: {:?}", file_path, e)))?;
+
+    Ok(Some(backup))
+}
 
+/// Writes the commit to `file_path`, without changing the current branch or
+/// commit, and returns it. With `verify`, the written file is hashed again,
+/// and if it does not match the commit, the file that was there before is put
+/// back and a `Consistency` error is returned.
+pub(crate) fn write_commit_to_file(
+    conn: &Persistence,
+    hash: &str,
+    file_path: &str,
+    verify: bool,
+) -> Result<Commit, DBError> {
     let commit = measure_time!(format!("Reading commit {:?}", hash), {
         conn.read_commit(hash)?
             .ok_or(DBError::Consistency("no such commit found".to_owned()))
@@ -30,11 +100,8 @@ pub fn restore_checkpoint(file_path: &str, db_path: &str, hash: &str) -> Result<
             .1
     });
 
-    let header = commit.header;
-
     let block_data: Vec<Vec<u8>> = measure_time!(format!("Decompressing blocks {:?}", hash), {
-        conn.read_blocks(block_hashes)
-            .map_err(|_| DBError::Error("Cannot read block hashes".to_owned()))?
+        conn.read_blocks(block_hashes)?
             .par_iter()
             .map(|record| {
                 let mut writer = Vec::new();
@@ -46,19 +113,72 @@ pub fn restore_checkpoint(file_path: &str, db_path: &str, hash: &str) -> Result<
             .collect()
     });
 
+    let backup = if verify {
+        back_up_file(file_path)?
+    } else {
+        None
+    };
+
     measure_time!(format!("Writing file {:?}", hash), {
-        to_file_transactional(file_path, header, block_data, b"ENDB".to_vec())
-            .map_err(|_| DBError::Fundamental("Cannot write to file".to_owned()))?;
+        to_file_transactional(
+            file_path,
+            commit.header.clone(),
+            block_data,
+            b"ENDB".to_vec(),
+        )
+        .map_err(|_| DBError::Fundamental("Cannot write to file".to_owned()))?;
     });
 
+    if verify {
+        let verified = measure_time!(format!("Verifying file {:?}", hash), {
+            verify_file(file_path, &commit, conn.read_hash_algorithm()?)
+        });
+
+        if let Err(err) = verified {
+            match backup {
+                Some(backup) => backup.persist(file_path).map(|_| ()).map_err(|e| e.error),
+                None => fs::remove_file(file_path),
+            }
+            .map_err(|e| {
+                DBError::Fundamental(format!("Cannot roll back {}: {:?}", file_path, e))
+            })?;
+            return Err(err);
+        }
+    }
+
+    Ok(commit)
+}
+
+/// Writes the commit to `file_path` and makes it the current commit, with
+/// `branch_name` or the branch of the commit as the current branch. The
+/// change is recorded in the reflog under `operation`.
+pub(crate) fn checkout_commit(
+    file_path: &str,
+    db_path: &str,
+    hash: &str,
+    branch_name: Option<&str>,
+    operation: &str,
+    verify: bool,
+) -> Result<(), DBError> {
+    let end_to_end_timer = Instant::now();
+
+    let mut conn = Persistence::open(db_path)?;
+
+    let hash = &resolve_commit_hash(&conn, hash)?;
+
+    let commit = write_commit_to_file(&conn, hash, file_path, verify)?;
+
     conn.execute_in_transaction(|tx| {
-        Persistence::write_current_branch_name(tx, &commit.branch)?;
-        Persistence::write_current_commit_pointer(tx, &commit.hash)?;
+        Persistence::write_current_branch_name(
+            tx,
+            branch_name.unwrap_or(&commit.branch),
+            operation,
+        )?;
+        Persistence::write_current_commit_pointer(tx, &commit.hash, operation)?;
         Ok(())
     })?;
 
     println!("Checkout took: {:?}", end_to_end_timer.elapsed());
-
     Ok(())
 }
 
@@ -67,11 +187,18 @@ mod test {
     use tempfile::{NamedTempFile, TempDir};
 
     use crate::{
-        api::{init_command::MAIN_BRANCH_NAME, test_utils},
-        db::db_ops::{Persistence, DB},
+        api::{
+            common::blend_file_data_from_file, init_command::MAIN_BRANCH_NAME,
+            switch_command::switch_branches, test_utils,
+        },
+        db::{
+            db_ops::{DBError, Persistence, DB},
+            structs::{hash_list, BlockRecord},
+        },
+        printer_parser::printerparser::PrinterParser,
     };
 
-    use super::restore_checkpoint;
+    use super::{materialize_checkpoint, restore_checkpoint};
 
     #[test]
     fn test_restore() {
@@ -88,7 +215,8 @@ mod test {
         restore_checkpoint(
             tmp_blend_path.path().to_str().unwrap(),
             tmp_db_path,
-            "b637ec695e10bed0ce06279d1dc46717",
+            "7d4ef4ea6028e3baf19af1a81b3f0046c3a598cfb2d741a99068cb9e876fa64e",
+            true,
         )
         .expect("Cannot restore checkpoint");
 
@@ -112,10 +240,102 @@ mod test {
             .expect("Cannot read latest commit");
 
         // The latest commit hash is updated to the hash of the restored commit
-        assert_eq!(latest_commit_hash, "b637ec695e10bed0ce06279d1dc46717");
+        assert_eq!(
+            latest_commit_hash,
+            "7d4ef4ea6028e3baf19af1a81b3f0046c3a598cfb2d741a99068cb9e876fa64e"
+        );
 
         // The tip of `main` stays the same
         let main_tip = db.read_branch_tip(MAIN_BRANCH_NAME).unwrap().unwrap();
-        assert_eq!(main_tip, "d9e8eb09f8270ad5326de946d951433a");
+        assert_eq!(
+            main_tip,
+            "a5c6d55d45b0b44fd09e99e955583cecdc1214781ac2b404835bda9bd1804bed"
+        );
+    }
+
+    #[test]
+    fn test_materialize() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().join("db");
+        let tmp_db_path = tmp_db_path.to_str().unwrap();
+        let tmp_blend_path = tmp_dir.path().join("working.blend");
+        let tmp_blend_path = tmp_blend_path.to_str().unwrap();
+        let reference_path = tmp_dir.path().join("compare").join("reference.blend");
+        let reference_path = reference_path.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::new_branch(tmp_db_path, "dev");
+        test_utils::commit(tmp_db_path, "Commit on dev", "data/untitled_2.blend");
+        switch_branches(tmp_db_path, MAIN_BRANCH_NAME, tmp_blend_path, false).unwrap();
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_3.blend");
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        let dev_tip = db.read_branch_tip("dev").unwrap().unwrap();
+        let main_tip = db.read_branch_tip(MAIN_BRANCH_NAME).unwrap().unwrap();
+        let ref_log_len = db.read_ref_log(None).unwrap().len();
+        drop(db);
+
+        std::fs::create_dir(tmp_dir.path().join("compare")).unwrap();
+        materialize_checkpoint(reference_path, tmp_db_path, &dev_tip, false).unwrap();
+
+        // The file is the commit on `dev`
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        let snapshot = blend_file_data_from_file(reference_path, db.read_hash_algorithm().unwrap())
+            .unwrap()
+            .snapshot;
+        assert_eq!(
+            snapshot,
+            db.read_commit(&dev_tip).unwrap().unwrap().snapshot
+        );
+
+        // Nothing moved
+        assert_eq!(db.read_current_branch_name().unwrap(), MAIN_BRANCH_NAME);
+        assert_eq!(db.read_current_commit_pointer().unwrap(), main_tip);
+        assert_eq!(db.read_ref_log(None).unwrap().len(), ref_log_len);
+    }
+
+    #[test]
+    fn test_restore_verify_rolls_back() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().join("db");
+        let tmp_db_path = tmp_db_path.to_str().unwrap();
+        let tmp_blend_path = tmp_dir.path().join("working.blend");
+        let tmp_blend_path = tmp_blend_path.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
+
+        // One of the blocks of the commit gets the data of another one
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        let tip = db.read_current_commit_pointer().unwrap();
+        let commit = db.read_commit(&tip).unwrap().unwrap();
+        let block_hashes = hash_list().parse(&commit.blocks, &mut ()).unwrap().1;
+        let other_block = db
+            .read_blocks(vec![block_hashes[2].clone()])
+            .unwrap()
+            .remove(0);
+        db.delete_blocks(&block_hashes[1..2]).unwrap();
+        db.write_blocks(&[BlockRecord {
+            hash: block_hashes[1].clone(),
+            data: other_block.data,
+        }])
+        .unwrap();
+        drop(db);
+
+        std::fs::copy("data/untitled.blend", tmp_blend_path).unwrap();
+        let previous = std::fs::read(tmp_blend_path).unwrap();
+
+        let result = restore_checkpoint(tmp_blend_path, tmp_db_path, &tip, true);
+        assert!(matches!(result, Err(DBError::Consistency(_))));
+
+        // The previous file is back
+        assert_eq!(std::fs::read(tmp_blend_path).unwrap(), previous);
+
+        // Nothing is left behind if there was no file before
+        let reference_path = tmp_dir.path().join("reference.blend");
+        let reference_path = reference_path.to_str().unwrap();
+        assert!(materialize_checkpoint(reference_path, tmp_db_path, &tip, true).is_err());
+        assert!(!std::path::Path::new(reference_path).exists());
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 2);
     }
 }
@@ -0,0 +1,96 @@
+use crate::db::{
+    db_ops::{DBError, Persistence, DB},
+    structs::CommitInfo,
+};
+
+/// Searches the messages of the checkpoints on all branches, best matches first
+pub fn search_commits(db_path: &str, text: &str, limit: usize) -> Result<Vec<CommitInfo>, DBError> {
+    Persistence::open_read_only(db_path).and_then(|db| db.search_commits(text, limit))
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use crate::{
+        api::{
+            delete_branch::delete_branch,
+            init_command::{INITIAL_COMMIT_HASH, MAIN_BRANCH_NAME},
+            test_utils::{init_db_from_simple_timeline, SimpleCommit, SimpleTimeline},
+        },
+        db::db_ops::{Persistence, DB},
+    };
+
+    use super::search_commits;
+
+    fn search(db_path: &str, text: &str) -> Vec<String> {
+        search_commits(db_path, text, 10)
+            .expect("Cannot search")
+            .into_iter()
+            .map(|commit| commit.hash)
+            .collect()
+    }
+
+    #[test]
+    fn test_search_commits() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        init_db_from_simple_timeline(
+            tmp_db_path,
+            SimpleTimeline {
+                project_id: String::from("a"),
+                author: "test".to_owned(),
+                blocks: vec![String::from("aaa")],
+                commits: vec![
+                    SimpleCommit {
+                        hash: "1".to_owned(),
+                        prev_hash: String::from(INITIAL_COMMIT_HASH),
+                        branch: String::from(MAIN_BRANCH_NAME),
+                        message: "Model the sword".to_owned(),
+                        blocks: "aaa".to_owned(),
+                    },
+                    SimpleCommit {
+                        hash: "2".to_owned(),
+                        prev_hash: "1".to_owned(),
+                        branch: String::from(MAIN_BRANCH_NAME),
+                        message: "Rigging the sword, sword handle follows the hand".to_owned(),
+                        blocks: "aaa".to_owned(),
+                    },
+                    SimpleCommit {
+                        hash: "a".to_owned(),
+                        prev_hash: "2".to_owned(),
+                        branch: "shield".to_owned(),
+                        message: "Shield rig".to_owned(),
+                        blocks: "aaa".to_owned(),
+                    },
+                ],
+            },
+        );
+
+        assert_eq!(search(tmp_db_path, "sword rig"), vec!["2"]);
+        assert_eq!(search(tmp_db_path, "rig"), vec!["a", "2"]);
+        assert_eq!(search(tmp_db_path, "SWORD").len(), 2);
+
+        // query syntax is not interpreted
+        assert_eq!(search(tmp_db_path, "\"sword OR"), Vec::<String>::new());
+        assert_eq!(search(tmp_db_path, "  "), Vec::<String>::new());
+
+        // the index follows the commits table
+        {
+            let mut db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+            db.execute_in_transaction(|tx| Persistence::rename_commit_hash(tx, "1", "one"))
+                .expect("Cannot rename commit");
+            db.execute_in_transaction(|tx| {
+                Persistence::write_current_branch_name(tx, MAIN_BRANCH_NAME, "switch")?;
+                // only `main` was shared
+                Persistence::write_remote_branch_tip(tx, MAIN_BRANCH_NAME, "2")
+            })
+            .expect("Cannot switch branch");
+        }
+        assert_eq!(search(tmp_db_path, "model"), vec!["one"]);
+
+        delete_branch(tmp_db_path, "shield").expect("Cannot delete branch");
+        assert_eq!(search(tmp_db_path, "shield"), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,363 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    iter,
+};
+
+use crate::{
+    api::common::{
+        hash_new_commit, read_current_commit_hash, rehash_commits, resolve_commit_hash,
+        rewrite_history, HistoryRewrite,
+    },
+    db::{
+        db_ops::{DBError, Persistence, DB},
+        structs::{Commit, CommitMetadata},
+    },
+};
+
+fn read_metadata(db: &Persistence, hash: &str) -> Result<BTreeMap<String, String>, DBError> {
+    Ok(db
+        .read_commit_metadata(hash)?
+        .into_iter()
+        .map(|entry| (entry.key, entry.value))
+        .collect())
+}
+
+/// The commits from `from` to `to` on the branch, oldest first, following
+/// the first parent of each commit back from the tip of the branch
+fn read_range(
+    db: &Persistence,
+    branch_name: &str,
+    from: &str,
+    to: &str,
+) -> Result<Vec<Commit>, DBError> {
+    let mut hash = db
+        .read_branch_tip(branch_name)?
+        .ok_or(DBError::Error(format!("No such branch: {}", branch_name)))?;
+
+    let mut range: Vec<Commit> = vec![];
+    loop {
+        let commit = db.read_commit(&hash)?.ok_or(DBError::Error(format!(
+            "Checkpoints {} to {} are not on {}",
+            from, to, branch_name
+        )))?;
+
+        let is_from = commit.hash == from;
+        if commit.hash == to || !range.is_empty() {
+            if commit.branch != branch_name {
+                return Err(DBError::Error(format!(
+                    "Checkpoint {} was not committed on {}",
+                    commit.hash, branch_name
+                )));
+            }
+            hash = commit.prev_commit_hash.clone();
+            range.push(commit);
+        } else {
+            hash = commit.prev_commit_hash.clone();
+        }
+
+        if is_from {
+            break;
+        }
+    }
+
+    range.reverse();
+    Ok(range)
+}
+
+/// Checks that squashing `range` does not change anything that other commits,
+/// branches, tags or other timelines rely on. Only the newest commit of the
+/// range can be referred to, since it is replaced by the squashed commit.
+fn check_squashable(db: &Persistence, range: &[Commit]) -> Result<(), DBError> {
+    let (_, replaced) = range.split_last().unwrap();
+    let replaced_hashes: HashSet<&str> = replaced.iter().map(|c| c.hash.as_str()).collect();
+
+    for (commit, next) in range.iter().zip(range.iter().skip(1)) {
+        let has_other_children = db
+            .read_descendants_of_commit(&commit.hash)?
+            .iter()
+            .filter(|child| {
+                iter::once(&child.prev_commit_hash)
+                    .chain(child.merge_parents.iter())
+                    .any(|parent| *parent == commit.hash)
+            })
+            .any(|child| child.hash != next.hash);
+
+        if has_other_children {
+            return Err(DBError::Error(format!(
+                "Other checkpoints are built on top of {}",
+                commit.hash
+            )));
+        }
+    }
+
+    for branch_name in db.read_all_branches()? {
+        if let Some(tip) = db.read_branch_tip(&branch_name)? {
+            if replaced_hashes.contains(tip.as_str()) {
+                return Err(DBError::Error(format!(
+                    "Checkpoint {} is the tip of {}",
+                    tip, branch_name
+                )));
+            }
+        }
+    }
+
+    if let Some(tag) = db
+        .read_all_tags()?
+        .into_iter()
+        .find(|tag| replaced_hashes.contains(tag.hash.as_str()))
+    {
+        return Err(DBError::Error(format!(
+            "Checkpoint {} is tagged as {}",
+            tag.hash, tag.name
+        )));
+    }
+
+    if replaced_hashes.contains(read_current_commit_hash(db)?.as_str()) {
+        return Err(DBError::Error(
+            "The current checkpoint would be squashed, restore a later one first".to_owned(),
+        ));
+    }
+
+    // other timelines have everything up to the remote tips, and would not
+    // know the squashed commits or the new hashes of their descendants
+    let rewritten: HashSet<String> = db
+        .read_descendants_of_commit(&range[0].hash)?
+        .into_iter()
+        .map(|commit| commit.hash)
+        .collect();
+    for branch_name in db.read_all_branches()? {
+        if let Ok(remote_tip) = db.read_remote_branch_tip(&branch_name) {
+            if rewritten.contains(&remote_tip) {
+                return Err(DBError::Error(format!(
+                    "Checkpoint {} is shared with other timelines as the tip of {}",
+                    remote_tip, branch_name
+                )));
+            }
+        }
+    }
+
+    // every timeline of the project starts from the same commit
+    if !db.commit_exists(&range[0].prev_commit_hash)? {
+        return Err(DBError::Error(
+            "The first checkpoint of the timeline cannot be squashed".to_owned(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Collapses the commits from `from` to `to` on `branch_name` into a single
+/// commit, and returns its hash. The squashed commit has the contents of `to`,
+/// the metadata of all of them, and `message`, or their messages one after the
+/// other if it is not given.
+///
+/// Every commit built on top of `to`, on any branch, is rewritten to follow the
+/// squashed commit. Their old hashes, like the hashes of the squashed commits,
+/// are kept as aliases. Commits that were already shared with other timelines
+/// cannot be squashed, since those timelines would not know the new hashes.
+pub fn squash_checkpoints(
+    db_path: &str,
+    branch_name: &str,
+    from: &str,
+    to: &str,
+    message: Option<String>,
+) -> Result<String, DBError> {
+    let mut db = Persistence::open(db_path)?;
+
+    let from = resolve_commit_hash(&db, from)?;
+    let to = resolve_commit_hash(&db, to)?;
+
+    let range = read_range(&db, branch_name, &from, &to)?;
+    if range.len() < 2 {
+        return Err(DBError::Error(
+            "At least two checkpoints are needed to squash".to_owned(),
+        ));
+    }
+    check_squashable(&db, &range)?;
+
+    let hash_algorithm = db.read_hash_algorithm()?;
+    let old_tip = db.read_branch_tip(branch_name)?.unwrap_or_default();
+
+    let mut metadata: BTreeMap<String, String> = BTreeMap::new();
+    let mut merge_parents: Vec<String> = vec![];
+    for commit in range.iter() {
+        metadata.extend(read_metadata(&db, &commit.hash)?);
+        for parent in commit.merge_parents.iter() {
+            if !merge_parents.contains(parent) {
+                merge_parents.push(parent.clone());
+            }
+        }
+    }
+
+    let combined_message = range
+        .iter()
+        .map(|commit| commit.message.as_str())
+        .filter(|message| !message.is_empty())
+        .collect::<Vec<&str>>()
+        .join("\n");
+
+    let newest = range.last().unwrap().clone();
+    let mut squashed = Commit {
+        prev_commit_hash: range[0].prev_commit_hash.clone(),
+        merge_parents,
+        message: message.unwrap_or(combined_message),
+        ..newest
+    };
+    let mut new_metadata: Vec<CommitMetadata> =
+        hash_new_commit(&mut squashed, metadata, hash_algorithm);
+
+    if db.commit_exists(&squashed.hash)? {
+        return Err(DBError::Error(format!(
+            "Checkpoint {} already exists",
+            squashed.hash
+        )));
+    }
+
+    // the hash of a commit covers the hashes of its parents, so everything
+    // after the squashed commits gets a new hash
+    let mut renamed: HashMap<String, String> = HashMap::from([(to.clone(), squashed.hash.clone())]);
+    let descendants: Vec<Commit> = db
+        .read_descendants_of_commit(&to)?
+        .into_iter()
+        .filter(|commit| commit.hash != to)
+        .collect();
+    let (rehashed, rehashed_metadata) =
+        rehash_commits(&db, descendants, &mut renamed, hash_algorithm)?;
+    new_metadata.extend(rehashed_metadata);
+
+    let new_tip = renamed.get(&old_tip).unwrap_or(&old_tip).clone();
+    let squashed_hash = squashed.hash.clone();
+
+    let rewrite = HistoryRewrite {
+        removed: range
+            .iter()
+            .map(|commit| commit.hash.clone())
+            .chain(rehashed.iter().map(|(old_hash, _)| old_hash.clone()))
+            .collect(),
+        renamed: range
+            .iter()
+            .map(|commit| (commit.hash.clone(), squashed_hash.clone()))
+            .chain(
+                rehashed
+                    .iter()
+                    .map(|(old_hash, commit)| (old_hash.clone(), commit.hash.clone())),
+            )
+            .collect(),
+        written: iter::once(squashed)
+            .chain(rehashed.into_iter().map(|(_, commit)| commit))
+            .collect(),
+        metadata: new_metadata,
+    };
+    rewrite_history(&mut db, "squash", branch_name, &new_tip, rewrite)?;
+
+    Ok(squashed_hash)
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use crate::{
+        api::{
+            common::{read_latest_commit_hash_on_branch, resolve_commit_hash},
+            delete_branch::delete_branch,
+            init_command::MAIN_BRANCH_NAME,
+            switch_command::switch_branches,
+            test_utils,
+        },
+        db::db_ops::{Persistence, DB},
+    };
+
+    use super::squash_checkpoints;
+
+    #[test]
+    fn test_squash_checkpoints() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+        let tmp_blend_path = tmp_dir.path().join("working.blend");
+        let tmp_blend_path = tmp_blend_path.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_db_path, "Autosave 1", "data/untitled_2.blend");
+        test_utils::commit(tmp_db_path, "Autosave 2", "data/untitled_3.blend");
+        test_utils::commit(tmp_db_path, "Autosave 3", "data/untitled_2.blend");
+        test_utils::new_branch(tmp_db_path, "dev");
+        test_utils::commit(tmp_db_path, "On dev", "data/untitled_3.blend");
+        switch_branches(tmp_db_path, MAIN_BRANCH_NAME, tmp_blend_path, false).unwrap();
+        test_utils::commit(tmp_db_path, "Done", "data/untitled_3.blend");
+
+        let checkpoints = test_utils::list_checkpoints(tmp_db_path, MAIN_BRANCH_NAME);
+        let (done, autosave_3, autosave_1, initial) = (
+            checkpoints[0].hash.clone(),
+            checkpoints[1].hash.clone(),
+            checkpoints[3].hash.clone(),
+            checkpoints[4].hash.clone(),
+        );
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        let snapshot = db.read_commit(&autosave_3).unwrap().unwrap().snapshot;
+        drop(db);
+
+        // `dev` starts from `Autosave 3`, which other timelines are expected to have
+        assert!(squash_checkpoints(
+            tmp_db_path,
+            MAIN_BRANCH_NAME,
+            &autosave_1,
+            &autosave_3,
+            None
+        )
+        .is_err());
+        delete_branch(tmp_db_path, "dev").unwrap();
+
+        let squashed = squash_checkpoints(
+            tmp_db_path,
+            MAIN_BRANCH_NAME,
+            &autosave_1,
+            &autosave_3,
+            None,
+        )
+        .unwrap();
+
+        let checkpoints = test_utils::list_checkpoints(tmp_db_path, MAIN_BRANCH_NAME);
+        assert_eq!(checkpoints.len(), 3);
+        assert_eq!(checkpoints[1].hash, squashed);
+        assert_eq!(checkpoints[1].message, "Autosave 1\nAutosave 2\nAutosave 3");
+        assert_eq!(checkpoints[2].hash, initial);
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        let squashed_commit = db.read_commit(&squashed).unwrap().unwrap();
+        assert_eq!(squashed_commit.snapshot, snapshot);
+        assert_eq!(squashed_commit.prev_commit_hash, initial);
+
+        // The commits after the squashed ones follow them
+        let main_tip = read_latest_commit_hash_on_branch(&db, MAIN_BRANCH_NAME).unwrap();
+        assert_ne!(main_tip, done);
+        assert_eq!(resolve_commit_hash(&db, &done).unwrap(), main_tip);
+        assert_eq!(db.read_current_commit_pointer().unwrap(), main_tip);
+        let main_commit = db.read_commit(&main_tip).unwrap().unwrap();
+        assert_eq!(main_commit.prev_commit_hash, squashed);
+        assert!(!db.commit_exists(&autosave_1).unwrap());
+        drop(db);
+
+        // Every timeline of the project starts from the first commit
+        assert!(
+            squash_checkpoints(tmp_db_path, MAIN_BRANCH_NAME, &initial, &squashed, None).is_err()
+        );
+
+        // Once `main` is shared, its commits stay as they are
+        let mut db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        db.execute_in_transaction(|tx| {
+            Persistence::write_remote_branch_tip(tx, MAIN_BRANCH_NAME, &main_tip)
+        })
+        .unwrap();
+        drop(db);
+        assert!(squash_checkpoints(
+            tmp_db_path,
+            MAIN_BRANCH_NAME,
+            &squashed,
+            &main_tip,
+            Some("All".to_owned())
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use std::{collections::HashSet, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::common::{
+        blend_file_data_from_file, read_blend_blocks, read_blend_file, read_current_commit_hash,
+        read_latest_commit_hash_on_branch,
+    },
+    blend::merge::changed_datablocks,
+    db::{
+        db_ops::{DBError, Persistence, DB},
+        structs::hash_list,
+    },
+    printer_parser::printerparser::PrinterParser,
+};
+
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub struct WorkingFileStatus {
+    /// The current commit, which the working file is compared with
+    pub commit: String,
+    /// Whether the current commit is older than the tip of the current branch,
+    /// in which case committing starts a new branch
+    pub detached: bool,
+    /// Whether the working file differs from the commit
+    pub dirty: bool,
+    /// The number of blocks of the working file that are not in the commit
+    pub changed_blocks: usize,
+    /// The datablocks that were added, removed or changed since the commit
+    pub changed_datablocks: Vec<String>,
+}
+
+/// Compares the blend file at `file_path` with the current commit, without
+/// writing anything.
+pub fn working_file_status(file_path: &str, db_path: &str) -> Result<WorkingFileStatus, DBError> {
+    let conn = Persistence::open_read_only(db_path)?;
+    file_status(&conn, file_path)
+}
+
+/// Fails if the blend file at `file_path` has changes that are not committed,
+/// before it is overwritten. A file that does not exist has nothing to lose.
+pub(crate) fn ensure_no_uncommitted_changes(
+    conn: &Persistence,
+    file_path: &str,
+) -> Result<(), DBError> {
+    if !Path::new(file_path).exists() || !file_status(conn, file_path)?.dirty {
+        return Ok(());
+    }
+
+    Err(DBError::Error(format!(
+        "{} has changes that are not committed, commit or restore it first",
+        file_path
+    )))
+}
+
+fn file_status(conn: &Persistence, file_path: &str) -> Result<WorkingFileStatus, DBError> {
+    let current_commit_hash = read_current_commit_hash(conn)?;
+    let detached = read_latest_commit_hash_on_branch(conn, &conn.read_current_branch_name()?)?
+        != current_commit_hash;
+    let commit = conn
+        .read_commit(&current_commit_hash)?
+        .ok_or(DBError::Consistency(format!(
+            "No such commit: {}",
+            current_commit_hash
+        )))?;
+
+    let blend_data = blend_file_data_from_file(file_path, conn.read_hash_algorithm()?)
+        .map_err(|e| DBError::Error(format!("Error parsing blend file: {}", e)))?;
+
+    let committed_block_hashes: HashSet<String> = hash_list()
+        .parse(&commit.blocks, &mut ())
+        .map_err(|_| DBError::Fundamental("Cannot parse blocks".to_owned()))?
+        .1
+        .into_iter()
+        .collect();
+
+    let changed_blocks = blend_data
+        .block_data
+        .iter()
+        .filter(|block| !committed_block_hashes.contains(&block.hash))
+        .count();
+
+    let dirty = blend_data.snapshot != commit.snapshot || blend_data.header_bytes != commit.header;
+
+    let changed_datablocks = if dirty {
+        changed_datablocks(
+            read_blend_blocks(conn, &current_commit_hash)?,
+            read_blend_file(file_path)?,
+        )
+        .map_err(|e| DBError::Error(format!("Cannot compare datablocks: {}", e)))?
+    } else {
+        vec![]
+    };
+
+    Ok(WorkingFileStatus {
+        commit: current_commit_hash,
+        detached,
+        dirty,
+        changed_blocks,
+        changed_datablocks,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use crate::api::test_utils;
+
+    use super::working_file_status;
+
+    #[test]
+    fn test_working_file_status() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+
+        let status = working_file_status("data/untitled.blend", tmp_db_path).unwrap();
+        assert!(!status.dirty);
+        assert_eq!(status.changed_blocks, 0);
+        assert_eq!(status.changed_datablocks, Vec::<String>::new());
+
+        let status = working_file_status("data/untitled_2.blend", tmp_db_path).unwrap();
+        assert!(status.dirty);
+        assert!(status.changed_blocks > 0);
+        assert_eq!(
+            status.changed_datablocks,
+            vec!["SCScene", "OBCube", "MECube", "WOWorld", "MAMaterial"]
+        );
+
+        // Nothing was written
+        assert_eq!(test_utils::list_checkpoints(tmp_db_path, "main").len(), 1);
+    }
+}
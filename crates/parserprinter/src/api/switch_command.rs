@@ -1,10 +1,18 @@
 use crate::db::db_ops::{DBError, Persistence, DB};
 
-use super::restore_command::restore_checkpoint;
-
-pub fn switch_branches(db_path: &str, branch_name: &str, file_path: &str) -> Result<(), DBError> {
+use super::restore_command::checkout_commit;
+
+/// Writes the tip of `branch_name` to `file_path` and makes it the current
+/// branch. With `verify`, the written file is checked against the tip, and the
+/// previous file is put back if it does not match.
+pub fn switch_branches(
+    db_path: &str,
+    branch_name: &str,
+    file_path: &str,
+    verify: bool,
+) -> Result<(), DBError> {
     let hash = {
-        let mut db = Persistence::open(db_path)?;
+        let db = Persistence::open(db_path)?;
 
         let tip = db.read_branch_tip(branch_name)?;
 
@@ -14,17 +22,17 @@ pub fn switch_branches(db_path: &str, branch_name: &str, file_path: &str) -> Res
             ));
         }
 
-        let hash = tip.unwrap();
-
-        db.execute_in_transaction(|tx| {
-            Persistence::write_current_branch_name(tx, branch_name)?;
-            Ok(())
-        })?;
-
-        hash
+        tip.unwrap()
     };
 
-    restore_checkpoint(file_path, db_path, &hash)
+    checkout_commit(
+        file_path,
+        db_path,
+        &hash,
+        Some(branch_name),
+        "switch",
+        verify,
+    )
 }
 
 #[cfg(test)]
@@ -47,7 +55,7 @@ mod test {
 
         test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
 
-        let res = switch_branches(tmp_db_path, "unknown", "void.blend");
+        let res = switch_branches(tmp_db_path, "unknown", "void.blend", false);
         assert!(matches!(res, Err(_)));
 
         let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
@@ -60,7 +68,10 @@ mod test {
         let main_tip = db.read_branch_tip(MAIN_BRANCH_NAME).unwrap().unwrap();
 
         // tip of main stays the same
-        assert_eq!(main_tip, "a5f92d0a988085ed66c9dcdccc7b9c90");
+        assert_eq!(
+            main_tip,
+            "35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb"
+        );
 
         let current_branch_name = db
             .read_current_branch_name()
@@ -73,7 +84,10 @@ mod test {
             .expect("Cannot read latest commit");
 
         // The latest commit hash stays the same
-        assert_eq!(latest_commit_hash, "a5f92d0a988085ed66c9dcdccc7b9c90");
+        assert_eq!(
+            latest_commit_hash,
+            "35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb"
+        );
     }
 
     #[test]
@@ -97,6 +111,7 @@ mod test {
             tmp_db_path,
             MAIN_BRANCH_NAME,
             tmp_blend_path.path().to_str().unwrap(),
+            true,
         )
         .expect("Cannot switch branches");
 
@@ -109,7 +124,10 @@ mod test {
         // latest commit hash is set to the tip of the checked out branch
         let lastest_commit_hash =
             read_latest_commit_hash_on_branch(&db, &current_branch_name).unwrap();
-        assert_eq!(lastest_commit_hash, "b637ec695e10bed0ce06279d1dc46717");
+        assert_eq!(
+            lastest_commit_hash,
+            "7d4ef4ea6028e3baf19af1a81b3f0046c3a598cfb2d741a99068cb9e876fa64e"
+        );
 
         let main_tip = db.read_branch_tip(MAIN_BRANCH_NAME).unwrap().unwrap();
         assert_eq!(lastest_commit_hash, main_tip);
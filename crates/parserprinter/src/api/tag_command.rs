@@ -0,0 +1,137 @@
+use crate::db::{
+    db_ops::{DBError, Persistence, DB},
+    structs::Tag,
+};
+
+use super::common::resolve_commit_hash;
+
+/// Tags the commit referred to by `hash`, which can be anything that
+/// `restore_checkpoint` accepts
+pub fn create_tag(db_path: &str, tag_name: &str, hash: &str) -> Result<(), DBError> {
+    let mut db = Persistence::open(db_path)?;
+
+    if tag_name.trim().is_empty() {
+        return Err(DBError::Error("Tag name cannot be empty".to_owned()));
+    }
+
+    if db.read_tag(tag_name)?.is_some() {
+        return Err(DBError::Error(format!("Tag {} already exists", tag_name)));
+    }
+
+    // tags are looked up before hashes, so they must not hide any
+    if looks_like_hash(tag_name)
+        || db.commit_exists(tag_name)?
+        || db.read_hash_alias(tag_name)?.is_some()
+    {
+        return Err(DBError::Error(format!(
+            "Tag {} could be mistaken for the hash of a commit",
+            tag_name
+        )));
+    }
+
+    let hash = resolve_commit_hash(&db, hash)?;
+
+    if !db.commit_exists(&hash)? {
+        return Err(DBError::Error(format!("No such commit: {}", hash)));
+    }
+
+    db.execute_in_transaction(|tx| {
+        Persistence::write_tag(
+            tx,
+            &Tag {
+                name: tag_name.to_owned(),
+                hash,
+            },
+        )
+    })
+}
+
+/// Whether `name` is as long as an MD5, BLAKE3 or SHA-256 hash, and made of hex
+/// digits only
+fn looks_like_hash(name: &str) -> bool {
+    (name.len() == 32 || name.len() == 64) && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+pub fn list_tags(db_path: &str) -> Result<Vec<Tag>, DBError> {
+    Persistence::open_read_only(db_path).and_then(|db| db.read_all_tags())
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::{NamedTempFile, TempDir};
+
+    use crate::{
+        api::{restore_command::restore_checkpoint, test_utils},
+        db::{
+            db_ops::{Persistence, DB},
+            structs::Tag,
+        },
+    };
+
+    use super::{create_tag, list_tags};
+
+    #[test]
+    fn test_create_tag() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+        test_utils::commit(tmp_db_path, "Commit", "data/untitled_2.blend");
+
+        create_tag(
+            tmp_db_path,
+            "v1-approved",
+            "35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb",
+        )
+        .expect("Cannot create tag");
+
+        assert_eq!(
+            list_tags(tmp_db_path).unwrap(),
+            vec![Tag {
+                name: "v1-approved".to_owned(),
+                hash: "35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb".to_owned()
+            }]
+        );
+
+        // tags are immutable
+        assert!(create_tag(
+            tmp_db_path,
+            "v1-approved",
+            "2db2d69f659354ca6f82fef2d94a2338f089d5d2f9c107b4cd437eb59211d272",
+        )
+        .is_err());
+
+        // tags cannot shadow hashes
+        assert!(create_tag(
+            tmp_db_path,
+            "35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb",
+            "v1-approved",
+        )
+        .is_err());
+        assert!(create_tag(
+            tmp_db_path,
+            "900150983cd24fb0d6963f7d28e17f72",
+            "v1-approved",
+        )
+        .is_err());
+
+        // only existing commits can be tagged
+        assert!(create_tag(tmp_db_path, "nope", "not-a-commit").is_err());
+
+        // tags can be used in place of hashes
+        let tmp_blend_path = NamedTempFile::new().expect("Cannot create temp file");
+        restore_checkpoint(
+            tmp_blend_path.path().to_str().unwrap(),
+            tmp_db_path,
+            "v1-approved",
+            false,
+        )
+        .expect("Cannot restore tag");
+
+        let db = Persistence::open(tmp_db_path).expect("Cannot open test DB");
+        assert_eq!(
+            db.read_current_commit_pointer().unwrap(),
+            "35024d6947b2e457da2ed479cb33a7069bc98d07ba89f0ce41596ca7d2aa61cb"
+        );
+    }
+}
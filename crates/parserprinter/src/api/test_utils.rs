@@ -1,23 +1,36 @@
 #[cfg(test)]
 pub fn init_db_from_file(db_path: &str, project_id: &str, blend_file_path: &str) {
     use super::init_command::init_db;
-
-    init_db(db_path, project_id, blend_file_path).expect("Cannot init DB")
+    use crate::db::structs::HashAlgorithm;
+
+    init_db(
+        db_path,
+        project_id,
+        blend_file_path,
+        HashAlgorithm::default(),
+    )
+    .expect("Cannot init DB")
 }
 
 #[cfg(test)]
 pub fn commit(db_path: &str, message: &str, blend_path: &str) {
     use super::commit_command::create_new_commit;
 
-    create_new_commit(blend_path, db_path, Some(message.to_owned()))
-        .expect("Cannot create new commit")
+    create_new_commit(
+        blend_path,
+        db_path,
+        Some(message.to_owned()),
+        Default::default(),
+        None,
+    )
+    .expect("Cannot create new commit");
 }
 
 #[cfg(test)]
 pub fn new_branch(db_path: &str, name: &str) {
     use super::new_branch_command::create_new_branch;
 
-    create_new_branch(db_path, name).expect("Cannot create new branch")
+    create_new_branch(db_path, name, None, None, None, false).expect("Cannot create new branch")
 }
 
 #[cfg(test)]
@@ -81,17 +94,19 @@ pub fn init_db_from_simple_timeline(db_path: &str, simple_timeline: SimpleTimeli
         db.execute_in_transaction(|tx| {
             let this_hash = commit.hash.clone();
             let this_branch_name = commit.branch.clone();
-            Persistence::write_branch_tip(tx, &this_branch_name, &this_hash)
+            Persistence::write_branch_tip(tx, &this_branch_name, &this_hash, "commit")
                 .expect("cannot write branch tip");
 
-            Persistence::write_current_branch_name(tx, &last_branch_name)
+            Persistence::write_current_branch_name(tx, &last_branch_name, "switch")
                 .expect("Cannot write current branch");
 
             Persistence::write_commit(
                 tx,
                 Commit {
-                    hash: commit.hash,
+                    hash: commit.hash.clone(),
+                    snapshot: commit.hash,
                     prev_commit_hash: commit.prev_hash,
+                    merge_parents: vec![],
                     project_id: simple_timeline.project_id.clone(),
                     branch: commit.branch,
                     message: commit.message,
@@ -87,7 +87,7 @@ mod test {
 
     use crate::{
         api::init_command::MAIN_BRANCH_NAME,
-        db::structs::{BlockRecord, Commit},
+        db::structs::{BlockRecord, Commit, HashAlgorithm},
         exchange::structs::Exchange,
     };
 
@@ -99,10 +99,13 @@ mod test {
         let path = file.path().to_str().unwrap();
 
         let original_exchange = Exchange {
+            hash_algorithm: HashAlgorithm::Blake3,
             commits: vec![
                 Commit {
                     hash: String::from("abc123"),
+                    snapshot: String::from("abc123"),
                     prev_commit_hash: String::from("def456"),
+                    merge_parents: vec![],
                     project_id: String::from("proj789"),
                     branch: String::from(MAIN_BRANCH_NAME),
                     message: String::from("Initial commit"),
@@ -113,7 +116,9 @@ mod test {
                 },
                 Commit {
                     hash: String::from("qwe234"),
+                    snapshot: String::from("qwe234"),
                     prev_commit_hash: String::from("abc123"),
+                    merge_parents: vec![],
                     project_id: String::from("proj78"),
                     branch: String::from(MAIN_BRANCH_NAME),
                     message: String::from("Next commit"),
@@ -133,6 +138,9 @@ mod test {
                     data: vec![5, 6, 7, 8],
                 },
             ],
+            tags: vec![],
+            metadata: vec![],
+            branch_renames: vec![],
         };
 
         write_exchange_to_file(&original_exchange, path).unwrap();
@@ -0,0 +1,271 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    api::{commit_command::create_new_commit, status_command::working_file_status},
+    db::db_ops::DBError,
+};
+
+/// How many changed datablocks are named in the message of an autosave
+const NAMED_DATABLOCKS: usize = 3;
+
+pub struct WatchOptions {
+    /// How often the file is checked for changes
+    pub poll_interval: Duration,
+    /// How long the file has to stay the same before a save counts as done
+    pub debounce: Duration,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            poll_interval: Duration::from_millis(500),
+            debounce: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WatchEvent {
+    /// A save was committed with this hash
+    Committed(String),
+    /// The file was saved without changing its contents
+    Unchanged,
+    /// The save was not committed, since the older checkpoint with this hash
+    /// is restored, and committing on top of it would start a new branch
+    Detached(String),
+    /// The save could not be committed, the watcher keeps going
+    Failed(DBError),
+}
+
+/// The modification time and size of a file, `None` if it does not exist.
+/// Saving the file, in place or by renaming a new file over it, changes it.
+fn file_state(path: &str) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Blender writes `file.blend@` first and renames it to `file.blend` when the
+/// save is complete
+fn save_in_progress(path: &str) -> bool {
+    Path::new(&format!("{}@", path)).exists()
+}
+
+/// Tells when the saves of a file are complete, by polling its state
+pub struct SaveDetector {
+    file_path: String,
+    debounce: Duration,
+    last_state: Option<(SystemTime, u64)>,
+    changed_at: Option<Instant>,
+}
+
+impl SaveDetector {
+    pub fn new(file_path: &str, debounce: Duration) -> Self {
+        SaveDetector {
+            file_path: file_path.to_owned(),
+            debounce,
+            last_state: file_state(file_path),
+            changed_at: None,
+        }
+    }
+
+    /// Whether a save completed since the last completed save. Saves are
+    /// complete once the file has not changed for the debounce period, and
+    /// Blender is not writing it.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        let state = file_state(&self.file_path);
+        if state != self.last_state {
+            self.last_state = state;
+            self.changed_at = Some(now);
+            return false;
+        }
+
+        match self.changed_at {
+            Some(changed_at)
+                if now.duration_since(changed_at) >= self.debounce
+                    && state.is_some()
+                    && !save_in_progress(&self.file_path) =>
+            {
+                self.changed_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn autosave_message(changed_datablocks: &[String]) -> String {
+    if changed_datablocks.is_empty() {
+        return "Autosave".to_owned();
+    }
+
+    let named = changed_datablocks
+        .iter()
+        .take(NAMED_DATABLOCKS)
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    match changed_datablocks.len().checked_sub(NAMED_DATABLOCKS) {
+        Some(rest) if rest > 0 => format!("Autosave: {} and {} more", named, rest),
+        _ => format!("Autosave: {}", named),
+    }
+}
+
+/// Commits the file at `file_path` with a message listing what changed, unless
+/// it is the same as the current commit, or an older checkpoint is restored
+pub fn commit_save(file_path: &str, db_path: &str) -> WatchEvent {
+    let result = working_file_status(file_path, db_path).and_then(|status| {
+        if !status.dirty {
+            return Ok(WatchEvent::Unchanged);
+        }
+
+        if status.detached {
+            return Ok(WatchEvent::Detached(status.commit));
+        }
+
+        create_new_commit(
+            file_path,
+            db_path,
+            Some(autosave_message(&status.changed_datablocks)),
+            HashMap::new(),
+            None,
+        )
+        .map(WatchEvent::Committed)
+    });
+
+    result.unwrap_or_else(WatchEvent::Failed)
+}
+
+/// Commits every save of the file at `file_path` until `stop` is set, and
+/// passes what happened to `on_event`
+pub fn watch_file(
+    file_path: &str,
+    db_path: &str,
+    options: &WatchOptions,
+    stop: &AtomicBool,
+    mut on_event: impl FnMut(WatchEvent),
+) {
+    let mut detector = SaveDetector::new(file_path, options.debounce);
+
+    while !stop.load(Ordering::Relaxed) {
+        thread::sleep(options.poll_interval);
+
+        if detector.poll(Instant::now()) {
+            on_event(commit_save(file_path, db_path));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        time::{Duration, Instant},
+    };
+
+    use tempfile::TempDir;
+
+    use crate::{
+        api::{init_command::MAIN_BRANCH_NAME, restore_command::restore_checkpoint, test_utils},
+        db::db_ops::{Persistence, DB},
+    };
+
+    use super::{autosave_message, commit_save, SaveDetector, WatchEvent};
+
+    #[test]
+    fn test_save_detector() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let file_path = tmp_dir.path().join("scene.blend");
+        let file_path = file_path.to_str().unwrap();
+        let debounce = Duration::from_secs(2);
+
+        fs::write(file_path, "v1").unwrap();
+        let mut detector = SaveDetector::new(file_path, debounce);
+        let start = Instant::now();
+        assert!(!detector.poll(start));
+
+        // Blender writes the new version next to the file
+        fs::write(format!("{}@", file_path), "version 2").unwrap();
+        assert!(!detector.poll(start + 2 * debounce));
+
+        fs::rename(format!("{}@", file_path), file_path).unwrap();
+        assert!(!detector.poll(start + 3 * debounce));
+        assert!(!detector.poll(start + 3 * debounce + debounce / 2));
+        assert!(detector.poll(start + 4 * debounce));
+
+        // Each save is reported once
+        assert!(!detector.poll(start + 5 * debounce));
+    }
+
+    #[test]
+    fn test_autosave_message() {
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(autosave_message(&[]), "Autosave");
+        assert_eq!(
+            autosave_message(&names(&["OBCube", "MECube"])),
+            "Autosave: OBCube, MECube"
+        );
+        assert_eq!(
+            autosave_message(&names(&[
+                "SCScene",
+                "OBCube",
+                "MECube",
+                "WOWorld",
+                "MAMaterial"
+            ])),
+            "Autosave: SCScene, OBCube, MECube and 2 more"
+        );
+    }
+
+    #[test]
+    fn test_commit_save() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_db_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+        let file_path = tmp_dir.path().join("working.blend");
+        let file_path = file_path.to_str().unwrap();
+
+        test_utils::init_db_from_file(tmp_db_path, "my-cool-project", "data/untitled.blend");
+
+        fs::copy("data/untitled.blend", file_path).unwrap();
+        assert!(matches!(
+            commit_save(file_path, tmp_db_path),
+            WatchEvent::Unchanged
+        ));
+
+        fs::copy("data/untitled_2.blend", file_path).unwrap();
+        let WatchEvent::Committed(hash) = commit_save(file_path, tmp_db_path) else {
+            panic!("Expected the save to be committed");
+        };
+
+        let checkpoints = test_utils::list_checkpoints(tmp_db_path, MAIN_BRANCH_NAME);
+        assert_eq!(checkpoints.len(), 2);
+        assert_eq!(checkpoints[0].hash, hash);
+        assert_eq!(
+            checkpoints[0].message,
+            "Autosave: SCScene, OBCube, MECube and 2 more"
+        );
+
+        // Saves on top of an older checkpoint are left for the user to commit
+        restore_checkpoint(file_path, tmp_db_path, &checkpoints[1].hash, false).unwrap();
+        fs::copy("data/untitled_3.blend", file_path).unwrap();
+        assert!(matches!(
+            commit_save(file_path, tmp_db_path),
+            WatchEvent::Detached(detached) if detached == checkpoints[1].hash
+        ));
+        let db = Persistence::open_read_only(tmp_db_path).expect("Cannot open test DB");
+        assert_eq!(db.read_all_branches().unwrap(), vec![MAIN_BRANCH_NAME]);
+    }
+}
@@ -110,7 +110,7 @@ pub struct RawBlend {
     pub dna: Dna,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SimpleParsedBlock {
     pub code: [u8; 4],
     pub size: u32,
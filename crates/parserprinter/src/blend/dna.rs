@@ -0,0 +1,296 @@
+use std::{collections::HashMap, ops::Range};
+
+use super::blend_file::{Dna, DnaField, DnaStruct, DnaType, Endianness, PointerSize};
+
+/// The code of the block holding the DNA of the blend file.
+pub const DNA_BLOCK_CODE: [u8; 4] = *b"DNA1";
+
+struct DnaReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    endianness: Endianness,
+}
+
+impl<'a> DnaReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + len)
+            .ok_or("Unexpected end of DNA".to_owned())?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn tag(&mut self, expected: &[u8; 4]) -> Result<(), String> {
+        match self.take(4)? {
+            tag if tag == expected => Ok(()),
+            tag => Err(format!(
+                "Expected {:?} in DNA, found {:?}",
+                String::from_utf8_lossy(expected),
+                String::from_utf8_lossy(tag)
+            )),
+        }
+    }
+
+    fn u16(&mut self) -> Result<usize, String> {
+        let bytes: [u8; 2] = self.take(2)?.try_into().unwrap();
+        Ok(match self.endianness {
+            Endianness::Little => u16::from_le_bytes(bytes),
+            Endianness::Big => u16::from_be_bytes(bytes),
+        } as usize)
+    }
+
+    fn u32(&mut self) -> Result<usize, String> {
+        let bytes: [u8; 4] = self.take(4)?.try_into().unwrap();
+        Ok(match self.endianness {
+            Endianness::Little => u32::from_le_bytes(bytes),
+            Endianness::Big => u32::from_be_bytes(bytes),
+        } as usize)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let rest = &self.data[self.position.min(self.data.len())..];
+        let len = rest
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or("Unterminated string in DNA".to_owned())?;
+        let string = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.position += len + 1;
+        Ok(string)
+    }
+
+    fn strings(&mut self, count: usize) -> Result<Vec<String>, String> {
+        (0..count).map(|_| self.string()).collect()
+    }
+
+    fn align(&mut self) {
+        self.position = (self.position + 3) & !3;
+    }
+}
+
+/// Parses the data of the `DNA1` block, which describes the layout of every
+/// struct stored in the blend file.
+pub fn parse_dna(data: &[u8], endianness: Endianness) -> Result<Dna, String> {
+    let mut reader = DnaReader {
+        data,
+        position: 0,
+        endianness,
+    };
+
+    reader.tag(b"SDNA")?;
+
+    reader.tag(b"NAME")?;
+    let names_len = reader.u32()?;
+    let names = reader.strings(names_len)?;
+    reader.align();
+
+    reader.tag(b"TYPE")?;
+    let types_len = reader.u32()?;
+    let type_names = reader.strings(types_len)?;
+    reader.align();
+
+    reader.tag(b"TLEN")?;
+    let types = type_names
+        .into_iter()
+        .map(|name| reader.u16().map(|bytes_len| DnaType { name, bytes_len }))
+        .collect::<Result<Vec<DnaType>, String>>()?;
+    reader.align();
+
+    reader.tag(b"STRC")?;
+    let structs_len = reader.u32()?;
+    let structs = (0..structs_len)
+        .map(|_| {
+            let type_index = reader.u16()?;
+            let fields_len = reader.u16()?;
+            let fields = (0..fields_len)
+                .map(|_| {
+                    Ok(DnaField {
+                        type_index: reader.u16()?,
+                        name_index: reader.u16()?,
+                    })
+                })
+                .collect::<Result<Vec<DnaField>, String>>()?;
+            Ok(DnaStruct { type_index, fields })
+        })
+        .collect::<Result<Vec<DnaStruct>, String>>()?;
+
+    Ok(Dna {
+        names,
+        types,
+        structs,
+    })
+}
+
+/// Field names in the DNA carry the declaration around the identifier, like
+/// `*next`, `name[66]` or `(*func)()`. Returns whether the field is a pointer
+/// and how many elements it holds.
+fn field_shape(name: &str) -> (bool, usize) {
+    let is_pointer = name.starts_with('*') || name.starts_with('(');
+    let elements = name
+        .split('[')
+        .skip(1)
+        .filter_map(|dimension| dimension.trim_end_matches(']').parse::<usize>().ok())
+        .product();
+    (is_pointer, elements)
+}
+
+/// The identifier of a field name, `name[66]` becomes `name`.
+fn field_identifier(name: &str) -> &str {
+    name.trim_start_matches(['*', '('])
+        .split(['[', ')'])
+        .next()
+        .unwrap_or_default()
+}
+
+impl Dna {
+    fn struct_index_by_type(&self) -> HashMap<usize, usize> {
+        self.structs
+            .iter()
+            .enumerate()
+            .map(|(index, s)| (s.type_index, index))
+            .collect()
+    }
+
+    /// The index in `structs` of the struct called `name`.
+    pub fn struct_index(&self, name: &str) -> Option<usize> {
+        self.structs
+            .iter()
+            .position(|s| self.types.get(s.type_index).map(|t| t.name.as_str()) == Some(name))
+    }
+
+    fn field_size(&self, field: &DnaField, pointer_size: PointerSize) -> usize {
+        let (is_pointer, elements) = field_shape(&self.names[field.name_index]);
+        let element_size = if is_pointer {
+            pointer_size.bytes_num()
+        } else {
+            self.types[field.type_index].bytes_len
+        };
+        element_size * elements
+    }
+
+    /// The bytes `field_name` (without any `*` or `[]`) takes up inside the
+    /// struct called `struct_name`.
+    pub fn field_range(
+        &self,
+        struct_name: &str,
+        field_name: &str,
+        pointer_size: PointerSize,
+    ) -> Option<Range<usize>> {
+        let dna_struct = &self.structs[self.struct_index(struct_name)?];
+        let mut offset = 0;
+        for field in dna_struct.fields.iter() {
+            let size = self.field_size(field, pointer_size);
+            if field_identifier(&self.names[field.name_index]) == field_name {
+                return Some(offset..offset + size);
+            }
+            offset += size;
+        }
+        None
+    }
+
+    /// The size in bytes of the struct at `struct_index`.
+    pub fn struct_len(&self, struct_index: usize) -> usize {
+        self.types[self.structs[struct_index].type_index].bytes_len
+    }
+
+    /// The offsets of every pointer inside each struct, including the ones in
+    /// structs embedded in it, indexed the same way as `structs`.
+    pub fn pointer_offsets(&self, pointer_size: PointerSize) -> Vec<Vec<usize>> {
+        let struct_index_by_type = self.struct_index_by_type();
+        (0..self.structs.len())
+            .map(|struct_index| {
+                let mut offsets = vec![];
+                self.collect_pointer_offsets(
+                    struct_index,
+                    0,
+                    pointer_size,
+                    &struct_index_by_type,
+                    &mut offsets,
+                );
+                offsets
+            })
+            .collect()
+    }
+
+    fn collect_pointer_offsets(
+        &self,
+        struct_index: usize,
+        base: usize,
+        pointer_size: PointerSize,
+        struct_index_by_type: &HashMap<usize, usize>,
+        offsets: &mut Vec<usize>,
+    ) {
+        let mut offset = base;
+        for field in self.structs[struct_index].fields.iter() {
+            let (is_pointer, elements) = field_shape(&self.names[field.name_index]);
+            let size = self.field_size(field, pointer_size);
+            if is_pointer {
+                let step = pointer_size.bytes_num();
+                offsets.extend((0..elements).map(|element| offset + element * step));
+            } else if let Some(&embedded) = struct_index_by_type.get(&field.type_index) {
+                let step = self.types[field.type_index].bytes_len;
+                for element in 0..elements {
+                    self.collect_pointer_offsets(
+                        embedded,
+                        offset + element * step,
+                        pointer_size,
+                        struct_index_by_type,
+                        offsets,
+                    );
+                }
+            }
+            offset += size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        blend::{
+            blend_file::{Endianness, PointerSize},
+            parsers::{blend, BlendFileParseState},
+            utils::from_file,
+        },
+        printer_parser::printerparser::PrinterParser,
+    };
+
+    use super::{parse_dna, DNA_BLOCK_CODE};
+
+    #[test]
+    fn test_parse_dna() {
+        let blend_bytes = from_file("data/untitled.blend").unwrap();
+        let mut state = BlendFileParseState {
+            pointer_size: PointerSize::Bits32,
+            endianness: Endianness::Little,
+            current_block_size: 0,
+        };
+        let (_, (header, blocks)) = blend().read(&blend_bytes, &mut state).unwrap();
+        let dna_block = blocks
+            .iter()
+            .find(|block| block.code == DNA_BLOCK_CODE)
+            .unwrap();
+
+        let dna = parse_dna(&dna_block.data, header.endianness).unwrap();
+
+        // `ID` starts with the `next`, `prev`, `newid`, `lib` and
+        // `asset_data` pointers
+        let pointer_len = header.pointer_size.bytes_num();
+        assert_eq!(
+            dna.field_range("ID", "next", header.pointer_size),
+            Some(0..pointer_len)
+        );
+        assert_eq!(
+            dna.field_range("ID", "name", header.pointer_size),
+            Some(5 * pointer_len..5 * pointer_len + 66)
+        );
+        assert_eq!(
+            dna.field_range("ID", "no_such_field", header.pointer_size),
+            None
+        );
+
+        let id_index = dna.struct_index("ID").unwrap();
+        let pointer_offsets = &dna.pointer_offsets(header.pointer_size)[id_index];
+        assert_eq!(&pointer_offsets[..3], &[0, pointer_len, 2 * pointer_len]);
+    }
+}
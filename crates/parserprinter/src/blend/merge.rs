@@ -0,0 +1,683 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    blend_file::{Dna, Endianness, Header, PointerSize, SimpleParsedBlock},
+    dna::{parse_dna, DNA_BLOCK_CODE},
+    utils::Either,
+};
+
+const SUBSIDIARY_BLOCK_CODE: [u8; 4] = *b"DATA";
+
+/// The window manager, screens and workspaces change whenever the UI does,
+/// so they are always taken from our side unless resolved otherwise.
+const UI_ID_CODES: [&str; 3] = ["WM", "SR", "WS"];
+
+/// Fields of `ID` that only make sense while the file is open in Blender and
+/// change on every save.
+const RUNTIME_ID_FIELDS: [&str; 6] = [
+    "tag",
+    "icon_id",
+    "recalc",
+    "recalc_up_to_undo_push",
+    "recalc_after_undo_push",
+    "session_uuid",
+];
+
+/// The side of a merge a datablock is taken from.
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeSide {
+    /// The branch being merged into
+    Ours,
+    /// The branch being merged
+    Theirs,
+}
+
+impl MergeSide {
+    fn branch(self) -> &'static str {
+        match self {
+            MergeSide::Ours => "our branch",
+            MergeSide::Theirs => "their branch",
+        }
+    }
+}
+
+/// A datablock that cannot be merged without picking one of the sides.
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub struct MergeConflict {
+    /// The name of the datablock, including its ID code, like `OBCube`
+    pub datablock: String,
+    pub reason: String,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum MergeError {
+    Conflicts(Vec<MergeConflict>),
+    /// The files cannot be merged at the block level, no matter which sides
+    /// are picked
+    Unsupported(String),
+}
+
+pub type BlendBlocks = (Header, Vec<SimpleParsedBlock>);
+
+fn is_ui_datablock(name: &str) -> bool {
+    UI_ID_CODES.iter().any(|code| name.starts_with(code))
+}
+
+/// A principal (ID) block has a two letter code, like `OB\0\0`.
+fn is_id_block(block: &SimpleParsedBlock) -> bool {
+    block.code[0] != 0 && block.code[2] == 0 && block.code[3] == 0
+}
+
+fn address(block: &SimpleParsedBlock) -> u64 {
+    match block.memory_address {
+        Either::Left(address) => address as u64,
+        Either::Right(address) => address,
+    }
+}
+
+fn set_address(block: &mut SimpleParsedBlock, address: u64) {
+    block.memory_address = match block.memory_address {
+        Either::Left(_) => Either::Left(address as u32),
+        Either::Right(_) => Either::Right(address),
+    }
+}
+
+fn read_pointer(data: &[u8], offset: usize, header: &Header) -> u64 {
+    let bytes = &data[offset..offset + header.pointer_size.bytes_num()];
+    match (header.pointer_size, header.endianness) {
+        (PointerSize::Bits32, Endianness::Little) => {
+            u32::from_le_bytes(bytes.try_into().unwrap()) as u64
+        }
+        (PointerSize::Bits32, Endianness::Big) => {
+            u32::from_be_bytes(bytes.try_into().unwrap()) as u64
+        }
+        (PointerSize::Bits64, Endianness::Little) => u64::from_le_bytes(bytes.try_into().unwrap()),
+        (PointerSize::Bits64, Endianness::Big) => u64::from_be_bytes(bytes.try_into().unwrap()),
+    }
+}
+
+fn write_pointer(data: &mut [u8], offset: usize, value: u64, header: &Header) {
+    let bytes = match (header.pointer_size, header.endianness) {
+        (PointerSize::Bits32, Endianness::Little) => (value as u32).to_le_bytes().to_vec(),
+        (PointerSize::Bits32, Endianness::Big) => (value as u32).to_be_bytes().to_vec(),
+        (PointerSize::Bits64, Endianness::Little) => value.to_le_bytes().to_vec(),
+        (PointerSize::Bits64, Endianness::Big) => value.to_be_bytes().to_vec(),
+    };
+    data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+}
+
+enum Segment {
+    Datablock(String),
+    Other(SimpleParsedBlock),
+}
+
+/// Where a block sits in a file: the datablock it belongs to and its
+/// position among the blocks of that datablock, the ID block being 0.
+type Location = (String, usize);
+
+/// A block with its pointers replaced by the locations they point to, so
+/// that the same data saved at different memory addresses compares equal.
+#[derive(PartialEq, Eq)]
+struct NormalizedBlock {
+    code: [u8; 4],
+    dna_index: u32,
+    count: u32,
+    data: Vec<u8>,
+    targets: Vec<Option<Location>>,
+}
+
+/// A blend file split into datablocks: an ID block followed by the `DATA`
+/// blocks Blender writes right after it.
+struct Datablocks {
+    header: Header,
+    dna: Dna,
+    pointer_offsets: Vec<Vec<usize>>,
+    runtime_id_ranges: Vec<Range<usize>>,
+    segments: Vec<Segment>,
+    by_name: HashMap<String, Vec<SimpleParsedBlock>>,
+    locations: HashMap<u64, Location>,
+}
+
+fn dna_block(blocks: &[SimpleParsedBlock]) -> Result<&SimpleParsedBlock, MergeError> {
+    blocks
+        .iter()
+        .find(|block| block.code == DNA_BLOCK_CODE)
+        .ok_or(MergeError::Unsupported("No DNA found".to_owned()))
+}
+
+impl Datablocks {
+    fn new(header: Header, blocks: Vec<SimpleParsedBlock>) -> Result<Datablocks, MergeError> {
+        let dna = parse_dna(&dna_block(&blocks)?.data, header.endianness)
+            .map_err(MergeError::Unsupported)?;
+        let name_range =
+            dna.field_range("ID", "name", header.pointer_size)
+                .ok_or(MergeError::Unsupported(
+                    "No ID name found in DNA".to_owned(),
+                ))?;
+        let runtime_id_ranges = RUNTIME_ID_FIELDS
+            .iter()
+            .filter_map(|field| dna.field_range("ID", field, header.pointer_size))
+            .collect();
+
+        let mut datablocks = Datablocks {
+            pointer_offsets: dna.pointer_offsets(header.pointer_size),
+            header,
+            dna,
+            runtime_id_ranges,
+            segments: vec![],
+            by_name: HashMap::new(),
+            locations: HashMap::new(),
+        };
+        let mut current: Option<String> = None;
+
+        for block in blocks {
+            if is_id_block(&block) {
+                let raw_name = block
+                    .data
+                    .get(name_range.clone())
+                    .ok_or(MergeError::Unsupported("ID block too short".to_owned()))?;
+                let name_len = raw_name
+                    .iter()
+                    .position(|b| *b == 0)
+                    .unwrap_or(raw_name.len());
+                let id_name = String::from_utf8_lossy(&raw_name[..name_len]).into_owned();
+
+                // Linked IDs coming from different libraries can share a name
+                let mut name = id_name.clone();
+                let mut occurrence = 1;
+                while datablocks.by_name.contains_key(&name) {
+                    occurrence += 1;
+                    name = format!("{}#{}", id_name, occurrence);
+                }
+
+                datablocks
+                    .locations
+                    .insert(address(&block), (name.clone(), 0));
+                datablocks.segments.push(Segment::Datablock(name.clone()));
+                datablocks.by_name.insert(name.clone(), vec![block]);
+                current = Some(name);
+            } else if let (true, Some(name)) = (block.code == SUBSIDIARY_BLOCK_CODE, &current) {
+                let blocks = datablocks.by_name.get_mut(name).unwrap();
+                datablocks
+                    .locations
+                    .insert(address(&block), (name.clone(), blocks.len()));
+                blocks.push(block);
+            } else {
+                current = None;
+                datablocks.segments.push(Segment::Other(block));
+            }
+        }
+
+        Ok(datablocks)
+    }
+
+    /// The offsets of the pointers in the data of `block`. The layout of ID
+    /// blocks and structs comes from the DNA. Blocks without a struct are
+    /// taken as arrays of pointers if all of their values point to blocks.
+    fn pointer_offsets_in(&self, block: &SimpleParsedBlock) -> Vec<usize> {
+        let dna_index = block.dna_index as usize;
+        let count = block.count as usize;
+        let pointer_len = self.header.pointer_size.bytes_num();
+
+        if dna_index > 0 || is_id_block(block) {
+            if dna_index >= self.pointer_offsets.len()
+                || self.dna.struct_len(dna_index) * count != block.data.len()
+            {
+                return vec![];
+            }
+            let struct_len = self.dna.struct_len(dna_index);
+            return (0..count)
+                .flat_map(|element| {
+                    self.pointer_offsets[dna_index]
+                        .iter()
+                        .map(move |offset| element * struct_len + offset)
+                })
+                .collect();
+        }
+
+        if block.data.is_empty() || !block.data.len().is_multiple_of(pointer_len) {
+            return vec![];
+        }
+        let offsets: Vec<usize> = (0..block.data.len()).step_by(pointer_len).collect();
+        let values: Vec<u64> = offsets
+            .iter()
+            .map(|offset| read_pointer(&block.data, *offset, &self.header))
+            .collect();
+        let is_pointer_array = values.iter().any(|value| *value != 0)
+            && values
+                .iter()
+                .all(|value| *value == 0 || self.locations.contains_key(value));
+        if is_pointer_array {
+            offsets
+        } else {
+            vec![]
+        }
+    }
+
+    fn normalized(&self, name: &str) -> Option<Vec<NormalizedBlock>> {
+        let blocks = self.by_name.get(name)?;
+        Some(
+            blocks
+                .iter()
+                .map(|block| {
+                    let mut data = block.data.clone();
+                    if is_id_block(block) {
+                        for range in self.runtime_id_ranges.iter() {
+                            if let Some(bytes) = data.get_mut(range.clone()) {
+                                bytes.fill(0);
+                            }
+                        }
+                    }
+                    let targets = self
+                        .pointer_offsets_in(block)
+                        .into_iter()
+                        .map(|offset| {
+                            let pointer = read_pointer(&data, offset, &self.header);
+                            write_pointer(&mut data, offset, 0, &self.header);
+                            self.locations.get(&pointer).cloned()
+                        })
+                        .collect();
+                    NormalizedBlock {
+                        code: block.code,
+                        dna_index: block.dna_index,
+                        count: block.count,
+                        data,
+                        targets,
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+fn pick(
+    name: &str,
+    base: &Datablocks,
+    ours: &Datablocks,
+    theirs: &Datablocks,
+    resolutions: &HashMap<String, MergeSide>,
+) -> Result<Option<MergeSide>, MergeConflict> {
+    if let Some(side) = resolutions.get(name) {
+        return Ok(Some(*side));
+    }
+
+    if is_ui_datablock(name) {
+        return Ok(ours.by_name.get(name).map(|_| MergeSide::Ours));
+    }
+
+    let base_version = base.normalized(name);
+    let our_version = ours.normalized(name);
+    let their_version = theirs.normalized(name);
+
+    if our_version == their_version || their_version == base_version {
+        return Ok(our_version.map(|_| MergeSide::Ours));
+    }
+
+    if our_version == base_version {
+        return Ok(their_version.map(|_| MergeSide::Theirs));
+    }
+
+    let reason = match (our_version, their_version) {
+        (None, _) => "Deleted on our branch and changed on their branch",
+        (_, None) => "Changed on our branch and deleted on their branch",
+        _ => "Changed differently on both branches",
+    };
+
+    Err(MergeConflict {
+        datablock: name.to_owned(),
+        reason: reason.to_owned(),
+    })
+}
+
+/// The datablocks that were added, removed or changed in `new` compared to
+/// `old`, in the order they appear in the files. UI datablocks are left out,
+/// they change whenever the file is saved.
+pub fn changed_datablocks(old: BlendBlocks, new: BlendBlocks) -> Result<Vec<String>, String> {
+    let unsupported = |e: MergeError| match e {
+        MergeError::Unsupported(reason) => reason,
+        MergeError::Conflicts(_) => "Unexpected conflicts".to_owned(),
+    };
+    let old = Datablocks::new(old.0, old.1).map_err(unsupported)?;
+    let new = Datablocks::new(new.0, new.1).map_err(unsupported)?;
+
+    let mut names: Vec<&String> = vec![];
+    for datablocks in [&new, &old] {
+        for segment in datablocks.segments.iter() {
+            if let Segment::Datablock(name) = segment {
+                if !is_ui_datablock(name) && !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+
+    Ok(names
+        .into_iter()
+        .filter(|name| old.normalized(name) != new.normalized(name))
+        .cloned()
+        .collect())
+}
+
+/// Three-way merge of two blend files at the datablock level.
+///
+/// Datablocks changed on one side only are taken from that side, datablocks
+/// changed on both are conflicts unless `resolutions` picks a side for them.
+/// The blocks taken from their side are moved to free memory addresses if
+/// needed, and pointers are relinked to the datablocks the merged file ends
+/// up with. Both sides need to be saved with the same version of Blender.
+pub fn merge_blend_blocks(
+    base: BlendBlocks,
+    ours: BlendBlocks,
+    theirs: BlendBlocks,
+    resolutions: &HashMap<String, MergeSide>,
+) -> Result<BlendBlocks, MergeError> {
+    if ours.0.pointer_size != theirs.0.pointer_size
+        || ours.0.endianness != theirs.0.endianness
+        || dna_block(&ours.1)?.data != dna_block(&theirs.1)?.data
+    {
+        return Err(MergeError::Unsupported(
+            "The branches were saved with different versions of Blender".to_owned(),
+        ));
+    }
+
+    let base = Datablocks::new(base.0, base.1)?;
+    let ours = Datablocks::new(ours.0, ours.1)?;
+    let theirs = Datablocks::new(theirs.0, theirs.1)?;
+
+    let mut names: Vec<&String> = vec![];
+    for datablocks in [&ours, &theirs] {
+        for segment in datablocks.segments.iter() {
+            if let Segment::Datablock(name) = segment {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+
+    let mut picked: HashMap<&String, MergeSide> = HashMap::new();
+    let mut conflicts: Vec<MergeConflict> = vec![];
+    for name in names {
+        match pick(name, &base, &ours, &theirs, resolutions) {
+            Ok(Some(side)) => {
+                picked.insert(name, side);
+            }
+            Ok(None) => {}
+            Err(conflict) => conflicts.push(conflict),
+        }
+    }
+
+    if !conflicts.is_empty() {
+        return Err(MergeError::Conflicts(conflicts));
+    }
+
+    let side_of = |side: MergeSide| match side {
+        MergeSide::Ours => &ours,
+        MergeSide::Theirs => &theirs,
+    };
+
+    // Blocks of ours keep their addresses, the ones of theirs move if the
+    // address is already taken
+    let mut taken: HashSet<u64> = ours
+        .segments
+        .iter()
+        .filter_map(|segment| match segment {
+            Segment::Other(block) => Some(address(block)),
+            Segment::Datablock(_) => None,
+        })
+        .collect();
+    for (name, side) in picked.iter() {
+        if *side == MergeSide::Ours {
+            taken.extend(ours.by_name[*name].iter().map(address));
+        }
+    }
+    let mut next_free = ours
+        .locations
+        .keys()
+        .chain(theirs.locations.keys())
+        .chain(taken.iter())
+        .max()
+        .copied()
+        .unwrap_or_default();
+    let mut moved: HashMap<u64, u64> = HashMap::new();
+    for (name, side) in picked.iter() {
+        if *side == MergeSide::Theirs {
+            for block in theirs.by_name[*name].iter() {
+                let mut new_address = address(block);
+                if !taken.insert(new_address) {
+                    next_free += 8;
+                    new_address = next_free;
+                    taken.insert(new_address);
+                }
+                moved.insert(address(block), new_address);
+            }
+        }
+    }
+    if ours.header.pointer_size == PointerSize::Bits32 && next_free > u32::MAX as u64 {
+        return Err(MergeError::Unsupported(
+            "Ran out of memory addresses for the merged file".to_owned(),
+        ));
+    }
+
+    let merged_address = |side: MergeSide, (name, index): &Location| -> Option<u64> {
+        let address = address(side_of(side).by_name[name].get(*index)?);
+        match side {
+            MergeSide::Ours => Some(address),
+            MergeSide::Theirs => moved.get(&address).copied(),
+        }
+    };
+
+    // Points every pointer of `block`, coming from `side`, to where its
+    // target is in the merged file. Blocks outside of datablocks, like
+    // `GLOB`, have no owner. They and the UI can do without the pointers
+    // that cannot be relinked, Blender falls back to defaults for them.
+    let relink = |block: &mut SimpleParsedBlock,
+                  side: MergeSide,
+                  owner: Option<&str>,
+                  conflicts: &mut Vec<MergeConflict>| {
+        let owner_name = owner.unwrap_or("the file");
+        let lenient = owner.is_none_or(is_ui_datablock);
+        let datablocks = side_of(side);
+        for offset in datablocks.pointer_offsets_in(block) {
+            let pointer = read_pointer(&block.data, offset, &datablocks.header);
+            let Some(location) = datablocks.locations.get(&pointer) else {
+                continue;
+            };
+            let (target, index) = location;
+            let target_side = picked.get(target).copied();
+            let same_struct = |target_side: MergeSide| {
+                side_of(target_side).by_name[target]
+                    .get(*index)
+                    .map(|target_block| target_block.dna_index)
+                    == Some(datablocks.by_name[target][*index].dna_index)
+            };
+            let relinked = match target_side {
+                Some(target_side) if target_side == side || *index == 0 => {
+                    merged_address(target_side, location)
+                }
+                Some(target_side) if lenient && same_struct(target_side) => {
+                    merged_address(target_side, location)
+                }
+                _ => None,
+            };
+            match (relinked, target_side) {
+                (Some(relinked), _) => {
+                    write_pointer(&mut block.data, offset, relinked, &datablocks.header)
+                }
+                _ if lenient => write_pointer(&mut block.data, offset, 0, &datablocks.header),
+                (None, None) => conflicts.push(MergeConflict {
+                    datablock: target.clone(),
+                    reason: format!("Deleted, but still used by {}", owner_name),
+                }),
+                (None, Some(target_side)) => conflicts.push(MergeConflict {
+                    datablock: target.clone(),
+                    reason: format!(
+                        "Taken from {}, but {} from {} uses data inside it",
+                        target_side.branch(),
+                        owner_name,
+                        side.branch()
+                    ),
+                }),
+            }
+        }
+        if side == MergeSide::Theirs {
+            set_address(block, moved[&address(block)]);
+        }
+    };
+
+    let mut merged_datablocks: HashMap<&String, Vec<SimpleParsedBlock>> = HashMap::new();
+    for (name, side) in picked.iter() {
+        let mut blocks = side_of(*side).by_name[*name].clone();
+        for block in blocks.iter_mut() {
+            relink(block, *side, Some(name), &mut conflicts);
+        }
+        merged_datablocks.insert(name, blocks);
+    }
+
+    let mut blocks: Vec<SimpleParsedBlock> = vec![];
+    // Datablocks only on their branch go after the last datablock of ours
+    let mut added_at = 0;
+    for segment in ours.segments.iter() {
+        match segment {
+            Segment::Other(block) => {
+                let mut block = block.clone();
+                relink(&mut block, MergeSide::Ours, None, &mut conflicts);
+                blocks.push(block);
+            }
+            Segment::Datablock(name) => {
+                blocks.extend(merged_datablocks.remove(name).unwrap_or_default());
+                added_at = blocks.len();
+            }
+        }
+    }
+
+    if !conflicts.is_empty() {
+        conflicts.sort_by(|a, b| a.datablock.cmp(&b.datablock));
+        conflicts.dedup();
+        return Err(MergeError::Conflicts(conflicts));
+    }
+
+    let added: Vec<SimpleParsedBlock> = theirs
+        .segments
+        .iter()
+        .filter_map(|segment| match segment {
+            Segment::Datablock(name) => merged_datablocks.remove(name),
+            Segment::Other(_) => None,
+        })
+        .flatten()
+        .collect();
+    blocks.splice(added_at..added_at, added);
+
+    Ok((ours.header, blocks))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use crate::{
+        blend::{
+            blend_file::{Endianness, PointerSize},
+            parsers::{blend, BlendFileParseState},
+            utils::from_file,
+        },
+        printer_parser::printerparser::PrinterParser,
+    };
+
+    use super::{
+        address, changed_datablocks, merge_blend_blocks, BlendBlocks, Datablocks, MergeError,
+        MergeSide,
+    };
+
+    fn read_blend(path: &str) -> BlendBlocks {
+        let blend_bytes = from_file(path).unwrap();
+        let mut state = BlendFileParseState {
+            pointer_size: PointerSize::Bits32,
+            endianness: Endianness::Little,
+            current_block_size: 0,
+        };
+        blend().read(&blend_bytes, &mut state).unwrap().1
+    }
+
+    #[test]
+    fn test_merge_unchanged_side() {
+        let base = read_blend("data/untitled.blend");
+        let ours = read_blend("data/untitled_2.blend");
+
+        let (_, merged) =
+            merge_blend_blocks(base.clone(), ours.clone(), base, &HashMap::new()).unwrap();
+
+        // Nothing to take from their side
+        assert_eq!(merged, ours.1);
+    }
+
+    #[test]
+    fn test_merge_conflicts() {
+        let base = read_blend("data/untitled.blend");
+        let ours = read_blend("data/untitled_2.blend");
+        let theirs = read_blend("data/untitled_3.blend");
+
+        let Err(MergeError::Conflicts(conflicts)) =
+            merge_blend_blocks(base, ours, theirs, &HashMap::new())
+        else {
+            panic!("Expected conflicts");
+        };
+
+        let conflicting: Vec<&str> = conflicts.iter().map(|c| c.datablock.as_str()).collect();
+        assert_eq!(conflicting, vec!["SCScene", "OBCube", "MECube"]);
+    }
+
+    #[test]
+    fn test_merge_with_resolutions() {
+        let base = read_blend("data/untitled.blend");
+        let ours = read_blend("data/untitled_2.blend");
+        let theirs = read_blend("data/untitled_3.blend");
+
+        let resolutions = HashMap::from([
+            ("SCScene".to_owned(), MergeSide::Ours),
+            ("OBCube".to_owned(), MergeSide::Theirs),
+            ("MECube".to_owned(), MergeSide::Theirs),
+        ]);
+        let (header, merged) =
+            merge_blend_blocks(base, ours.clone(), theirs.clone(), &resolutions).unwrap();
+
+        let merged = Datablocks::new(header, merged).unwrap();
+        let ours = Datablocks::new(ours.0, ours.1).unwrap();
+        let theirs = Datablocks::new(theirs.0, theirs.1).unwrap();
+
+        // Resolved datablocks come from the picked side
+        assert!(merged.normalized("SCScene") == ours.normalized("SCScene"));
+        assert!(merged.normalized("MECube") == theirs.normalized("MECube"));
+        // Datablocks changed only on their side are taken from there
+        assert!(merged.normalized("OBTorus") == theirs.normalized("OBTorus"));
+        assert!(merged.normalized("MAMaterial") == ours.normalized("MAMaterial"));
+
+        // No two blocks of the merged datablocks share an address
+        let mut addresses = HashSet::new();
+        for blocks in merged.by_name.values() {
+            for block in blocks {
+                assert!(addresses.insert(address(block)));
+            }
+        }
+    }
+    #[test]
+    fn test_changed_datablocks() {
+        let base = read_blend("data/untitled.blend");
+        let changed = read_blend("data/untitled_2.blend");
+
+        assert_eq!(
+            changed_datablocks(base.clone(), base.clone()).unwrap(),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            changed_datablocks(base, changed).unwrap(),
+            vec!["SCScene", "OBCube", "MECube", "WOWorld", "MAMaterial"]
+        );
+    }
+}
@@ -1,3 +1,5 @@
 pub mod blend_file;
+pub mod dna;
+pub mod merge;
 pub mod parsers;
 pub mod utils;
\ No newline at end of file
@@ -8,7 +8,7 @@ use zstd::decode_all;
 
 use crate::printer_parser::printerparser::PrinterParserOps;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Either<Left, Right> {
     Left(Left),
     Right(Right),
@@ -1,6 +1,20 @@
-use std::{fmt::Display, path::Path};
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    fs::{File, OpenOptions, TryLockError},
+    path::Path,
+    thread::{available_parallelism, sleep},
+    time::{Duration, Instant},
+};
 
-use super::structs::{BlockRecord, Commit};
+use rocksdb::{BlockBasedOptions, DBCompressionType, WriteBatch};
+
+use rusqlite::{OptionalExtension, ToSql};
+
+use super::structs::{
+    BlockRecord, BranchInfo, BranchRename, Commit, CommitInfo, CommitMetadata, HashAlgorithm,
+    HistoryOrder, HistoryQuery, Intent, RefLogEntry, RefLogTarget, RetentionPolicy, Tag,
+};
 
 pub struct ShortCommitRecord {
     pub hash: String,
@@ -13,6 +27,7 @@ pub enum DBError {
     Fundamental(String), // means that stuff is very wrong
     Consistency(String), // the timeline maybe in an inconsistent state
     Error(String),       // a recoverable error
+    Busy(String),        // another process is writing to the timeline
 }
 
 impl Display for DBError {
@@ -21,19 +36,51 @@ impl Display for DBError {
             DBError::Fundamental(msg) => write!(f, "Fundamental error: {}", msg),
             DBError::Consistency(msg) => write!(f, "Consistency error: {}", msg),
             DBError::Error(msg) => write!(f, "Error: {}", msg),
+            DBError::Busy(msg) => write!(f, "Timeline busy: {}", msg),
         }
     }
 }
 
 pub trait DB: Sized {
+    /// Opens the timeline for writing. Only one writer can have the timeline
+    /// open at a time, across processes. Operations that were interrupted
+    /// halfway are rolled back, see `rolled_back_operations`.
     fn open(path: &str) -> Result<Self, DBError>;
 
+    /// Opens the timeline without taking the writer lock. Writes through the
+    /// returned handle fail.
+    fn open_read_only(path: &str) -> Result<Self, DBError>;
+
     fn write_blocks(&self, blocks: &[BlockRecord]) -> Result<(), DBError>;
     fn read_blocks(&self, hashes: Vec<String>) -> Result<Vec<BlockRecord>, DBError>;
+    fn read_missing_block_hashes(&self, hashes: &[String]) -> Result<Vec<String>, DBError>;
+    fn delete_blocks(&self, hashes: &[String]) -> Result<(), DBError>;
+
+    /// Makes sure that everything written to RocksDB so far survives a crash
+    fn sync_blocks(&self) -> Result<(), DBError>;
 
     fn write_commit(tx: &rusqlite::Transaction, commit: Commit) -> Result<(), DBError>;
+    /// Deletes the commit with its metadata and parents, but not its blocks
+    fn delete_commit(tx: &rusqlite::Transaction, hash: &str) -> Result<(), DBError>;
     fn write_blocks_str(&self, hash: &str, blocks_str: &str) -> Result<(), DBError>;
+    fn delete_blocks_str(&self, hash: &str) -> Result<(), DBError>;
     fn read_commit(&self, hash: &str) -> Result<Option<Commit>, DBError>;
+    fn commit_exists(&self, hash: &str) -> Result<bool, DBError>;
+    fn read_all_commits(&self) -> Result<Vec<Commit>, DBError>;
+
+    /// Changes the hash of a commit everywhere it is referenced, and keeps the
+    /// old hash around as an alias of the new one
+    fn rename_commit_hash(
+        tx: &rusqlite::Transaction,
+        old_hash: &str,
+        new_hash: &str,
+    ) -> Result<(), DBError>;
+    fn read_hash_alias(&self, alias: &str) -> Result<Option<String>, DBError>;
+    fn write_commit_snapshot(
+        tx: &rusqlite::Transaction,
+        hash: &str,
+        snapshot: &str,
+    ) -> Result<(), DBError>;
 
     fn read_ancestors_of_commit(
         &self,
@@ -42,24 +89,83 @@ pub trait DB: Sized {
 
     fn read_descendants_of_commit(&self, hash: &str) -> Result<Vec<Commit>, DBError>;
 
+    fn read_history(&self, query: &HistoryQuery) -> Result<Vec<CommitInfo>, DBError>;
+    /// Full-text search in commit messages, best matches first
+    fn search_commits(&self, text: &str, limit: usize) -> Result<Vec<CommitInfo>, DBError>;
+
+    fn read_commit_metadata(&self, hash: &str) -> Result<Vec<CommitMetadata>, DBError>;
+    fn write_commit_metadata(
+        tx: &rusqlite::Transaction,
+        metadata: &CommitMetadata,
+    ) -> Result<(), DBError>;
+    /// Returns the commits where `key` is set to `value`, oldest first
+    fn read_commits_with_metadata(
+        &self,
+        key: &str,
+        value: &str,
+    ) -> Result<Vec<ShortCommitRecord>, DBError>;
+
     fn read_current_branch_name(&self) -> Result<String, DBError>;
+    /// Records the change in the reflog under `operation`
     fn write_current_branch_name(
         tx: &rusqlite::Transaction,
         brach_name: &str,
+        operation: &str,
     ) -> Result<(), DBError>;
 
     fn read_current_commit_pointer(&self) -> Result<String, DBError>;
-    fn write_current_commit_pointer(tx: &rusqlite::Transaction, hash: &str) -> Result<(), DBError>;
+    /// Records the change in the reflog under `operation`
+    fn write_current_commit_pointer(
+        tx: &rusqlite::Transaction,
+        hash: &str,
+        operation: &str,
+    ) -> Result<(), DBError>;
 
     fn read_all_branches(&self) -> Result<Vec<String>, DBError>;
 
     fn read_branch_tip(&self, branch_name: &str) -> Result<Option<String>, DBError>;
+    /// Records the change in the reflog under `operation`
     fn write_branch_tip(
         tx: &rusqlite::Transaction,
         brach_name: &str,
         tip: &str,
+        operation: &str,
+    ) -> Result<(), DBError>;
+
+    /// Newest entries first
+    fn read_ref_log(&self, limit: Option<usize>) -> Result<Vec<RefLogEntry>, DBError>;
+    fn read_ref_log_entry(&self, id: i64) -> Result<Option<RefLogEntry>, DBError>;
+
+    /// Renames the branch everywhere, including the `branch` of its commits
+    fn rename_branch(
+        tx: &rusqlite::Transaction,
+        old_name: &str,
+        new_name: &str,
+    ) -> Result<(), DBError>;
+
+    fn read_branch_info(&self, branch_name: &str) -> Result<Option<BranchInfo>, DBError>;
+    fn write_branch_info(tx: &rusqlite::Transaction, info: &BranchInfo) -> Result<(), DBError>;
+
+    /// Oldest first
+    fn read_branch_renames(&self) -> Result<Vec<BranchRename>, DBError>;
+    /// Renames that are already recorded are skipped
+    fn write_branch_rename(
+        tx: &rusqlite::Transaction,
+        rename: &BranchRename,
+    ) -> Result<(), DBError>;
+
+    fn read_retention_policy(&self, branch_name: &str) -> Result<Option<RetentionPolicy>, DBError>;
+    fn write_retention_policy(
+        tx: &rusqlite::Transaction,
+        branch_name: &str,
+        policy: &RetentionPolicy,
     ) -> Result<(), DBError>;
 
+    fn read_all_tags(&self) -> Result<Vec<Tag>, DBError>;
+    fn read_tag(&self, name: &str) -> Result<Option<String>, DBError>;
+    /// Fails if a tag with the same name exists
+    fn write_tag(tx: &rusqlite::Transaction, tag: &Tag) -> Result<(), DBError>;
+
     fn read_remote_branch_tip(&self, branch_name: &str) -> Result<String, DBError>;
     fn write_remote_branch_tip(
         tx: &rusqlite::Transaction,
@@ -73,11 +179,26 @@ pub trait DB: Sized {
     fn read_name(&self) -> Result<Option<String>, DBError>;
     fn write_name(tx: &rusqlite::Transaction, name: &str) -> Result<(), DBError>;
 
+    fn read_hash_algorithm(&self) -> Result<HashAlgorithm, DBError>;
+    fn write_hash_algorithm(
+        tx: &rusqlite::Transaction,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<(), DBError>;
+
+    /// Deletes the branch along with `commit_hashes`, which are left to the
+    /// caller, since commits of the branch can be shared with other branches
     fn delete_branch_with_commits(
         tx: &rusqlite::Transaction,
         branch_name: &str,
+        commit_hashes: &[String],
     ) -> Result<(), DBError>;
 
+    fn read_intents(&self) -> Result<Vec<Intent>, DBError>;
+    fn write_intent(tx: &rusqlite::Transaction, intent: &Intent) -> Result<(), DBError>;
+    fn delete_intent(tx: &rusqlite::Transaction, id: &str) -> Result<(), DBError>;
+    /// The operations that were rolled back when the timeline was opened
+    fn rolled_back_operations(&self) -> &[String];
+
     fn execute_in_transaction<F>(&mut self, f: F) -> Result<(), DBError>
     where
         F: FnOnce(&rusqlite::Transaction) -> Result<(), DBError>;
@@ -86,6 +207,125 @@ pub trait DB: Sized {
 pub struct Persistence {
     rocks_db: rocksdb::DB,
     sqlite_db: rusqlite::Connection,
+    rolled_back: Vec<String>,
+    // declared last, so that it is released after the databases are closed
+    _lock: Option<File>,
+}
+
+/// Stored in the `user_version` pragma of SQLite, bump it whenever the schema
+/// changes. Timelines with a lower version were created by an older version,
+/// and need to be opened for writing once to be upgraded.
+const SCHEMA_VERSION: i64 = 1;
+
+/// The search index refers to commits by `id`, since the implicit rowid of a
+/// table can change
+const COMMITS_COLUMNS: &str = "
+    id INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL UNIQUE,
+    snapshot TEXT,
+    prev_commit_hash TEXT,
+    project_id TEXT,
+    branch TEXT,
+    message TEXT,
+    author TEXT,
+    date INTEGER,
+    header BLOB
+";
+
+const LOCK_FILE_NAME: &str = "timeline.lock";
+const LOCK_OWNER_FILE_NAME: &str = "timeline.owner";
+
+/// How long to wait for another writer to finish before giving up
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn lock_owner_description() -> String {
+    let program = std::env::current_exe()
+        .ok()
+        .and_then(|exe| {
+            exe.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or("unknown program".to_owned());
+
+    format!("{} (process {})", program, std::process::id())
+}
+
+fn read_schema_version(sqlite_db: &rusqlite::Connection) -> Result<i64, DBError> {
+    sqlite_db
+        .query_row("PRAGMA user_version", [], |row| row.get::<usize, i64>(0))
+        .map_err(|e| DBError::Fundamental(format!("Cannot read schema version: {:?}", e)))
+}
+
+/// Takes the advisory lock of the timeline at `path`. The lock is released when
+/// the returned file is closed, including when the process dies.
+fn acquire_lock(path: &str) -> Result<File, DBError> {
+    std::fs::create_dir_all(path)
+        .map_err(|e| DBError::Fundamental(format!("Cannot create timeline folder: {:?}", e)))?;
+
+    let lock_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(Path::new(path).join(LOCK_FILE_NAME))
+        .map_err(|e| DBError::Fundamental(format!("Cannot open lock file: {:?}", e)))?;
+
+    let owner_path = Path::new(path).join(LOCK_OWNER_FILE_NAME);
+    let start = Instant::now();
+
+    loop {
+        match lock_file.try_lock() {
+            Ok(()) => break,
+            Err(TryLockError::WouldBlock) if start.elapsed() < LOCK_TIMEOUT => {
+                sleep(Duration::from_millis(50))
+            }
+            Err(TryLockError::WouldBlock) => {
+                let owner =
+                    std::fs::read_to_string(&owner_path).unwrap_or("another process".to_owned());
+                return Err(DBError::Busy(format!(
+                    "The timeline is being used by {}",
+                    owner.trim()
+                )));
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(DBError::Fundamental(format!(
+                    "Cannot lock timeline: {:?}",
+                    e
+                )))
+            }
+        }
+    }
+
+    // only informational, so failing to write it is not an error
+    let _ = std::fs::write(owner_path, lock_owner_description());
+
+    Ok(lock_file)
+}
+
+/// Blocks are written and read in batches of this size, so that big files
+/// (tens of thousands of blocks) don't need a round trip per block
+const BLOCK_BATCH_SIZE: usize = 4096;
+
+fn rocks_db_options() -> rocksdb::Options {
+    let mut options = rocksdb::Options::default();
+    options.create_if_missing(true);
+
+    let parallelism = available_parallelism().map_or(1, |n| n.get());
+    options.increase_parallelism(parallelism as i32);
+
+    // commits write all new blocks in one go, bigger memtables mean fewer stalls
+    options.set_write_buffer_size(64 * 1024 * 1024);
+    options.set_max_write_buffer_number(4);
+
+    // block data is already compressed with gzip
+    options.set_compression_type(DBCompressionType::None);
+
+    // makes checking for blocks that are already stored cheap
+    let mut table_options = BlockBasedOptions::default();
+    table_options.set_bloom_filter(10.0, false);
+    options.set_block_based_table_factory(&table_options);
+
+    options
 }
 
 #[inline]
@@ -118,6 +358,11 @@ fn user_name_key() -> String {
     "USER_NAME".to_string()
 }
 
+#[inline]
+fn hash_algorithm_key() -> String {
+    "HASH_ALGORITHM".to_string()
+}
+
 fn write_config_inner(tx: &rusqlite::Transaction, key: &str, value: &str) -> Result<(), DBError> {
     tx.execute(
         "INSERT OR REPLACE INTO config (key, value) VALUES (?1, ?2)",
@@ -144,6 +389,175 @@ fn read_config_inner(conn: &rusqlite::Connection, key: &str) -> Result<Option<St
     }
 }
 
+fn ref_log_target_columns(target: &RefLogTarget) -> (&str, Option<&str>) {
+    match target {
+        RefLogTarget::CurrentCommit => ("commit", None),
+        RefLogTarget::CurrentBranch => ("branch", None),
+        RefLogTarget::BranchTip(branch_name) => ("tip", Some(branch_name)),
+    }
+}
+
+fn ref_log_entry_from_row(data: &rusqlite::Row) -> RefLogEntry {
+    let target: String = data.get(1).expect("No target found in row");
+    let branch: Option<String> = data.get(2).expect("No branch found in row");
+    RefLogEntry {
+        id: data.get(0).expect("No id found in row"),
+        target: match (target.as_str(), branch) {
+            ("commit", _) => RefLogTarget::CurrentCommit,
+            ("branch", _) => RefLogTarget::CurrentBranch,
+            (_, branch_name) => RefLogTarget::BranchTip(branch_name.unwrap_or_default()),
+        },
+        old_value: data.get(3).expect("No old_value found in row"),
+        new_value: data.get(4).expect("No new_value found in row"),
+        operation: data.get(5).expect("No operation found in row"),
+        date: data.get(6).expect("No date found in row"),
+    }
+}
+
+/// Appends to the reflog, unless nothing changed
+fn write_ref_log_entry(
+    tx: &rusqlite::Transaction,
+    target: &RefLogTarget,
+    old_value: Option<&str>,
+    new_value: Option<&str>,
+    operation: &str,
+) -> Result<(), DBError> {
+    if old_value == new_value {
+        return Ok(());
+    }
+
+    let (target, branch) = ref_log_target_columns(target);
+    tx.execute(
+        "INSERT INTO ref_log (target, branch, old_value, new_value, operation, date)
+            VALUES (?1, ?2, ?3, ?4, ?5, CAST(strftime('%s', 'now') AS INTEGER))",
+        rusqlite::params![target, branch, old_value, new_value, operation],
+    )
+    .map_err(|e| DBError::Error(format!("Cannot write reflog: {:?}", e)))
+    .map(|_| ())
+}
+
+const REF_LOG_COLUMNS: &str = "id, target, branch, old_value, new_value, operation, date";
+
+/// Reads the merge parents of a commit, in the order they were merged
+fn read_merge_parents(conn: &rusqlite::Connection, hash: &str) -> Result<Vec<String>, DBError> {
+    let mut stmt = conn
+        .prepare_cached("SELECT parent FROM commit_parents WHERE hash = ?1 ORDER BY position")
+        .map_err(|e| DBError::Error(format!("Cannot query merge parents: {:?}", e)))?;
+
+    let parents = stmt
+        .query_map([hash], |row| row.get::<usize, String>(0))
+        .map_err(|e| DBError::Error(format!("Cannot query merge parents: {:?}", e)))?
+        .collect::<Result<Vec<String>, _>>()
+        .map_err(|e| DBError::Error(format!("Cannot read merge parents: {:?}", e)))?;
+
+    Ok(parents)
+}
+
+/// Reads a row of `hash, prev_commit_hash, branch, message, author, date`
+fn commit_info_from_row(
+    conn: &rusqlite::Connection,
+    data: &rusqlite::Row,
+) -> Result<CommitInfo, DBError> {
+    let hash: String = data.get(0).expect("No hash found in row");
+    Ok(CommitInfo {
+        merge_parents: read_merge_parents(conn, &hash)?,
+        hash,
+        prev_commit_hash: data.get(1).expect("No prev_commit_hash found in row"),
+        branch: data.get(2).expect("No branch found in row"),
+        message: data.get(3).expect("No message found in row"),
+        author: data.get(4).expect("No author found in row"),
+        date: data.get(5).expect("No date found in row"),
+    })
+}
+
+/// Turns free text into an FTS5 query where every word has to match the
+/// beginning of a word in the message, so that `sword rig` finds `Rigging the sword`
+fn fts_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Escapes the wildcards of `LIKE`, so that `text` is matched literally
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[inline]
+fn print_hashes(hashes: &[String]) -> String {
+    hashes.join(",")
+}
+
+#[inline]
+fn parse_hashes(hashes: &str) -> Vec<String> {
+    hashes
+        .split(',')
+        .filter(|hash| !hash.is_empty())
+        .map(|hash| hash.to_owned())
+        .collect()
+}
+
+/// Returns the subset of `keys` that are stored in RocksDB
+fn read_stored_keys<'a>(
+    rocks_db: &rocksdb::DB,
+    keys: &'a [String],
+) -> Result<HashSet<&'a String>, DBError> {
+    // The bloom filter can give false positives, those are checked for real
+    let maybe_stored: Vec<&String> = keys
+        .iter()
+        .filter(|key| rocks_db.key_may_exist(key))
+        .collect();
+
+    let mut stored: HashSet<&String> = HashSet::new();
+    for (key, value) in maybe_stored
+        .iter()
+        .zip(rocks_db.multi_get(maybe_stored.iter()))
+    {
+        let value = value.map_err(|e| DBError::Error(format!("Error reading block: {:?}", e)))?;
+        if value.is_some() {
+            stored.insert(key);
+        }
+    }
+
+    Ok(stored)
+}
+
+/// Undoes the operations that were interrupted halfway, see `Intent`, and
+/// returns their names. Only the holder of the writer lock may do this,
+/// otherwise the intent could belong to an operation that is still running in
+/// another process.
+fn roll_back_intents(db: &mut Persistence) -> Result<Vec<String>, DBError> {
+    if db._lock.is_none() {
+        return Err(DBError::Error(
+            "Unfinished operations can only be rolled back by the writer".to_owned(),
+        ));
+    }
+
+    let mut rolled_back: Vec<String> = vec![];
+    for intent in db.read_intents()? {
+        db.delete_blocks(&intent.block_hashes)?;
+        for hash in intent.commit_hashes.iter() {
+            db.delete_blocks_str(hash)?;
+        }
+        db.sync_blocks()?;
+
+        db.execute_in_transaction(|tx| {
+            for hash in intent.commit_hashes.iter() {
+                Persistence::delete_commit(tx, hash)?;
+            }
+
+            Persistence::delete_intent(tx, &intent.id)
+        })?;
+
+        rolled_back.push(intent.operation);
+    }
+
+    Ok(rolled_back)
+}
+
 fn get_blocks_by_hash(rocks_db: &rocksdb::DB, hash: &str) -> Result<String, DBError> {
     rocks_db
         .get(working_dir_key(hash))
@@ -154,30 +568,75 @@ fn get_blocks_by_hash(rocks_db: &rocksdb::DB, hash: &str) -> Result<String, DBEr
 
 impl DB for Persistence {
     fn open(path: &str) -> Result<Self, DBError> {
+        let lock = acquire_lock(path)?;
+
         let sqlite_path = Path::new(path).join("commits.sqlite");
         let rocks_path = Path::new(path).join("blobs.rocks");
 
-        let rocks_db = rocksdb::DB::open_default(rocks_path)
+        let rocks_db = rocksdb::DB::open(&rocks_db_options(), rocks_path)
             .map_err(|e| DBError::Fundamental(format!("Cannot open RocksDB: {:?}", e)))?;
         let sqlite_db = rusqlite::Connection::open(sqlite_path)
             .map_err(|e| DBError::Fundamental(format!("Cannot open SQLite: {:?}", e)))?;
 
+        if read_schema_version(&sqlite_db)? > SCHEMA_VERSION {
+            return Err(DBError::Error(
+                "The timeline was created by a newer version".to_owned(),
+            ));
+        }
+
         sqlite_db
             .execute(
-                "CREATE TABLE IF NOT EXISTS commits (
-                    hash TEXT PRIMARY KEY,
-                    prev_commit_hash TEXT,
-                    project_id TEXT,
-                    branch TEXT,
-                    message TEXT,
-                    author TEXT,
-                    date INTEGER,
-                    header BLOB
-                )",
+                &format!("CREATE TABLE IF NOT EXISTS commits ({})", COMMITS_COLUMNS),
                 [],
             )
             .map_err(|e| DBError::Fundamental(format!("Cannot create commits table: {:?}", e)))?;
 
+        let has_snapshots = sqlite_db
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('commits') WHERE name = 'snapshot'",
+                [],
+                |row| row.get::<usize, i64>(0),
+            )
+            .map_err(|e| DBError::Fundamental(format!("Cannot read schema: {:?}", e)))?
+            > 0;
+
+        if !has_snapshots {
+            // The hash of older commits is the hash of their contents
+            sqlite_db
+                .execute_batch(
+                    "ALTER TABLE commits ADD COLUMN snapshot TEXT;
+                    UPDATE commits SET snapshot = hash;",
+                )
+                .map_err(|e| DBError::Fundamental(format!("Cannot add snapshots: {:?}", e)))?;
+        }
+
+        let has_commit_ids = sqlite_db
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('commits') WHERE name = 'id'",
+                [],
+                |row| row.get::<usize, i64>(0),
+            )
+            .map_err(|e| DBError::Fundamental(format!("Cannot read schema: {:?}", e)))?
+            > 0;
+
+        if !has_commit_ids {
+            // Older commits only have the implicit rowid, which `VACUUM` can
+            // renumber, so the search index could not refer to them by it
+            sqlite_db
+                .execute_batch(&format!(
+                    "BEGIN;
+                    CREATE TABLE commits_with_ids ({});
+                    INSERT INTO commits_with_ids (hash, snapshot, prev_commit_hash, project_id, branch, message, author, date, header)
+                        SELECT hash, snapshot, prev_commit_hash, project_id, branch, message, author, date, header
+                        FROM commits ORDER BY rowid;
+                    DROP TABLE commits;
+                    ALTER TABLE commits_with_ids RENAME TO commits;
+                    COMMIT;",
+                    COMMITS_COLUMNS
+                ))
+                .map_err(|e| DBError::Fundamental(format!("Cannot add commit ids: {:?}", e)))?;
+        }
+
         sqlite_db
             .execute(
                 "CREATE TABLE IF NOT EXISTS branches (
@@ -208,51 +667,318 @@ impl DB for Persistence {
             )
             .map_err(|e| DBError::Fundamental(format!("Cannot create config table: {:?}", e)))?;
 
+        sqlite_db
+            .execute(
+                "CREATE TABLE IF NOT EXISTS hash_aliases (
+                    alias TEXT PRIMARY KEY,
+                    hash TEXT
+                )",
+                [],
+            )
+            .map_err(|e| {
+                DBError::Fundamental(format!("Cannot create hash_aliases table: {:?}", e))
+            })?;
+
+        sqlite_db
+            .execute(
+                "CREATE TABLE IF NOT EXISTS intents (
+                    id TEXT PRIMARY KEY,
+                    operation TEXT,
+                    commit_hashes TEXT,
+                    block_hashes TEXT
+                )",
+                [],
+            )
+            .map_err(|e| DBError::Fundamental(format!("Cannot create intents table: {:?}", e)))?;
+
+        sqlite_db
+            .execute(
+                "CREATE TABLE IF NOT EXISTS tags (
+                    name TEXT PRIMARY KEY,
+                    hash TEXT
+                )",
+                [],
+            )
+            .map_err(|e| DBError::Fundamental(format!("Cannot create tags table: {:?}", e)))?;
+
+        sqlite_db
+            .execute(
+                "CREATE TABLE IF NOT EXISTS branch_info (
+                    name TEXT PRIMARY KEY,
+                    base TEXT,
+                    created INTEGER,
+                    description TEXT
+                )",
+                [],
+            )
+            .map_err(|e| {
+                DBError::Fundamental(format!("Cannot create branch_info table: {:?}", e))
+            })?;
+
+        let has_search_index = sqlite_db
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name = 'commit_search'",
+                [],
+                |row| row.get::<usize, i64>(0),
+            )
+            .map_err(|e| DBError::Fundamental(format!("Cannot read schema: {:?}", e)))?
+            > 0;
+
+        // the index reads the messages from `commits` by id, and is kept up
+        // to date by triggers, so every way of changing the commits
+        // (importing, rolling back, deleting branches) is covered
+        sqlite_db
+            .execute_batch(
+                "CREATE VIRTUAL TABLE IF NOT EXISTS commit_search USING fts5(message, content='commits', content_rowid='id');
+
+                CREATE TRIGGER IF NOT EXISTS commit_search_insert AFTER INSERT ON commits BEGIN
+                    INSERT INTO commit_search (rowid, message) VALUES (new.id, new.message);
+                END;
+
+                CREATE TRIGGER IF NOT EXISTS commit_search_delete AFTER DELETE ON commits BEGIN
+                    INSERT INTO commit_search (commit_search, rowid, message) VALUES ('delete', old.id, old.message);
+                END;
+
+                CREATE TRIGGER IF NOT EXISTS commit_search_update AFTER UPDATE OF message ON commits BEGIN
+                    INSERT INTO commit_search (commit_search, rowid, message) VALUES ('delete', old.id, old.message);
+                    INSERT INTO commit_search (rowid, message) VALUES (new.id, new.message);
+                END;",
+            )
+            .map_err(|e| DBError::Fundamental(format!("Cannot create search index: {:?}", e)))?;
+
+        if !has_search_index {
+            sqlite_db
+                .execute(
+                    "INSERT INTO commit_search (commit_search) VALUES ('rebuild')",
+                    [],
+                )
+                .map_err(|e| DBError::Fundamental(format!("Cannot build search index: {:?}", e)))?;
+        }
+
+        sqlite_db
+            .execute(
+                "CREATE TABLE IF NOT EXISTS commit_metadata (
+                    hash TEXT,
+                    key TEXT,
+                    value TEXT,
+                    hashed INTEGER NOT NULL DEFAULT 1,
+                    PRIMARY KEY (hash, key)
+                )",
+                [],
+            )
+            .map_err(|e| {
+                DBError::Fundamental(format!("Cannot create commit_metadata table: {:?}", e))
+            })?;
+
+        sqlite_db
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS commit_parents (
+                    hash TEXT,
+                    parent TEXT,
+                    position INTEGER,
+                    PRIMARY KEY (hash, position)
+                );
+                CREATE INDEX IF NOT EXISTS commit_parents_parent ON commit_parents (parent);",
+            )
+            .map_err(|e| {
+                DBError::Fundamental(format!("Cannot create commit_parents table: {:?}", e))
+            })?;
+
+        sqlite_db
+            .execute(
+                "CREATE TABLE IF NOT EXISTS ref_log (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    target TEXT NOT NULL,
+                    branch TEXT,
+                    old_value TEXT,
+                    new_value TEXT,
+                    operation TEXT NOT NULL,
+                    date INTEGER NOT NULL
+                )",
+                [],
+            )
+            .map_err(|e| DBError::Fundamental(format!("Cannot create ref_log table: {:?}", e)))?;
+
+        sqlite_db
+            .execute(
+                "CREATE TABLE IF NOT EXISTS retention_policies (
+                    branch TEXT PRIMARY KEY,
+                    keep_all_for INTEGER,
+                    keep_hourly_for INTEGER
+                )",
+                [],
+            )
+            .map_err(|e| {
+                DBError::Fundamental(format!("Cannot create retention_policies table: {:?}", e))
+            })?;
+
+        sqlite_db
+            .execute(
+                "CREATE TABLE IF NOT EXISTS branch_renames (
+                    old_name TEXT,
+                    new_name TEXT,
+                    date INTEGER,
+                    PRIMARY KEY (old_name, new_name, date)
+                )",
+                [],
+            )
+            .map_err(|e| {
+                DBError::Fundamental(format!("Cannot create branch_renames table: {:?}", e))
+            })?;
+
+        sqlite_db
+            .pragma_update(None, "user_version", SCHEMA_VERSION)
+            .map_err(|e| DBError::Fundamental(format!("Cannot write schema version: {:?}", e)))?;
+
+        let mut db = Self {
+            rocks_db,
+            sqlite_db,
+            rolled_back: vec![],
+            _lock: Some(lock),
+        };
+
+        db.rolled_back = roll_back_intents(&mut db)?;
+
+        Ok(db)
+    }
+
+    fn open_read_only(path: &str) -> Result<Self, DBError> {
+        let sqlite_path = Path::new(path).join("commits.sqlite");
+        let rocks_path = Path::new(path).join("blobs.rocks");
+
+        let rocks_db = rocksdb::DB::open_for_read_only(&rocks_db_options(), rocks_path, false)
+            .map_err(|e| DBError::Fundamental(format!("Cannot open RocksDB: {:?}", e)))?;
+        let sqlite_db = rusqlite::Connection::open_with_flags(
+            &sqlite_path,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(|e| DBError::Fundamental(format!("Cannot open SQLite: {:?}", e)))?;
+
+        let schema_version = read_schema_version(&sqlite_db)?;
+
+        if schema_version < SCHEMA_VERSION {
+            return Err(DBError::Error(
+                "The timeline needs upgrading, open it for writing once".to_owned(),
+            ));
+        }
+        if schema_version > SCHEMA_VERSION {
+            return Err(DBError::Error(
+                "The timeline was created by a newer version".to_owned(),
+            ));
+        }
+
         Ok(Self {
             rocks_db,
             sqlite_db,
+            rolled_back: vec![],
+            _lock: None,
         })
     }
 
     fn write_blocks(&self, blocks: &[BlockRecord]) -> Result<(), DBError> {
-        for block in blocks {
+        for chunk in blocks.chunks(BLOCK_BATCH_SIZE) {
+            let keys: Vec<String> = chunk.iter().map(|b| block_hash_key(&b.hash)).collect();
+
+            // blocks are content addressed, so the ones already stored can be skipped
+            let stored = read_stored_keys(&self.rocks_db, &keys)?;
+
+            let mut batch = WriteBatch::default();
+            for (key, block) in keys.iter().zip(chunk) {
+                if !stored.contains(key) {
+                    batch.put(key, &block.data);
+                }
+            }
+
             self.rocks_db
-                .put(block_hash_key(&block.hash), &block.data)
-                .map_err(|e| DBError::Error(format!("Cannot write block: {:?}", e)))?;
+                .write(batch)
+                .map_err(|e| DBError::Error(format!("Cannot write blocks: {:?}", e)))?;
         }
 
         Ok(())
     }
 
     fn read_blocks(&self, hashes: Vec<String>) -> Result<Vec<BlockRecord>, DBError> {
-        let mut result: Vec<BlockRecord> = Vec::new();
-        for hash in hashes {
-            let block_data = self
+        let mut result: Vec<BlockRecord> = Vec::with_capacity(hashes.len());
+        let mut missing: Vec<String> = vec![];
+
+        for chunk in hashes.chunks(BLOCK_BATCH_SIZE) {
+            let values = self
                 .rocks_db
-                .get(block_hash_key(&hash))
-                .map_err(|e| DBError::Error(format!("Error reading block: {:?}", e)))?
-                .ok_or(DBError::Error("No block with hash found".to_owned()))?;
+                .multi_get(chunk.iter().map(|hash| block_hash_key(hash)));
+
+            for (hash, value) in chunk.iter().zip(values) {
+                match value.map_err(|e| DBError::Error(format!("Error reading block: {:?}", e)))? {
+                    Some(data) => result.push(BlockRecord {
+                        hash: hash.clone(),
+                        data,
+                    }),
+                    None => missing.push(hash.clone()),
+                }
+            }
+        }
 
-            result.push(BlockRecord {
-                hash,
-                data: block_data,
-            })
+        if !missing.is_empty() {
+            return Err(DBError::Consistency(format!(
+                "{} blocks are missing: {}",
+                missing.len(),
+                missing.join(", ")
+            )));
         }
 
         Ok(result)
     }
 
+    fn read_missing_block_hashes(&self, hashes: &[String]) -> Result<Vec<String>, DBError> {
+        let mut missing: Vec<String> = vec![];
+
+        for chunk in hashes.chunks(BLOCK_BATCH_SIZE) {
+            let keys: Vec<String> = chunk.iter().map(|hash| block_hash_key(hash)).collect();
+            let stored = read_stored_keys(&self.rocks_db, &keys)?;
+
+            for (key, hash) in keys.iter().zip(chunk) {
+                if !stored.contains(key) {
+                    missing.push(hash.clone());
+                }
+            }
+        }
+
+        Ok(missing)
+    }
+
+    fn sync_blocks(&self) -> Result<(), DBError> {
+        self.rocks_db
+            .flush_wal(true)
+            .map_err(|e| DBError::Fundamental(format!("Cannot sync RocksDB: {:?}", e)))
+    }
+
+    fn delete_blocks(&self, hashes: &[String]) -> Result<(), DBError> {
+        for hash in hashes {
+            self.rocks_db
+                .delete(block_hash_key(hash))
+                .map_err(|e| DBError::Error(format!("Cannot delete block: {:?}", e)))?;
+        }
+
+        Ok(())
+    }
+
     fn write_blocks_str(&self, hash: &str, blocks_str: &str) -> Result<(), DBError> {
         self.rocks_db
             .put(working_dir_key(hash), blocks_str)
             .map_err(|_| DBError::Error("Cannot write working dir blocks".to_owned()))
     }
 
+    fn delete_blocks_str(&self, hash: &str) -> Result<(), DBError> {
+        self.rocks_db
+            .delete(working_dir_key(hash))
+            .map_err(|_| DBError::Error("Cannot delete working dir blocks".to_owned()))
+    }
+
     fn write_commit(tx: &rusqlite::Transaction, commit: Commit) -> Result<(), DBError> {
         tx.execute(
-            "INSERT INTO commits (hash, prev_commit_hash, project_id, branch, message, author, date, header) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            "INSERT INTO commits (hash, snapshot, prev_commit_hash, project_id, branch, message, author, date, header) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
             (
-                commit.hash,
+                &commit.hash,
+                commit.snapshot,
                 commit.prev_commit_hash,
                 commit.project_id,
                 commit.branch,
@@ -264,6 +990,25 @@ impl DB for Persistence {
         )
         .map_err(|e| DBError::Error(format!("Cannot insert commit object: {:?}", e)))?;
 
+        for (position, parent) in commit.merge_parents.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO commit_parents (hash, parent, position) VALUES (?1, ?2, ?3)",
+                rusqlite::params![commit.hash, parent, position as i64 + 1],
+            )
+            .map_err(|e| DBError::Error(format!("Cannot insert commit parent: {:?}", e)))?;
+        }
+
+        Ok(())
+    }
+
+    fn delete_commit(tx: &rusqlite::Transaction, hash: &str) -> Result<(), DBError> {
+        tx.execute("DELETE FROM commits WHERE hash = ?1", [hash])
+            .map_err(|e| DBError::Error(format!("Cannot delete commit: {:?}", e)))?;
+        tx.execute("DELETE FROM commit_metadata WHERE hash = ?1", [hash])
+            .map_err(|e| DBError::Error(format!("Cannot delete metadata: {:?}", e)))?;
+        tx.execute("DELETE FROM commit_parents WHERE hash = ?1", [hash])
+            .map_err(|e| DBError::Error(format!("Cannot delete parents: {:?}", e)))?;
+
         Ok(())
     }
 
@@ -275,9 +1020,13 @@ impl DB for Persistence {
             .map(|bs| String::from_utf8(bs).unwrap())
             .ok_or(DBError::Consistency("No working dir found".to_owned()))?;
 
-        self.sqlite_db.query_row("SELECT hash, prev_commit_hash, project_id, branch, message, author, date, header FROM commits WHERE hash = ?1", [hash], |row| Ok(Some(Commit {
+        let merge_parents = read_merge_parents(&self.sqlite_db, hash)?;
+
+        self.sqlite_db.query_row("SELECT hash, prev_commit_hash, project_id, branch, message, author, date, header, snapshot FROM commits WHERE hash = ?1", [hash], |row| Ok(Some(Commit {
             hash: row.get(0).expect("No hash found in row"),
+            snapshot: row.get(8).expect("No snapshot found in row"),
             prev_commit_hash: row.get(1).expect("No prev_commit_hash found in row"),
+            merge_parents,
             project_id: row.get(2).expect("No project_id found in row"),
             branch: row.get(3).expect("No branch found in row"),
             message: row.get(4).expect("No message found in row"),
@@ -288,6 +1037,124 @@ impl DB for Persistence {
         }))).map_err(|e| DBError::Error(format!("Cannot read commit: {:?}", e)))
     }
 
+    fn commit_exists(&self, hash: &str) -> Result<bool, DBError> {
+        self.sqlite_db
+            .query_row(
+                "SELECT COUNT(*) FROM commits WHERE hash = ?1",
+                [hash],
+                |row| row.get::<usize, i64>(0),
+            )
+            .map(|count| count > 0)
+            .map_err(|e| DBError::Error(format!("Cannot query commits: {:?}", e)))
+    }
+
+    fn read_all_commits(&self) -> Result<Vec<Commit>, DBError> {
+        let mut stmt = self
+            .sqlite_db
+            .prepare("SELECT hash, prev_commit_hash, project_id, branch, message, author, date, header, snapshot FROM commits ORDER BY date ASC")
+            .map_err(|e| {
+                DBError::Fundamental(format!("Cannot prepare read commits query: {:?}", e))
+            })?;
+
+        let mut rows = stmt
+            .query([])
+            .map_err(|e| DBError::Error(format!("Cannot read commits: {:?}", e)))?;
+
+        let mut result: Vec<Commit> = vec![];
+
+        while let Ok(Some(data)) = rows.next() {
+            let hash: String = data.get(0).expect("No hash found in row");
+            let blocks = get_blocks_by_hash(&self.rocks_db, &hash)?;
+            let merge_parents = read_merge_parents(&self.sqlite_db, &hash)?;
+
+            result.push(Commit {
+                hash,
+                snapshot: data.get(8).expect("No snapshot found in row"),
+                prev_commit_hash: data.get(1).expect("No prev_commit_hash found in row"),
+                merge_parents,
+                project_id: data.get(2).expect("No project_id found in row"),
+                branch: data.get(3).expect("No branch found in row"),
+                message: data.get(4).expect("No message found in row"),
+                author: data.get(5).expect("No author found in row"),
+                date: data.get(6).expect("No date found in row"),
+                header: data.get(7).expect("No header found in row"),
+                blocks,
+            })
+        }
+
+        Ok(result)
+    }
+
+    fn rename_commit_hash(
+        tx: &rusqlite::Transaction,
+        old_hash: &str,
+        new_hash: &str,
+    ) -> Result<(), DBError> {
+        let statements = [
+            "UPDATE commits SET hash = ?2 WHERE hash = ?1",
+            "UPDATE commits SET prev_commit_hash = ?2 WHERE prev_commit_hash = ?1",
+            "UPDATE commit_parents SET hash = ?2 WHERE hash = ?1",
+            "UPDATE commit_parents SET parent = ?2 WHERE parent = ?1",
+            "UPDATE branches SET tip = ?2 WHERE tip = ?1",
+            "UPDATE remote_branches SET tip = ?2 WHERE tip = ?1",
+            "UPDATE tags SET hash = ?2 WHERE hash = ?1",
+            "UPDATE commit_metadata SET hash = ?2 WHERE hash = ?1",
+            "UPDATE branch_info SET base = ?2 WHERE base = ?1",
+            "UPDATE hash_aliases SET hash = ?2 WHERE hash = ?1",
+            "INSERT OR REPLACE INTO hash_aliases (alias, hash) VALUES (?1, ?2)",
+        ];
+
+        for statement in statements {
+            tx.execute(statement, [old_hash, new_hash])
+                .map_err(|e| DBError::Error(format!("Cannot rename commit hash: {:?}", e)))?;
+        }
+
+        tx.execute(
+            "UPDATE config SET value = ?3 WHERE key = ?1 AND value = ?2",
+            [current_latest_commit_key().as_str(), old_hash, new_hash],
+        )
+        .map_err(|e| DBError::Error(format!("Cannot rename commit hash: {:?}", e)))?;
+
+        Ok(())
+    }
+
+    fn write_commit_snapshot(
+        tx: &rusqlite::Transaction,
+        hash: &str,
+        snapshot: &str,
+    ) -> Result<(), DBError> {
+        tx.execute(
+            "UPDATE commits SET snapshot = ?2 WHERE hash = ?1",
+            [hash, snapshot],
+        )
+        .map_err(|e| DBError::Error(format!("Cannot write snapshot: {:?}", e)))?;
+
+        Ok(())
+    }
+
+    fn read_hash_alias(&self, alias: &str) -> Result<Option<String>, DBError> {
+        let mut stmt = self
+            .sqlite_db
+            .prepare("SELECT hash FROM hash_aliases WHERE alias = ?1")
+            .map_err(|e| DBError::Error(format!("Cannot query hash aliases: {:?}", e)))?;
+
+        let mut rows = stmt
+            .query([alias])
+            .map_err(|e| DBError::Error(format!("Cannot query hash aliases: {:?}", e)))?;
+
+        match rows.next() {
+            Ok(Some(data)) => data
+                .get(0)
+                .map(Some)
+                .map_err(|e| DBError::Fundamental(format!("Cannot read hash alias: {:?}", e))),
+            Ok(None) => Ok(None),
+            Err(e) => Err(DBError::Error(format!(
+                "Cannot query hash aliases: {:?}",
+                e
+            ))),
+        }
+    }
+
     fn read_ancestors_of_commit(
         &self,
         starting_from_hash: &str,
@@ -296,13 +1163,20 @@ impl DB for Persistence {
             .sqlite_db
             .prepare(
                 "
-                WITH RECURSIVE ancestor_commits(hash, branch, message, prev_commit_hash, date) AS (
-                    SELECT hash, branch, message, prev_commit_hash, date FROM commits WHERE hash = ?1
+                WITH RECURSIVE parent_links(hash, parent) AS (
+                    SELECT hash, prev_commit_hash FROM commits
                     UNION ALL
-                    SELECT c.hash, c.branch, c.message, c.prev_commit_hash, c.date FROM commits c
-                    JOIN ancestor_commits a ON a.prev_commit_hash = c.hash
+                    SELECT hash, parent FROM commit_parents
+                ),
+                ancestor_commits(hash) AS (
+                    SELECT ?1
+                    UNION
+                    SELECT l.parent FROM parent_links l
+                    JOIN ancestor_commits a ON l.hash = a.hash
                 )
-                SELECT hash, branch, message FROM ancestor_commits ORDER BY date DESC;
+                SELECT c.hash, c.branch, c.message FROM commits c
+                JOIN ancestor_commits a ON a.hash = c.hash
+                ORDER BY c.date DESC;
                 ",
             )
             .map_err(|e| {
@@ -325,96 +1199,480 @@ impl DB for Persistence {
         Ok(result)
     }
 
-    fn read_descendants_of_commit(&self, hash: &str) -> Result<Vec<Commit>, DBError> {
+    fn read_descendants_of_commit(&self, hash: &str) -> Result<Vec<Commit>, DBError> {
+        let mut stmt = self
+            .sqlite_db
+            .prepare(
+                "
+                WITH RECURSIVE parent_links(hash, parent) AS (
+                    SELECT hash, prev_commit_hash FROM commits
+                    UNION ALL
+                    SELECT hash, parent FROM commit_parents
+                ),
+                descendant_commits(hash) AS (
+                    SELECT ?1
+                    UNION
+                    SELECT l.hash FROM parent_links l
+                    JOIN descendant_commits d ON l.parent = d.hash
+                )
+                SELECT hash, prev_commit_hash, project_id, branch, message, author, date, header, snapshot FROM commits
+                WHERE hash IN descendant_commits ORDER BY date ASC;
+                ",
+            )
+            .map_err(|e| {
+                DBError::Fundamental(format!("Cannot prepare read commits query: {:?}", e))
+            })?;
+
+        let mut rows = stmt
+            .query([hash])
+            .map_err(|e| DBError::Error(format!("Cannot read commits: {:?}", e)))?;
+
+        let mut result: Vec<Commit> = vec![];
+
+        while let Ok(Some(data)) = rows.next() {
+            let hash: String = data
+                .get::<usize, String>(0)
+                .expect("No hash found in row")
+                .to_string();
+
+            let blocks = get_blocks_by_hash(&self.rocks_db, &hash)?;
+            let merge_parents = read_merge_parents(&self.sqlite_db, &hash)?;
+
+            result.push(Commit {
+                hash,
+                snapshot: data.get(8).expect("No snapshot found in row"),
+                prev_commit_hash: data.get(1).expect("No prev_commit_hash found in row"),
+                merge_parents,
+                project_id: data.get(2).expect("No project_id found in row"),
+                branch: data.get(3).expect("No branch found in row"),
+                message: data.get(4).expect("No message found in row"),
+                author: data.get(5).expect("No author found in row"),
+                date: data.get(6).expect("No date found in row"),
+                header: data.get(7).expect("No header found in row"),
+                blocks,
+            })
+        }
+
+        Ok(result)
+    }
+
+    fn read_current_branch_name(&self) -> Result<String, DBError> {
+        read_config_inner(&self.sqlite_db, &current_branch_name_key())
+            .map_err(|_| DBError::Error("Cannot read current branch name".to_owned()))
+            .and_then(|v| {
+                v.map_or(
+                    Err(DBError::Consistency(
+                        "Cannot read current branch name".to_owned(),
+                    )),
+                    Ok,
+                )
+            })
+    }
+
+    fn write_current_branch_name(
+        tx: &rusqlite::Transaction,
+        brach_name: &str,
+        operation: &str,
+    ) -> Result<(), DBError> {
+        let old_value = read_config_inner(tx, &current_branch_name_key())?;
+        write_ref_log_entry(
+            tx,
+            &RefLogTarget::CurrentBranch,
+            old_value.as_deref(),
+            Some(brach_name),
+            operation,
+        )?;
+        write_config_inner(tx, &current_branch_name_key(), brach_name)
+    }
+
+    fn read_all_branches(&self) -> Result<Vec<String>, DBError> {
+        let mut stmt = self
+            .sqlite_db
+            .prepare("SELECT name FROM branches")
+            .map_err(|e| DBError::Error(format!("Cannot query branches: {:?}", e)))?;
+        let mut rows = stmt
+            .query([])
+            .map_err(|e| DBError::Error(format!("Cannot query branches: {:?}", e)))?;
+
+        let mut result: Vec<String> = vec![];
+
+        while let Ok(Some(data)) = rows.next() {
+            let name = data.get(0).map_err(|e| {
+                DBError::Fundamental(format!("Branch name not returned in result set: {:?}", e))
+            })?;
+
+            result.push(name);
+        }
+
+        Ok(result)
+    }
+
+    fn read_history(&self, query: &HistoryQuery) -> Result<Vec<CommitInfo>, DBError> {
+        let mut conditions: Vec<&str> = vec![];
+        let mut params: Vec<Box<dyn ToSql>> = vec![];
+
+        if let Some(author) = &query.author {
+            conditions.push("author = ?");
+            params.push(Box::new(author.clone()));
+        }
+
+        if let Some(branch) = &query.branch {
+            conditions.push("branch = ?");
+            params.push(Box::new(branch.clone()));
+        }
+
+        if let Some(text) = &query.message_contains {
+            conditions.push("message LIKE ? ESCAPE '\\'");
+            params.push(Box::new(format!("%{}%", escape_like(text))));
+        }
+
+        if let Some(since) = query.since {
+            conditions.push("date >= ?");
+            params.push(Box::new(since as i64));
+        }
+
+        if let Some(until) = query.until {
+            conditions.push("date <= ?");
+            params.push(Box::new(until as i64));
+        }
+
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+
+        let order = match query.order {
+            HistoryOrder::NewestFirst => "DESC",
+            HistoryOrder::OldestFirst => "ASC",
+        };
+
+        // a negative limit means no limit in SQLite
+        params.push(Box::new(query.limit.map_or(-1, |limit| limit as i64)));
+        params.push(Box::new(query.offset as i64));
+
+        let sql = format!(
+            "SELECT hash, prev_commit_hash, branch, message, author, date FROM commits
+            {} ORDER BY date {}, id {} LIMIT ? OFFSET ?",
+            where_clause, order, order
+        );
+
+        let mut stmt = self
+            .sqlite_db
+            .prepare(&sql)
+            .map_err(|e| DBError::Fundamental(format!("Cannot prepare query: {:?}", e)))?;
+
+        let mut rows = stmt
+            .query(rusqlite::params_from_iter(params.iter()))
+            .map_err(|e| DBError::Error(format!("Cannot read commits: {:?}", e)))?;
+
+        let mut result: Vec<CommitInfo> = vec![];
+        while let Ok(Some(data)) = rows.next() {
+            result.push(commit_info_from_row(&self.sqlite_db, data)?)
+        }
+
+        Ok(result)
+    }
+
+    fn search_commits(&self, text: &str, limit: usize) -> Result<Vec<CommitInfo>, DBError> {
+        let query = fts_query(text);
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut stmt = self
+            .sqlite_db
+            .prepare(
+                "SELECT commits.hash, commits.prev_commit_hash, commits.branch, commits.message, commits.author, commits.date
+                FROM commit_search JOIN commits ON commits.id = commit_search.rowid
+                WHERE commit_search MATCH ?1
+                ORDER BY commit_search.rank, commits.date DESC
+                LIMIT ?2",
+            )
+            .map_err(|e| DBError::Fundamental(format!("Cannot prepare query: {:?}", e)))?;
+
+        let mut rows = stmt
+            .query(rusqlite::params![query, limit as i64])
+            .map_err(|e| DBError::Error(format!("Cannot search commits: {:?}", e)))?;
+
+        let mut result: Vec<CommitInfo> = vec![];
+        while let Ok(Some(data)) = rows.next() {
+            result.push(commit_info_from_row(&self.sqlite_db, data)?)
+        }
+
+        Ok(result)
+    }
+
+    fn read_commit_metadata(&self, hash: &str) -> Result<Vec<CommitMetadata>, DBError> {
+        let mut stmt = self
+            .sqlite_db
+            .prepare("SELECT key, value, hashed FROM commit_metadata WHERE hash = ?1 ORDER BY key")
+            .map_err(|e| DBError::Error(format!("Cannot query metadata: {:?}", e)))?;
+        let mut rows = stmt
+            .query([hash])
+            .map_err(|e| DBError::Error(format!("Cannot query metadata: {:?}", e)))?;
+
+        let mut result: Vec<CommitMetadata> = vec![];
+
+        while let Ok(Some(data)) = rows.next() {
+            result.push(CommitMetadata {
+                hash: hash.to_owned(),
+                key: data.get(0).expect("No key found in row"),
+                value: data.get(1).expect("No value found in row"),
+                hashed: data.get(2).expect("No hashed found in row"),
+            });
+        }
+
+        Ok(result)
+    }
+
+    fn write_commit_metadata(
+        tx: &rusqlite::Transaction,
+        metadata: &CommitMetadata,
+    ) -> Result<(), DBError> {
+        tx.execute(
+            "INSERT OR REPLACE INTO commit_metadata (hash, key, value, hashed) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![metadata.hash, metadata.key, metadata.value, metadata.hashed],
+        )
+        .map_err(|e| DBError::Error(format!("Cannot write metadata: {:?}", e)))
+        .map(|_| ())
+    }
+
+    fn read_commits_with_metadata(
+        &self,
+        key: &str,
+        value: &str,
+    ) -> Result<Vec<ShortCommitRecord>, DBError> {
+        let mut stmt = self
+            .sqlite_db
+            .prepare(
+                "SELECT commits.hash, commits.branch, commits.message
+                FROM commits JOIN commit_metadata ON commits.hash = commit_metadata.hash
+                WHERE commit_metadata.key = ?1 AND commit_metadata.value = ?2
+                ORDER BY commits.date ASC",
+            )
+            .map_err(|e| DBError::Error(format!("Cannot query metadata: {:?}", e)))?;
+        let mut rows = stmt
+            .query([key, value])
+            .map_err(|e| DBError::Error(format!("Cannot query metadata: {:?}", e)))?;
+
+        let mut result: Vec<ShortCommitRecord> = vec![];
+        while let Ok(Some(data)) = rows.next() {
+            result.push(ShortCommitRecord {
+                hash: data.get(0).expect("cannot get hash"),
+                branch: data.get(1).expect("cannot get branch"),
+                message: data.get(2).expect("cannot read message"),
+            })
+        }
+
+        Ok(result)
+    }
+
+    fn rename_branch(
+        tx: &rusqlite::Transaction,
+        old_name: &str,
+        new_name: &str,
+    ) -> Result<(), DBError> {
+        let tip: Option<String> = tx
+            .query_row(
+                "SELECT tip FROM branches WHERE name = ?1",
+                [old_name],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| DBError::Error(format!("Cannot rename branch: {:?}", e)))?;
+        let current_branch_name = read_config_inner(tx, &current_branch_name_key())?;
+
+        write_ref_log_entry(
+            tx,
+            &RefLogTarget::BranchTip(old_name.to_owned()),
+            tip.as_deref(),
+            None,
+            "rename_branch",
+        )?;
+        write_ref_log_entry(
+            tx,
+            &RefLogTarget::BranchTip(new_name.to_owned()),
+            None,
+            tip.as_deref(),
+            "rename_branch",
+        )?;
+        if current_branch_name.as_deref() == Some(old_name) {
+            write_ref_log_entry(
+                tx,
+                &RefLogTarget::CurrentBranch,
+                Some(old_name),
+                Some(new_name),
+                "rename_branch",
+            )?;
+        }
+
+        let statements = [
+            "UPDATE branches SET name = ?2 WHERE name = ?1",
+            "UPDATE remote_branches SET name = ?2 WHERE name = ?1",
+            "UPDATE branch_info SET name = ?2 WHERE name = ?1",
+            "UPDATE retention_policies SET branch = ?2 WHERE branch = ?1",
+            "UPDATE commits SET branch = ?2 WHERE branch = ?1",
+        ];
+
+        for statement in statements {
+            tx.execute(statement, [old_name, new_name])
+                .map_err(|e| DBError::Error(format!("Cannot rename branch: {:?}", e)))?;
+        }
+
+        tx.execute(
+            "UPDATE config SET value = ?3 WHERE key = ?1 AND value = ?2",
+            [current_branch_name_key().as_str(), old_name, new_name],
+        )
+        .map_err(|e| DBError::Error(format!("Cannot rename branch: {:?}", e)))?;
+
+        Ok(())
+    }
+
+    fn read_branch_info(&self, branch_name: &str) -> Result<Option<BranchInfo>, DBError> {
+        let mut stmt = self
+            .sqlite_db
+            .prepare("SELECT base, created, description FROM branch_info WHERE name = ?1")
+            .map_err(|e| DBError::Error(format!("Cannot query branch info: {:?}", e)))?;
+
+        let mut rows = stmt
+            .query([branch_name])
+            .map_err(|e| DBError::Error(format!("Cannot query branch info: {:?}", e)))?;
+
+        match rows.next() {
+            Ok(Some(data)) => Ok(Some(BranchInfo {
+                name: branch_name.to_owned(),
+                base: data.get(0).expect("No base found in row"),
+                created: data.get(1).expect("No created found in row"),
+                description: data.get(2).expect("No description found in row"),
+            })),
+            Ok(None) => Ok(None),
+            Err(e) => Err(DBError::Error(format!("Cannot query branch info: {:?}", e))),
+        }
+    }
+
+    fn write_branch_info(tx: &rusqlite::Transaction, info: &BranchInfo) -> Result<(), DBError> {
+        tx.execute(
+            "INSERT OR REPLACE INTO branch_info (name, base, created, description) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![info.name, info.base, info.created, info.description],
+        )
+        .map_err(|e| DBError::Error(format!("Cannot write branch info: {:?}", e)))
+        .map(|_| ())
+    }
+
+    fn read_branch_renames(&self) -> Result<Vec<BranchRename>, DBError> {
         let mut stmt = self
             .sqlite_db
-            .prepare(
-                "
-                WITH RECURSIVE ancestor_commits(hash, prev_commit_hash, project_id, branch, message, author, date, header) AS (
-                    SELECT hash, prev_commit_hash, project_id, branch, message, author, date, header FROM commits WHERE hash = ?1
-                    UNION ALL
-                    SELECT c.hash, c.prev_commit_hash, c.project_id, c.branch, c.message, c.author, c.date, c.header FROM commits c
-                    JOIN ancestor_commits a ON c.prev_commit_hash = a.hash
-                )
-                SELECT hash, prev_commit_hash, project_id, branch, message, author, date, header FROM ancestor_commits ORDER BY date ASC;
-                ",
-            )
-            .map_err(|e| {
-                DBError::Fundamental(format!("Cannot prepare read commits query: {:?}", e))
-            })?;
-
+            .prepare("SELECT old_name, new_name, date FROM branch_renames ORDER BY date, rowid")
+            .map_err(|e| DBError::Error(format!("Cannot query branch renames: {:?}", e)))?;
         let mut rows = stmt
-            .query([hash])
-            .map_err(|e| DBError::Error(format!("Cannot read commits: {:?}", e)))?;
+            .query([])
+            .map_err(|e| DBError::Error(format!("Cannot query branch renames: {:?}", e)))?;
 
-        let mut result: Vec<Commit> = vec![];
+        let mut result: Vec<BranchRename> = vec![];
 
         while let Ok(Some(data)) = rows.next() {
-            let hash: String = data
-                .get::<usize, String>(0)
-                .expect("No hash found in row")
-                .to_string();
-
-            let blocks = get_blocks_by_hash(&self.rocks_db, &hash)?;
-
-            result.push(Commit {
-                hash,
-                prev_commit_hash: data.get(1).expect("No prev_commit_hash found in row"),
-                project_id: data.get(2).expect("No project_id found in row"),
-                branch: data.get(3).expect("No branch found in row"),
-                message: data.get(4).expect("No message found in row"),
-                author: data.get(5).expect("No author found in row"),
-                date: data.get(6).expect("No date found in row"),
-                header: data.get(7).expect("No header found in row"),
-                blocks,
-            })
+            result.push(BranchRename {
+                old_name: data.get(0).expect("No old name found in row"),
+                new_name: data.get(1).expect("No new name found in row"),
+                date: data.get(2).expect("No date found in row"),
+            });
         }
 
         Ok(result)
     }
 
-    fn read_current_branch_name(&self) -> Result<String, DBError> {
-        read_config_inner(&self.sqlite_db, &current_branch_name_key())
-            .map_err(|_| DBError::Error("Cannot read current branch name".to_owned()))
-            .and_then(|v| {
-                v.map_or(
-                    Err(DBError::Consistency(
-                        "Cannot read current branch name".to_owned(),
-                    )),
-                    Ok,
-                )
-            })
+    fn write_branch_rename(
+        tx: &rusqlite::Transaction,
+        rename: &BranchRename,
+    ) -> Result<(), DBError> {
+        tx.execute(
+            "INSERT OR IGNORE INTO branch_renames (old_name, new_name, date) VALUES (?1, ?2, ?3)",
+            rusqlite::params![rename.old_name, rename.new_name, rename.date],
+        )
+        .map_err(|e| DBError::Error(format!("Cannot write branch rename: {:?}", e)))
+        .map(|_| ())
     }
 
-    fn write_current_branch_name(
+    fn read_retention_policy(&self, branch_name: &str) -> Result<Option<RetentionPolicy>, DBError> {
+        self.sqlite_db
+            .query_row(
+                "SELECT keep_all_for, keep_hourly_for FROM retention_policies WHERE branch = ?1",
+                [branch_name],
+                |row| {
+                    Ok(RetentionPolicy {
+                        keep_all_for: row.get(0)?,
+                        keep_hourly_for: row.get(1)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(|e| DBError::Error(format!("Cannot read retention policy: {:?}", e)))
+    }
+
+    fn write_retention_policy(
         tx: &rusqlite::Transaction,
-        brach_name: &str,
+        branch_name: &str,
+        policy: &RetentionPolicy,
     ) -> Result<(), DBError> {
-        write_config_inner(tx, &current_branch_name_key(), brach_name)
+        tx.execute(
+            "INSERT OR REPLACE INTO retention_policies (branch, keep_all_for, keep_hourly_for) VALUES (?1, ?2, ?3)",
+            rusqlite::params![branch_name, policy.keep_all_for, policy.keep_hourly_for],
+        )
+        .map_err(|e| DBError::Error(format!("Cannot write retention policy: {:?}", e)))
+        .map(|_| ())
     }
 
-    fn read_all_branches(&self) -> Result<Vec<String>, DBError> {
+    fn read_all_tags(&self) -> Result<Vec<Tag>, DBError> {
         let mut stmt = self
             .sqlite_db
-            .prepare("SELECT name FROM branches")
-            .map_err(|e| DBError::Error(format!("Cannot query branches: {:?}", e)))?;
+            .prepare("SELECT name, hash FROM tags ORDER BY name")
+            .map_err(|e| DBError::Error(format!("Cannot query tags: {:?}", e)))?;
         let mut rows = stmt
             .query([])
-            .map_err(|e| DBError::Error(format!("Cannot query branches: {:?}", e)))?;
+            .map_err(|e| DBError::Error(format!("Cannot query tags: {:?}", e)))?;
 
-        let mut result: Vec<String> = vec![];
+        let mut result: Vec<Tag> = vec![];
 
         while let Ok(Some(data)) = rows.next() {
-            let name = data.get(0).map_err(|e| {
-                DBError::Fundamental(format!("Branch name not returned in result set: {:?}", e))
-            })?;
-
-            result.push(name);
+            result.push(Tag {
+                name: data.get(0).expect("No name found in row"),
+                hash: data.get(1).expect("No hash found in row"),
+            });
         }
 
         Ok(result)
     }
 
+    fn read_tag(&self, name: &str) -> Result<Option<String>, DBError> {
+        let mut stmt = self
+            .sqlite_db
+            .prepare("SELECT hash FROM tags WHERE name = ?1")
+            .map_err(|e| DBError::Error(format!("Cannot query tag: {:?}", e)))?;
+
+        let mut rows = stmt
+            .query([name])
+            .map_err(|e| DBError::Error(format!("Cannot query tag: {:?}", e)))?;
+
+        match rows.next() {
+            Ok(Some(data)) => data
+                .get(0)
+                .map(Some)
+                .map_err(|e| DBError::Fundamental(format!("Cannot read tag: {:?}", e))),
+            Ok(None) => Ok(None),
+            Err(e) => Err(DBError::Error(format!("Cannot query tag: {:?}", e))),
+        }
+    }
+
+    fn write_tag(tx: &rusqlite::Transaction, tag: &Tag) -> Result<(), DBError> {
+        tx.execute(
+            "INSERT INTO tags (name, hash) VALUES (?1, ?2)",
+            [&tag.name, &tag.hash],
+        )
+        .map_err(|e| DBError::Error(format!("Cannot write tag {}: {:?}", tag.name, e)))
+        .map(|_| ())
+    }
+
     fn read_branch_tip(&self, branch_name: &str) -> Result<Option<String>, DBError> {
         let mut stmt = self
             .sqlite_db
@@ -440,7 +1698,24 @@ impl DB for Persistence {
         tx: &rusqlite::Transaction,
         brach_name: &str,
         tip: &str,
+        operation: &str,
     ) -> Result<(), DBError> {
+        let old_tip: Option<String> = tx
+            .query_row(
+                "SELECT tip FROM branches WHERE name = ?1",
+                [brach_name],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| DBError::Error(format!("Cannot query branch: {:?}", e)))?;
+        write_ref_log_entry(
+            tx,
+            &RefLogTarget::BranchTip(brach_name.to_owned()),
+            old_tip.as_deref(),
+            Some(tip),
+            operation,
+        )?;
+
         tx.execute(
             "INSERT OR REPLACE INTO branches (name, tip) VALUES (?1, ?2)",
             [&brach_name, &tip],
@@ -506,11 +1781,114 @@ impl DB for Persistence {
             })
     }
 
-    fn write_current_commit_pointer(tx: &rusqlite::Transaction, hash: &str) -> Result<(), DBError> {
+    fn write_current_commit_pointer(
+        tx: &rusqlite::Transaction,
+        hash: &str,
+        operation: &str,
+    ) -> Result<(), DBError> {
+        let old_value = read_config_inner(tx, &current_latest_commit_key())?;
+        write_ref_log_entry(
+            tx,
+            &RefLogTarget::CurrentCommit,
+            old_value.as_deref(),
+            Some(hash),
+            operation,
+        )?;
         write_config_inner(tx, &current_latest_commit_key(), hash)
             .map_err(|e| DBError::Error(format!("Cannot write latest commit hash: {:?}", e)))
     }
 
+    fn read_ref_log(&self, limit: Option<usize>) -> Result<Vec<RefLogEntry>, DBError> {
+        let mut stmt = self
+            .sqlite_db
+            .prepare(&format!(
+                "SELECT {} FROM ref_log ORDER BY id DESC LIMIT ?1",
+                REF_LOG_COLUMNS
+            ))
+            .map_err(|e| DBError::Fundamental(format!("Cannot prepare query: {:?}", e)))?;
+
+        // a negative limit means no limit in SQLite
+        let limit = limit.map_or(-1, |limit| limit as i64);
+        let mut rows = stmt
+            .query([limit])
+            .map_err(|e| DBError::Error(format!("Cannot read reflog: {:?}", e)))?;
+
+        let mut result: Vec<RefLogEntry> = vec![];
+
+        while let Ok(Some(data)) = rows.next() {
+            result.push(ref_log_entry_from_row(data))
+        }
+
+        Ok(result)
+    }
+
+    fn read_ref_log_entry(&self, id: i64) -> Result<Option<RefLogEntry>, DBError> {
+        self.sqlite_db
+            .query_row(
+                &format!("SELECT {} FROM ref_log WHERE id = ?1", REF_LOG_COLUMNS),
+                [id],
+                |row| Ok(ref_log_entry_from_row(row)),
+            )
+            .optional()
+            .map_err(|e| DBError::Error(format!("Cannot read reflog: {:?}", e)))
+    }
+
+    fn read_intents(&self) -> Result<Vec<Intent>, DBError> {
+        let mut stmt = self
+            .sqlite_db
+            .prepare("SELECT id, operation, commit_hashes, block_hashes FROM intents")
+            .map_err(|e| DBError::Fundamental(format!("Cannot prepare query: {:?}", e)))?;
+
+        let mut rows = stmt
+            .query([])
+            .map_err(|e| DBError::Error(format!("Cannot read intents: {:?}", e)))?;
+
+        let mut result: Vec<Intent> = vec![];
+
+        while let Ok(Some(data)) = rows.next() {
+            result.push(Intent {
+                id: data.get(0).expect("No id found in row"),
+                operation: data.get(1).expect("No operation found in row"),
+                commit_hashes: parse_hashes(
+                    &data
+                        .get::<usize, String>(2)
+                        .expect("No commit_hashes found in row"),
+                ),
+                block_hashes: parse_hashes(
+                    &data
+                        .get::<usize, String>(3)
+                        .expect("No block_hashes found in row"),
+                ),
+            })
+        }
+
+        Ok(result)
+    }
+
+    fn write_intent(tx: &rusqlite::Transaction, intent: &Intent) -> Result<(), DBError> {
+        tx.execute(
+            "INSERT INTO intents (id, operation, commit_hashes, block_hashes) VALUES (?1, ?2, ?3, ?4)",
+            [
+                &intent.id,
+                &intent.operation,
+                &print_hashes(&intent.commit_hashes),
+                &print_hashes(&intent.block_hashes),
+            ],
+        )
+        .map_err(|e| DBError::Error(format!("Cannot write intent: {:?}", e)))
+        .map(|_| ())
+    }
+
+    fn delete_intent(tx: &rusqlite::Transaction, id: &str) -> Result<(), DBError> {
+        tx.execute("DELETE FROM intents WHERE id = ?1", [id])
+            .map_err(|e| DBError::Error(format!("Cannot delete intent: {:?}", e)))
+            .map(|_| ())
+    }
+
+    fn rolled_back_operations(&self) -> &[String] {
+        &self.rolled_back
+    }
+
     fn execute_in_transaction<F>(&mut self, f: F) -> Result<(), DBError>
     where
         F: FnOnce(&rusqlite::Transaction) -> Result<(), DBError>,
@@ -546,31 +1924,58 @@ impl DB for Persistence {
     fn delete_branch_with_commits(
         tx: &rusqlite::Transaction,
         branch_name: &str,
+        commit_hashes: &[String],
     ) -> Result<(), DBError> {
-        let mut delete_commits_stmt = tx
+        let mut delete_branch_stmt = tx
             .prepare(
                 "
-            DELETE FROM commits WHERE branch = ?1;
+            DELETE FROM branches WHERE name = ?1;
             ",
             )
             .map_err(|e| DBError::Fundamental(format!("Cannot prepare query: {:?}", e)))?;
 
-        let mut delete_branch_stmt = tx
+        let mut delete_branch_info_stmt = tx
             .prepare(
                 "
-            DELETE FROM branches WHERE name = ?1;
+            DELETE FROM branch_info WHERE name = ?1;
             ",
             )
             .map_err(|e| DBError::Fundamental(format!("Cannot prepare query: {:?}", e)))?;
 
-        delete_commits_stmt
+        let tip: Option<String> = tx
+            .query_row(
+                "SELECT tip FROM branches WHERE name = ?1",
+                [branch_name],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| DBError::Error(format!("Cannot query branch: {:?}", e)))?;
+        write_ref_log_entry(
+            tx,
+            &RefLogTarget::BranchTip(branch_name.to_owned()),
+            tip.as_deref(),
+            None,
+            "delete_branch",
+        )?;
+
+        for hash in commit_hashes.iter() {
+            Persistence::delete_commit(tx, hash)?;
+        }
+
+        delete_branch_stmt
             .execute([branch_name])
             .map_err(|e| DBError::Error(format!("Cannot execute statement: {:?}", e)))?;
 
-        delete_branch_stmt
+        delete_branch_info_stmt
             .execute([branch_name])
             .map_err(|e| DBError::Error(format!("Cannot execute statement: {:?}", e)))?;
 
+        tx.execute(
+            "DELETE FROM retention_policies WHERE branch = ?1",
+            [branch_name],
+        )
+        .map_err(|e| DBError::Error(format!("Cannot execute statement: {:?}", e)))?;
+
         Ok(())
     }
 
@@ -581,6 +1986,24 @@ impl DB for Persistence {
     fn write_name(tx: &rusqlite::Transaction, name: &str) -> Result<(), DBError> {
         write_config_inner(tx, &user_name_key(), name)
     }
+
+    fn read_hash_algorithm(&self) -> Result<HashAlgorithm, DBError> {
+        match read_config_inner(&self.sqlite_db, &hash_algorithm_key())? {
+            // timelines created before the hash algorithm was recorded all use MD5
+            None => Ok(HashAlgorithm::Md5),
+            Some(name) => HashAlgorithm::from_name(&name).ok_or(DBError::Fundamental(format!(
+                "Unknown hash algorithm: {:?}",
+                name
+            ))),
+        }
+    }
+
+    fn write_hash_algorithm(
+        tx: &rusqlite::Transaction,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<(), DBError> {
+        write_config_inner(tx, &hash_algorithm_key(), hash_algorithm.name())
+    }
 }
 
 #[cfg(test)]
@@ -620,7 +2043,9 @@ mod tests {
                 tx,
                 Commit {
                     hash: "1".to_owned(),
+                    snapshot: "1".to_owned(),
                     prev_commit_hash: String::from(INITIAL_COMMIT_HASH),
+                    merge_parents: vec![],
                     project_id: "a".to_owned(),
                     branch: String::from(MAIN_BRANCH_NAME),
                     message: "hi".to_owned(),
@@ -635,7 +2060,9 @@ mod tests {
                 tx,
                 Commit {
                     hash: "2".to_owned(),
+                    snapshot: "2".to_owned(),
                     prev_commit_hash: "1".to_owned(),
+                    merge_parents: vec![],
                     project_id: "a".to_owned(),
                     branch: String::from(MAIN_BRANCH_NAME),
                     message: "hi".to_owned(),
@@ -650,7 +2077,9 @@ mod tests {
                 tx,
                 Commit {
                     hash: "3".to_owned(),
+                    snapshot: "3".to_owned(),
                     prev_commit_hash: "2".to_owned(),
+                    merge_parents: vec![],
                     project_id: "a".to_owned(),
                     branch: String::from(MAIN_BRANCH_NAME),
                     message: "hi".to_owned(),
@@ -665,7 +2094,9 @@ mod tests {
                 tx,
                 Commit {
                     hash: "4".to_owned(),
+                    snapshot: "4".to_owned(),
                     prev_commit_hash: "3".to_owned(),
+                    merge_parents: vec![],
                     project_id: "a".to_owned(),
                     branch: String::from(MAIN_BRANCH_NAME),
                     message: "hi".to_owned(),
@@ -680,7 +2111,9 @@ mod tests {
                 tx,
                 Commit {
                     hash: "a".to_owned(),
+                    snapshot: "a".to_owned(),
                     prev_commit_hash: "1".to_owned(),
+                    merge_parents: vec![],
                     project_id: "a".to_owned(),
                     branch: String::from(MAIN_BRANCH_NAME),
                     message: "hi".to_owned(),
@@ -695,7 +2128,9 @@ mod tests {
                 tx,
                 Commit {
                     hash: "b".to_owned(),
+                    snapshot: "b".to_owned(),
                     prev_commit_hash: "a".to_owned(),
+                    merge_parents: vec![],
                     project_id: "a".to_owned(),
                     branch: String::from(MAIN_BRANCH_NAME),
                     message: "hi".to_owned(),
@@ -710,7 +2145,9 @@ mod tests {
                 tx,
                 Commit {
                     hash: "x".to_owned(),
+                    snapshot: "x".to_owned(),
                     prev_commit_hash: "3".to_owned(),
+                    merge_parents: vec![],
                     project_id: "a".to_owned(),
                     branch: String::from(MAIN_BRANCH_NAME),
                     message: "hi".to_owned(),
@@ -783,6 +2220,299 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_commits_with_several_parents() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        let mut db = Persistence::open(tmp_path).expect("Cannot open test DB");
+
+        /*
+          1 - 2 - m - 3
+           \     /
+              a
+        */
+        let commit = |hash: &str, prev: &str, merge_parents: Vec<String>, date: u64| Commit {
+            hash: hash.to_owned(),
+            snapshot: hash.to_owned(),
+            prev_commit_hash: prev.to_owned(),
+            merge_parents,
+            project_id: "a".to_owned(),
+            branch: String::from(MAIN_BRANCH_NAME),
+            message: "hi".to_owned(),
+            author: "test".to_owned(),
+            date,
+            header: vec![],
+            blocks: "".to_owned(),
+        };
+        for hash in ["1", "2", "a", "m", "3"] {
+            db.write_blocks_str(hash, "aaa").unwrap();
+        }
+        db.execute_in_transaction(|tx| {
+            Persistence::write_commit(tx, commit("1", INITIAL_COMMIT_HASH, vec![], 1))?;
+            Persistence::write_commit(tx, commit("2", "1", vec![], 2))?;
+            Persistence::write_commit(tx, commit("a", "1", vec![], 3))?;
+            Persistence::write_commit(tx, commit("m", "2", vec!["a".to_owned()], 4))?;
+            Persistence::write_commit(tx, commit("3", "m", vec![], 5))
+        })
+        .expect("Cannot execute transaction");
+
+        let ancestors: Vec<String> = db
+            .read_ancestors_of_commit("3")
+            .expect("Cannot read commits")
+            .into_iter()
+            .map(|c| c.hash)
+            .collect();
+        assert_eq!(ancestors, vec!["3", "m", "a", "2", "1"]);
+
+        let descendants: Vec<String> = db
+            .read_descendants_of_commit("a")
+            .expect("Cannot read commits")
+            .into_iter()
+            .map(|c| c.hash)
+            .collect();
+        assert_eq!(descendants, vec!["a", "m", "3"]);
+
+        // `1` is reached through both parents of `m`, but listed once
+        let descendants: Vec<String> = db
+            .read_descendants_of_commit("1")
+            .expect("Cannot read commits")
+            .into_iter()
+            .map(|c| c.hash)
+            .collect();
+        assert_eq!(descendants, vec!["1", "2", "a", "m", "3"]);
+
+        db.execute_in_transaction(|tx| Persistence::rename_commit_hash(tx, "a", "b"))
+            .expect("Cannot rename commit");
+
+        let merge_commit = db.read_commit("m").unwrap().unwrap();
+        assert_eq!(merge_commit.prev_commit_hash, "2");
+        assert_eq!(merge_commit.merge_parents, vec!["b"]);
+    }
+
+    #[test]
+    fn test_write_blocks_skips_stored_blocks() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+
+        db.write_blocks(&[BlockRecord {
+            hash: "aaa".to_owned(),
+            data: vec![1, 1, 1],
+        }])
+        .expect("Cannot write blocks");
+
+        db.write_blocks(&[
+            BlockRecord {
+                hash: "aaa".to_owned(),
+                data: vec![2, 2, 2],
+            },
+            BlockRecord {
+                hash: "bbb".to_owned(),
+                data: vec![3, 3, 3],
+            },
+        ])
+        .expect("Cannot write blocks");
+
+        let blocks = db
+            .read_blocks(vec!["aaa".to_owned(), "bbb".to_owned()])
+            .expect("Cannot read blocks");
+
+        // the block that was already stored is left untouched
+        assert_eq!(
+            blocks,
+            vec![
+                BlockRecord {
+                    hash: "aaa".to_owned(),
+                    data: vec![1, 1, 1],
+                },
+                BlockRecord {
+                    hash: "bbb".to_owned(),
+                    data: vec![3, 3, 3],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_blocks_reports_all_missing_blocks() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+
+        db.write_blocks(&[BlockRecord {
+            hash: "aaa".to_owned(),
+            data: vec![1, 1, 1],
+        }])
+        .expect("Cannot write blocks");
+
+        let result = db.read_blocks(vec!["ccc".to_owned(), "aaa".to_owned(), "ddd".to_owned()]);
+
+        match result {
+            Err(DBError::Consistency(msg)) => {
+                assert_eq!(msg, "2 blocks are missing: ccc, ddd")
+            }
+            _ => panic!("Missing blocks should be reported"),
+        }
+    }
+
+    #[test]
+    fn test_second_writer_is_busy() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        let mut db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        db.execute_in_transaction(|tx| {
+            Persistence::write_current_branch_name(tx, "main", "switch")
+        })
+        .expect("Cannot write current branch name");
+
+        let error = Persistence::open(tmp_path)
+            .err()
+            .expect("Opened a locked DB");
+        assert!(
+            matches!(error, DBError::Busy(msg) if msg.contains(&std::process::id().to_string()))
+        );
+
+        // readers don't need the lock
+        let reader = Persistence::open_read_only(tmp_path).expect("Cannot open read-only DB");
+        assert_eq!(reader.read_current_branch_name().unwrap(), "main");
+
+        drop(db);
+        Persistence::open(tmp_path).expect("Cannot open DB after the lock is released");
+    }
+
+    #[test]
+    fn test_reader_of_older_timeline_asks_for_upgrade() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        // as if the timeline was last opened for writing by an older version
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        db.sqlite_db
+            .pragma_update(None, "user_version", 0)
+            .expect("Cannot write schema version");
+        drop(db);
+
+        let error = Persistence::open_read_only(tmp_path)
+            .err()
+            .expect("Opened an older timeline for reading");
+        assert!(matches!(error, DBError::Error(msg) if msg.contains("needs upgrading")));
+
+        drop(Persistence::open(tmp_path).expect("Cannot open test DB"));
+        Persistence::open_read_only(tmp_path).expect("Cannot open upgraded timeline");
+    }
+
+    #[test]
+    fn test_roll_back_unfinished_intent() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        {
+            let mut db = Persistence::open(tmp_path).expect("Cannot open test DB");
+
+            db.write_blocks(&[BlockRecord {
+                hash: "aaa".to_owned(),
+                data: vec![1, 1, 1],
+            }])
+            .expect("Cannot write blocks");
+
+            let missing = db
+                .read_missing_block_hashes(&["aaa".to_owned(), "bbb".to_owned()])
+                .expect("Cannot read missing blocks");
+            assert_eq!(missing, vec!["bbb"]);
+
+            let intent = Intent::new("commit", vec!["1".to_owned()], missing);
+
+            // the process "crashes" after the blocks are written to RocksDB,
+            // but before the commit is written to SQLite
+            db.execute_in_transaction(|tx| Persistence::write_intent(tx, &intent))
+                .expect("Cannot write intent");
+            db.write_blocks(&[BlockRecord {
+                hash: "bbb".to_owned(),
+                data: vec![2, 2, 2],
+            }])
+            .expect("Cannot write blocks");
+            db.write_blocks_str("1", "aaa,bbb").unwrap();
+            db.sync_blocks().unwrap();
+
+            // readers leave the operation of the writer alone
+            let reader = Persistence::open_read_only(tmp_path).expect("Cannot open test DB");
+            assert_eq!(reader.read_intents().unwrap().len(), 1);
+        }
+
+        let db = Persistence::open(tmp_path).expect("Cannot open test DB");
+
+        assert_eq!(db.rolled_back_operations(), ["commit"]);
+        assert_eq!(db.read_intents().unwrap(), vec![]);
+        assert!(!db.commit_exists("1").unwrap());
+        assert!(matches!(
+            get_blocks_by_hash(&db.rocks_db, "1"),
+            Err(DBError::Consistency(_))
+        ));
+
+        // blocks stored before the operation are kept
+        let missing = db
+            .read_missing_block_hashes(&["aaa".to_owned(), "bbb".to_owned()])
+            .expect("Cannot read missing blocks");
+        assert_eq!(missing, vec!["bbb"]);
+    }
+
+    #[test]
+    fn test_search_index_of_older_timeline_survives_vacuum() {
+        let tmp_dir = TempDir::new().expect("Cannot create temp dir");
+        let tmp_path = tmp_dir.path().to_str().expect("Cannot get temp dir path");
+
+        // commits as they were stored before they had ids
+        let old_db = rusqlite::Connection::open(tmp_dir.path().join("commits.sqlite"))
+            .expect("Cannot open SQLite");
+        old_db
+            .execute_batch(
+                "CREATE TABLE commits (
+                    hash TEXT PRIMARY KEY,
+                    prev_commit_hash TEXT,
+                    project_id TEXT,
+                    branch TEXT,
+                    message TEXT,
+                    author TEXT,
+                    date INTEGER,
+                    header BLOB
+                );
+                INSERT INTO commits VALUES ('1', 'initial', 'a', 'main', 'Model the sword', 'test', 1, x'');
+                INSERT INTO commits VALUES ('2', '1', 'a', 'main', 'Rig the shield', 'test', 2, x'');
+                INSERT INTO commits VALUES ('3', '2', 'a', 'main', 'Paint the sword', 'test', 3, x'');",
+            )
+            .expect("Cannot create old commits");
+        drop(old_db);
+
+        let mut db = Persistence::open(tmp_path).expect("Cannot open test DB");
+        let search = |db: &Persistence, text: &str| -> Vec<String> {
+            db.search_commits(text, 10)
+                .expect("Cannot search")
+                .into_iter()
+                .map(|commit| commit.hash)
+                .collect()
+        };
+        assert_eq!(search(&db, "shield"), vec!["2"]);
+
+        // leaves a gap for `VACUUM` to close
+        db.execute_in_transaction(|tx| Persistence::delete_commit(tx, "1"))
+            .expect("Cannot delete commit");
+        db.sqlite_db.execute_batch("VACUUM").expect("Cannot vacuum");
+
+        assert_eq!(search(&db, "shield"), vec!["2"]);
+        assert_eq!(search(&db, "sword"), vec!["3"]);
+        let snapshot: String = db
+            .sqlite_db
+            .query_row("SELECT snapshot FROM commits WHERE hash = '3'", [], |row| {
+                row.get(0)
+            })
+            .expect("Cannot read snapshot");
+        assert_eq!(snapshot, "3");
+    }
+
     #[test]
     fn test_delete_branch_with_commits() {
         let tmp_dir = TempDir::new().expect("Cannot create temp dir");
@@ -865,8 +2595,10 @@ mod tests {
 
         let mut db = Persistence::open(tmp_path).expect("Cannot open test DB");
 
-        db.execute_in_transaction(|tx| Persistence::delete_branch_with_commits(tx, "alt2"))
-            .expect("cannot delete");
+        db.execute_in_transaction(|tx| {
+            Persistence::delete_branch_with_commits(tx, "alt2", &["a".to_owned(), "b".to_owned()])
+        })
+        .expect("cannot delete");
 
         let branches = db.read_all_branches().expect("Cannot read branches");
         assert_eq!(branches, vec!["alt1", "main"]);
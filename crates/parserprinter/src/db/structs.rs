@@ -1,4 +1,7 @@
+use std::{collections::BTreeMap, iter};
+
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use crate::printer_parser::{
     combinator::{repeat1, separated_list},
@@ -12,10 +15,52 @@ pub struct BlockRecord {
     pub data: Vec<u8>,
 }
 
+/// A free-form property of a commit, like the Blender version it was made with
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub struct CommitMetadata {
+    pub hash: String,
+    pub key: String,
+    pub value: String,
+    /// Set when the commit was made and covered by its hash. Entries added
+    /// later can change without changing the hash.
+    pub hashed: bool,
+}
+
+/// How a branch came to be
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub struct BranchInfo {
+    pub name: String,
+    /// The commit the branch was created from
+    pub base: String,
+    pub created: u64,
+    pub description: Option<String>,
+}
+
+/// A branch was renamed. Renames are sent along with the commits, so that
+/// timelines that still use the old name end up with the new one.
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub struct BranchRename {
+    pub old_name: String,
+    pub new_name: String,
+    pub date: u64,
+}
+
+/// A name given to a commit. Unlike branches, tags never move.
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub struct Tag {
+    pub name: String,
+    pub hash: String,
+}
+
 #[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
 pub struct Commit {
+    /// Identifies the commit, see `Commit::compute_hash`
     pub hash: String,
+    /// The hash of `blocks`, the same for every commit of the same contents
+    pub snapshot: String,
     pub prev_commit_hash: String,
+    /// Parents other than `prev_commit_hash`, like the tip of a merged branch
+    pub merge_parents: Vec<String>,
     pub project_id: String,
     pub branch: String,
     pub message: String,
@@ -25,6 +70,193 @@ pub struct Commit {
     pub blocks: String,
 }
 
+impl Commit {
+    /// The hash of a commit covers its snapshot and header, where it sits in
+    /// the history, and the author, message and metadata it was created with.
+    /// The branch is left out, since branches can be renamed. A commit that
+    /// would get the hash of an existing one is told apart by a nonce in its
+    /// metadata, see `hash_unique_commit`.
+    pub fn compute_hash(
+        &self,
+        metadata: &BTreeMap<String, String>,
+        hash_algorithm: HashAlgorithm,
+    ) -> String {
+        let mut content = format!("snapshot {}\n", self.snapshot);
+        for parent in iter::once(&self.prev_commit_hash).chain(self.merge_parents.iter()) {
+            content.push_str(&format!("parent {}\n", parent));
+        }
+        let header: String = self.header.iter().map(|b| format!("{:02x}", b)).collect();
+        content.push_str(&format!("header {}\n", header));
+        content.push_str(&format!("author {}\n", escape_hashed(&self.author)));
+        for (key, value) in metadata.iter() {
+            content.push_str(&format!(
+                "meta {}={}\n",
+                escape_hashed(key),
+                escape_hashed(value)
+            ));
+        }
+        content.push_str(&format!("\n{}", self.message));
+
+        hash_algorithm.hash(content.as_bytes())
+    }
+}
+
+/// Escapes the line breaks and `=` signs of a field that is hashed as part of
+/// a line, so that no two sets of fields are hashed the same
+fn escape_hashed(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '=' => escaped.push_str("\\="),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The fields of a commit that describe it, without its contents
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub struct CommitInfo {
+    pub hash: String,
+    pub prev_commit_hash: String,
+    pub merge_parents: Vec<String>,
+    pub branch: String,
+    pub message: String,
+    pub author: String,
+    pub date: u64,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Debug, Default)]
+pub enum HistoryOrder {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+}
+
+/// Filters for the commit history. Every filter that is set has to match.
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct HistoryQuery {
+    pub author: Option<String>,
+    pub branch: Option<String>,
+    /// Matched case-insensitively anywhere in the message
+    pub message_contains: Option<String>,
+    /// Unix timestamp, inclusive
+    pub since: Option<u64>,
+    /// Unix timestamp, inclusive
+    pub until: Option<u64>,
+    pub order: HistoryOrder,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+/// Which checkpoints of a branch are kept when it is pruned, by their age in
+/// seconds. Every checkpoint newer than `keep_all_for` is kept, then the
+/// latest one of every hour up to `keep_hourly_for`, and the latest one of
+/// every day after that.
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Debug)]
+pub struct RetentionPolicy {
+    pub keep_all_for: u64,
+    pub keep_hourly_for: u64,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_all_for: 24 * 60 * 60,
+            keep_hourly_for: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// What a reflog entry records the movement of
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub enum RefLogTarget {
+    /// The commit the working file was last committed as or restored from
+    CurrentCommit,
+    CurrentBranch,
+    BranchTip(String),
+}
+
+/// A change of the current commit, the current branch or a branch tip. `None`
+/// stands for a value that was not set, like the tip of a deleted branch.
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
+pub struct RefLogEntry {
+    /// Increases with every change, newer entries have higher ids
+    pub id: i64,
+    pub target: RefLogTarget,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    /// The command that made the change, like `commit` or `restore`
+    pub operation: String,
+    pub date: u64,
+}
+
+/// A write that spans both RocksDB and SQLite. It is recorded before the first
+/// write and removed in the same SQLite transaction as the last one, so an
+/// intent that is still around means that the operation did not finish. These
+/// are rolled back when the timeline is opened again.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Intent {
+    pub id: String,
+    pub operation: String,
+    /// Commits created by the operation
+    pub commit_hashes: Vec<String>,
+    /// Blocks that were not stored before the operation
+    pub block_hashes: Vec<String>,
+}
+
+impl Intent {
+    pub fn new(operation: &str, commit_hashes: Vec<String>, block_hashes: Vec<String>) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            operation: operation.to_owned(),
+            commit_hashes,
+            block_hashes,
+        }
+    }
+}
+
+/// The hash function used to derive block and commit hashes in a timeline.
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Debug, Default)]
+pub enum HashAlgorithm {
+    /// Only kept around for timelines created before the hash became configurable
+    Md5,
+    #[default]
+    Blake3,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "md5" => Some(HashAlgorithm::Md5),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    /// Hashes `data`, returning the digest as a lowercase hex string
+    pub fn hash(&self, data: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Md5 => format!("{:x}", md5::compute(data)),
+            HashAlgorithm::Blake3 => blake3::hash(data).to_hex().to_string(),
+            HashAlgorithm::Sha256 => format!("{:x}", Sha256::digest(data)),
+        }
+    }
+}
+
 fn hexa() -> impl PrinterParserOps<(), char> {
     consume_char.filter(|c| c.is_ascii_hexdigit())
 }
@@ -60,4 +292,59 @@ mod test {
         let printed = hash_list().print(&vals, &mut ()).unwrap();
         assert_eq!(printed, "567ab,4893edda,ca849280bcd")
     }
+
+    #[test]
+    fn test_hash_algorithms() {
+        assert_eq!(
+            HashAlgorithm::Md5.hash(b"abc"),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+        assert_eq!(
+            HashAlgorithm::Blake3.hash(b"abc"),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(
+            HashAlgorithm::Sha256.hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        for algorithm in [
+            HashAlgorithm::Md5,
+            HashAlgorithm::Blake3,
+            HashAlgorithm::Sha256,
+        ] {
+            assert_eq!(HashAlgorithm::from_name(algorithm.name()), Some(algorithm));
+        }
+    }
+
+    #[test]
+    fn test_commit_hash_metadata_is_unambiguous() {
+        let commit = Commit {
+            hash: String::new(),
+            snapshot: "abc".to_owned(),
+            prev_commit_hash: "def".to_owned(),
+            merge_parents: vec![],
+            project_id: "project".to_owned(),
+            branch: "main".to_owned(),
+            message: "Message".to_owned(),
+            author: "Anon".to_owned(),
+            date: 314,
+            header: vec![1, 2, 3],
+            blocks: String::new(),
+        };
+
+        let hash_with = |entries: &[(&str, &str)]| {
+            let metadata: BTreeMap<String, String> = entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            commit.compute_hash(&metadata, HashAlgorithm::Blake3)
+        };
+
+        assert_ne!(hash_with(&[("a=b", "c")]), hash_with(&[("a", "b=c")]));
+        assert_ne!(
+            hash_with(&[("a", "b\nmeta c=d")]),
+            hash_with(&[("a", "b"), ("c", "d")])
+        );
+    }
 }
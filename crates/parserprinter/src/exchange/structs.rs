@@ -1,18 +1,64 @@
-use crate::db::structs::{BlockRecord, Commit};
+use crate::db::structs::{BlockRecord, BranchRename, Commit, CommitMetadata, HashAlgorithm, Tag};
 use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Debug)]
+#[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Default, Debug)]
 pub struct Exchange {
+    /// The hash algorithm of the timeline the exchange was made from, the
+    /// hashes of its commits and blocks only make sense with it
+    pub hash_algorithm: HashAlgorithm,
     pub commits: Vec<Commit>,
     pub blocks: Vec<BlockRecord>,
+    pub tags: Vec<Tag>,
+    pub metadata: Vec<CommitMetadata>,
+    /// Every rename of the timeline, oldest first
+    pub branch_renames: Vec<BranchRename>,
+}
+
+/// Leads every encoded exchange and sync, followed by the format version, so
+/// that data from other versions is rejected before the rest is decoded.
+/// Exchanges from before the format was versioned do not start with it.
+const EXCHANGE_MAGIC: &[u8; 4] = b"BTLX";
+
+/// Changes whenever the fields of `Exchange`, `Sync` or the structs in them change
+pub const EXCHANGE_FORMAT_VERSION: u32 = 1;
+
+fn encode_versioned<T: Serialize>(value: &T) -> Result<Vec<u8>, bincode::Error> {
+    let mut data = EXCHANGE_MAGIC.to_vec();
+    data.extend(EXCHANGE_FORMAT_VERSION.to_le_bytes());
+    data.extend(bincode::serialize(value)?);
+    Ok(data)
+}
+
+/// Checks the magic and the format version, and returns the data after them
+fn check_format_version(data: &[u8]) -> Result<&[u8], String> {
+    if data.get(..EXCHANGE_MAGIC.len()) != Some(&EXCHANGE_MAGIC[..]) {
+        return Err("Unsupported exchange format, it was made by an older version".to_owned());
+    }
+
+    let rest = &data[EXCHANGE_MAGIC.len()..];
+    let version = rest
+        .get(..4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_le_bytes)
+        .ok_or_else(|| "Unsupported exchange format, the version is missing".to_owned())?;
+
+    if version != EXCHANGE_FORMAT_VERSION {
+        return Err(format!(
+            "Unsupported exchange format version {}, only version {} can be read",
+            version, EXCHANGE_FORMAT_VERSION
+        ));
+    }
+
+    Ok(&rest[4..])
 }
 
 pub fn encode_exchange(exchange: &Exchange) -> Result<Vec<u8>, String> {
-    bincode::serialize(exchange).map_err(|e| format!("Cannot encode exchange: {:?}", e))
+    encode_versioned(exchange).map_err(|e| format!("Cannot encode exchange: {:?}", e))
 }
 
 pub fn decode_exchange(data: &[u8]) -> Result<Exchange, String> {
-    bincode::deserialize(data).map_err(|e| format!("Cannot decode exchange: {:?}", e))
+    bincode::deserialize(check_format_version(data)?)
+        .map_err(|e| format!("Cannot decode exchange: {:?}", e))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -25,30 +71,34 @@ pub struct Sync {
 }
 
 pub fn decode_sync(data: &[u8]) -> Result<Sync, String> {
-    bincode::deserialize(data).map_err(|e| format!("Cannot decode sync: {:?}", e))
+    bincode::deserialize(check_format_version(data)?)
+        .map_err(|e| format!("Cannot decode sync: {:?}", e))
 }
 
 pub fn encode_sync(sync: &Sync) -> Result<Vec<u8>, String> {
-    bincode::serialize(sync).map_err(|e| format!("Cannot encode sync: {:?}", e))
+    encode_versioned(sync).map_err(|e| format!("Cannot encode sync: {:?}", e))
 }
 
 #[cfg(test)]
 mod test {
     use crate::{
         api::init_command::MAIN_BRANCH_NAME,
-        db::structs::{BlockRecord, Commit},
+        db::structs::{BlockRecord, Commit, CommitMetadata, HashAlgorithm, Tag},
         exchange::structs::decode_exchange,
     };
 
-    use super::{encode_exchange, Exchange};
+    use super::{encode_exchange, Exchange, EXCHANGE_FORMAT_VERSION};
 
     #[test]
     fn test_round_trip_serialize_deserialize() {
         let original_exchange = Exchange {
+            hash_algorithm: HashAlgorithm::Blake3,
             commits: vec![
                 Commit {
                     hash: String::from("abc123"),
+                    snapshot: String::from("abc123"),
                     prev_commit_hash: String::from("def456"),
+                    merge_parents: vec![],
                     project_id: String::from("proj789"),
                     branch: String::from(MAIN_BRANCH_NAME),
                     message: String::from("Initial commit"),
@@ -59,7 +109,9 @@ mod test {
                 },
                 Commit {
                     hash: String::from("qwe234"),
+                    snapshot: String::from("qwe234"),
                     prev_commit_hash: String::from("abc123"),
+                    merge_parents: vec![String::from("xyz345")],
                     project_id: String::from("proj78"),
                     branch: String::from(MAIN_BRANCH_NAME),
                     message: String::from("Next commit"),
@@ -79,12 +131,38 @@ mod test {
                     data: vec![5, 6, 7, 8],
                 },
             ],
+            tags: vec![Tag {
+                name: String::from("v1"),
+                hash: String::from("qwe234"),
+            }],
+            metadata: vec![CommitMetadata {
+                hash: String::from("abc123"),
+                key: String::from("blender_version"),
+                value: String::from("3.6"),
+                hashed: true,
+            }],
+            branch_renames: vec![],
         };
 
         let serialized = encode_exchange(&original_exchange).unwrap();
-        assert_eq!(serialized.len(), 342);
+        assert_eq!(serialized.len(), 509);
 
         let deserialized = decode_exchange(&serialized).unwrap();
         assert_eq!(deserialized, original_exchange);
     }
+
+    #[test]
+    fn test_unsupported_format() {
+        // written before the format was versioned, starts with the number of commits
+        let unversioned = bincode::serialize(&Exchange::default()).unwrap();
+        let error = decode_exchange(&unversioned).unwrap_err();
+        assert!(error.starts_with("Unsupported exchange format"));
+
+        let mut newer = encode_exchange(&Exchange::default()).unwrap();
+        newer[4..8].copy_from_slice(&(EXCHANGE_FORMAT_VERSION + 1).to_le_bytes());
+        let error = decode_exchange(&newer).unwrap_err();
+        assert!(error.starts_with("Unsupported exchange format version"));
+
+        assert!(decode_exchange(b"BTL").is_err());
+    }
 }